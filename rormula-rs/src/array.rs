@@ -171,6 +171,10 @@ where
     pub fn set_capacity(&mut self, capa: usize) {
         self.capacity = Some(capa);
     }
+    /// Reserves memory for at least `additional` more elements, e.g., for concatenated columns
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+    }
     pub fn new(data: Vec<f64>, n_rows: usize, n_cols: usize) -> RoResult<Self> {
         if data.len() != n_rows * n_cols {
             Err(roerr!("dimension of input data does not fit"))
@@ -418,8 +422,7 @@ fn test_colmutate() {
         M: MemOrder,
     {
         let mut a =
-            Array2d::<M>::from_iter(vec![1.0, 0.0, 1.0, 2.0, 1.0, 3.0, 1.0, 4.0].iter(), 4, 2)
-                .unwrap();
+            Array2d::<M>::from_iter([1.0, 0.0, 1.0, 2.0, 1.0, 3.0, 1.0, 4.0].iter(), 4, 2).unwrap();
         println!("{:?}", a);
         println!("{:?}", a.data);
         assert_eq!(a.get(0, 1), 0.0);
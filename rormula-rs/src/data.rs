@@ -0,0 +1,268 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    result::{RoErr, RoResult},
+    roerr,
+};
+
+/// Default level that missing values of categorical columns form if they are kept, see
+/// [`NaAction::Keep`]
+pub const NA_LEVEL: &str = "NA";
+
+/// Source of the columns the variables of a formula refer to.
+pub trait DataSource {
+    fn n_rows(&self) -> usize;
+    /// Returns a copy of the numerical column or `None` if there is no numerical column with this
+    /// name. Missing values are NaN.
+    fn numerical(&self, name: &str) -> RoResult<Option<Vec<f64>>>;
+    /// Returns a copy of the categorical column or `None` if there is no categorical column with
+    /// this name. Missing values are `None`.
+    fn categorical(&self, name: &str) -> RoResult<Option<Vec<Option<String>>>>;
+    /// Returns the declared levels of a categorical column in the order of the encoding or
+    /// `None` if the levels are derived from the data.
+    fn levels(&self, _name: &str) -> RoResult<Option<Vec<String>>> {
+        Ok(None)
+    }
+    /// Returns for each row whether the value of the column is missing or `None` if there is no
+    /// column with this name.
+    fn missing(&self, name: &str) -> RoResult<Option<Vec<bool>>> {
+        if let Some(col) = self.numerical(name)? {
+            Ok(Some(col.iter().map(|x| x.is_nan()).collect()))
+        } else {
+            Ok(self
+                .categorical(name)?
+                .map(|col| col.iter().map(Option::is_none).collect()))
+        }
+    }
+}
+
+/// Handling of missing values in the columns a formula refers to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NaAction {
+    /// Missing values are an error that names the column and the row
+    Raise,
+    /// Rows with missing values are removed
+    Drop,
+    /// NaN is propagated and missing categoricals form the level [`NA_LEVEL`] unless another level
+    /// is set, see [`Wilkinson::with_na_level`](crate::expression::Wilkinson::with_na_level)
+    #[default]
+    Keep,
+}
+impl NaAction {
+    /// Returns the indices of the rows that are used or `None` if all rows are used.
+    pub fn complete_rows(
+        &self,
+        data: &impl DataSource,
+        columns: &[String],
+    ) -> RoResult<Option<Vec<usize>>> {
+        match self {
+            NaAction::Keep => Ok(None),
+            NaAction::Raise => {
+                for col in columns {
+                    if let Some(row) = data
+                        .missing(col)?
+                        .and_then(|missing| missing.iter().position(|m| *m))
+                    {
+                        return Err(roerr!("missing value in column {} in row {}", col, row));
+                    }
+                }
+                Ok(None)
+            }
+            NaAction::Drop => {
+                let mut complete = vec![true; data.n_rows()];
+                for col in columns {
+                    if let Some(missing) = data.missing(col)? {
+                        complete
+                            .iter_mut()
+                            .zip(missing)
+                            .for_each(|(c, m)| *c = *c && !m);
+                    }
+                }
+                Ok(Some(
+                    (0..complete.len()).filter(|row| complete[*row]).collect(),
+                ))
+            }
+        }
+    }
+}
+impl FromStr for NaAction {
+    type Err = RoErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raise" => Ok(NaAction::Raise),
+            "drop" => Ok(NaAction::Drop),
+            "keep" => Ok(NaAction::Keep),
+            _ => Err(roerr!(
+                "unknown na action {}, expected raise, drop, or keep",
+                s
+            )),
+        }
+    }
+}
+
+/// View on a subset of the rows of a data source
+#[derive(Clone, Debug)]
+pub struct RowSubset<'a, D: DataSource> {
+    data: &'a D,
+    rows: Vec<usize>,
+}
+impl<'a, D: DataSource> RowSubset<'a, D> {
+    pub fn new(data: &'a D, rows: Vec<usize>) -> Self {
+        RowSubset { data, rows }
+    }
+    pub fn rows(&self) -> &[usize] {
+        &self.rows
+    }
+    pub fn into_rows(self) -> Vec<usize> {
+        self.rows
+    }
+    fn select<T: Clone>(&self, col: Option<Vec<T>>) -> Option<Vec<T>> {
+        col.map(|col| self.rows.iter().map(|row| col[*row].clone()).collect())
+    }
+}
+impl<D: DataSource> DataSource for RowSubset<'_, D> {
+    fn n_rows(&self) -> usize {
+        self.rows.len()
+    }
+    fn numerical(&self, name: &str) -> RoResult<Option<Vec<f64>>> {
+        Ok(self.select(self.data.numerical(name)?))
+    }
+    fn categorical(&self, name: &str) -> RoResult<Option<Vec<Option<String>>>> {
+        Ok(self.select(self.data.categorical(name)?))
+    }
+    fn levels(&self, name: &str) -> RoResult<Option<Vec<String>>> {
+        self.data.levels(name)
+    }
+}
+
+/// Column-wise data that is separated into numerical and categorical columns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SeparatedData {
+    numerical_cols: Vec<String>,
+    numerical_data: Vec<Vec<f64>>,
+    categorical_cols: Vec<String>,
+    categorical_data: Vec<Vec<Option<String>>>,
+    /// Declared levels of categorical columns by column name
+    levels: HashMap<String, Vec<String>>,
+}
+impl SeparatedData {
+    pub fn new(
+        numerical_cols: Vec<String>,
+        numerical_data: Vec<Vec<f64>>,
+        categorical_cols: Vec<String>,
+        categorical_data: Vec<Vec<String>>,
+    ) -> RoResult<Self> {
+        if numerical_cols.len() != numerical_data.len()
+            || categorical_cols.len() != categorical_data.len()
+        {
+            return Err(roerr!("number of column names and columns do not match"));
+        }
+        let mut n_rows = numerical_data
+            .iter()
+            .map(|c| c.len())
+            .chain(categorical_data.iter().map(|c| c.len()));
+        if let Some(n) = n_rows.next()
+            && n_rows.any(|n_other| n_other != n)
+        {
+            return Err(roerr!("all columns need to have the same number of rows"));
+        }
+        Ok(Self {
+            numerical_cols,
+            numerical_data,
+            categorical_cols,
+            categorical_data: categorical_data
+                .into_iter()
+                .map(|col| col.into_iter().map(Some).collect())
+                .collect(),
+            levels: HashMap::new(),
+        })
+    }
+
+    /// Declares the levels of a categorical column. The order of the levels is the order of the
+    /// encoding and the number of columns does not depend on the levels present in the data.
+    pub fn with_levels(mut self, column: &str, levels: Vec<String>) -> RoResult<Self> {
+        if !self.categorical_cols.iter().any(|c| c == column) {
+            return Err(roerr!("{} is not a categorical column", column));
+        }
+        if levels.is_empty() {
+            return Err(roerr!("levels of {} are empty", column));
+        }
+        if levels
+            .iter()
+            .enumerate()
+            .any(|(i, l)| levels[..i].contains(l))
+        {
+            return Err(roerr!("levels of {} are not unique", column));
+        }
+        self.levels.insert(column.to_string(), levels);
+        Ok(self)
+    }
+
+    /// Marks values of a categorical column as missing. Missing numerical values are NaN.
+    pub fn with_missing(mut self, column: &str, missing: &[bool]) -> RoResult<Self> {
+        let col = self
+            .categorical_cols
+            .iter()
+            .position(|c| c == column)
+            .ok_or_else(|| roerr!("{} is not a categorical column", column))?;
+        let col = &mut self.categorical_data[col];
+        if missing.len() != col.len() {
+            return Err(roerr!("missing values of {} do not match its rows", column));
+        }
+        for (value, missing) in col.iter_mut().zip(missing) {
+            if *missing {
+                *value = None;
+            }
+        }
+        Ok(self)
+    }
+}
+impl DataSource for SeparatedData {
+    fn n_rows(&self) -> usize {
+        self.numerical_data
+            .first()
+            .map(|c| c.len())
+            .or_else(|| self.categorical_data.first().map(|c| c.len()))
+            .unwrap_or(0)
+    }
+    fn numerical(&self, name: &str) -> RoResult<Option<Vec<f64>>> {
+        Ok(self
+            .numerical_cols
+            .iter()
+            .position(|c| c == name)
+            .map(|idx| self.numerical_data[idx].clone()))
+    }
+    fn categorical(&self, name: &str) -> RoResult<Option<Vec<Option<String>>>> {
+        Ok(self
+            .categorical_cols
+            .iter()
+            .position(|c| c == name)
+            .map(|idx| self.categorical_data[idx].clone()))
+    }
+    fn levels(&self, name: &str) -> RoResult<Option<Vec<String>>> {
+        Ok(self.levels.get(name).cloned())
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn to_strings(strs: &[&str]) -> Vec<String> {
+    strs.iter().map(|s| s.to_string()).collect()
+}
+
+/// Test data from the names and values of numerical and categorical columns
+#[cfg(test)]
+pub(crate) fn separated_data(
+    numerical: &[(&str, &[f64])],
+    categorical: &[(&str, &[&str])],
+) -> SeparatedData {
+    SeparatedData::new(
+        numerical.iter().map(|(name, _)| name.to_string()).collect(),
+        numerical.iter().map(|(_, col)| col.to_vec()).collect(),
+        categorical
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        categorical.iter().map(|(_, col)| to_strings(col)).collect(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,791 @@
+use crate::{
+    array::{Array2d, DefaultOrder},
+    data::DataSource,
+    result::RoResult,
+    roerr,
+    sparse::SparseCol,
+    timing,
+};
+
+use super::{
+    Contrast, FactorEncoding, Tolerance, UnseenLevels, Value,
+    expr_arithmetic::load_vars,
+    ops_common::{orthogonal_poly, orthogonal_poly_coefs, unique_levels},
+    parse_arithmetic,
+    splines::{SplineBasis, SplineKind},
+    value::Columns,
+};
+
+/// Functions that can be called within Wilkinson formulas. Each call is a single factor.
+const FACTOR_FNS: [&str; 10] = [
+    "I",
+    "C",
+    "poly",
+    "bs",
+    "ns",
+    "cr",
+    "center",
+    "scale",
+    "standardize",
+    "coded",
+];
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Returns the index of the closing parenthesis that matches the opening parenthesis at `open`.
+fn find_closing_paren(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Wraps calls of factor functions in braces such that exmex parses each call as a single
+/// variable, e.g., `a + I(b^2)` becomes `a + {I(b^2)}`.
+pub(crate) fn wrap_calls(formula: &str) -> RoResult<String> {
+    let mut wrapped = String::with_capacity(formula.len());
+    let mut in_braces = false;
+    let mut i = 0;
+    while let Some(c) = formula[i..].chars().next() {
+        match c {
+            '{' => in_braces = true,
+            '}' => in_braces = false,
+            _ => (),
+        }
+        let starts_ident = !in_braces
+            && is_ident_char(c)
+            && !formula[..i].chars().next_back().is_some_and(is_ident_char);
+        if starts_ident {
+            let ident_len = formula[i..]
+                .find(|c| !is_ident_char(c))
+                .unwrap_or(formula.len() - i);
+            let ident = &formula[i..i + ident_len];
+            let open = i + ident_len;
+            if FACTOR_FNS.contains(&ident) && formula[open..].starts_with('(') {
+                let close = find_closing_paren(formula, open).ok_or_else(|| {
+                    roerr!("missing closing parenthesis of {} in {}", ident, formula)
+                })?;
+                let call = &formula[i..=close];
+                if call.contains(['{', '}']) {
+                    return Err(roerr!("braces are not supported within {}", call));
+                }
+                wrapped.push('{');
+                wrapped.push_str(call);
+                wrapped.push('}');
+                i = close + 1;
+            } else {
+                wrapped.push_str(ident);
+                i = open;
+            }
+        } else {
+            wrapped.push(c);
+            i += c.len_utf8();
+        }
+    }
+    Ok(wrapped)
+}
+
+/// Splits a variable name like `I(b^2)` into the function name `I` and its arguments `b^2`.
+/// Returns `None` if the variable is not a call of a factor function.
+pub(super) fn split_call(var_name: &str) -> Option<(&str, &str)> {
+    let open = var_name.find('(')?;
+    let fn_name = &var_name[..open];
+    if FACTOR_FNS.contains(&fn_name) && var_name.ends_with(')') {
+        Some((fn_name, &var_name[open + 1..var_name.len() - 1]))
+    } else {
+        None
+    }
+}
+
+/// Names of the data columns a variable of a Wilkinson formula refers to, e.g., `temp` and
+/// `pressure` for `I(temp/pressure)`
+pub(crate) fn column_names(var_name: &str) -> RoResult<Vec<String>> {
+    match split_call(var_name) {
+        Some(("I", args)) => Ok(
+            parse_arithmetic::<DefaultOrder>(args, Tolerance::default())?
+                .var_names()
+                .to_vec(),
+        ),
+        Some((
+            "C" | "poly" | "bs" | "ns" | "cr" | "center" | "scale" | "standardize" | "coded",
+            args,
+        )) => Ok(vec![split_args(args)[0].to_string()]),
+        _ => Ok(vec![var_name.to_string()]),
+    }
+}
+
+/// Splits the arguments of a call at commas that are not nested in parentheses.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut splitted = vec![];
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                splitted.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    splitted.push(args[start..].trim());
+    splitted
+}
+
+/// Parses contrasts like `Sum` or `Treatment(reference="ctrl")`.
+fn parse_contrast(spec: &str) -> RoResult<Contrast> {
+    let (name, inner) = match spec.find('(') {
+        Some(open) if spec.ends_with(')') => (&spec[..open], spec[open + 1..spec.len() - 1].trim()),
+        _ => (spec, ""),
+    };
+    let no_args = |contrast| {
+        if inner.is_empty() {
+            Ok(contrast)
+        } else {
+            Err(roerr!("contrast {} does not have arguments", name))
+        }
+    };
+    match name.trim() {
+        "Treatment" => {
+            let reference = inner
+                .strip_prefix("reference")
+                .map(|r| r.trim_start().trim_start_matches('='))
+                .unwrap_or(inner)
+                .trim()
+                .trim_matches(['"', '\'']);
+            Ok(Contrast::Treatment {
+                reference: (!reference.is_empty()).then(|| reference.to_string()),
+            })
+        }
+        "Sum" => no_args(Contrast::Sum),
+        "Helmert" => no_args(Contrast::Helmert),
+        "Diff" => no_args(Contrast::Diff),
+        "Poly" => no_args(Contrast::Poly),
+        _ => Err(roerr!("unknown contrast {}", spec)),
+    }
+}
+
+/// Parameters of a factor that are derived from the training data, see
+/// [`ModelSpec`](super::ModelSpec).
+#[derive(Clone, Debug, PartialEq)]
+pub enum FactorState {
+    /// Levels of a categorical variable in the order of the encoding
+    Levels(Vec<String>),
+    /// Coefficients of orthogonal polynomials, see
+    /// [`orthogonal_poly_coefs`](super::ops_common::orthogonal_poly_coefs)
+    Poly { alpha: Vec<f64>, norm2: Vec<f64> },
+    /// Knots of a spline basis
+    Spline(SplineBasis),
+    /// Parameters of the transformation `(x - shift) / scale`
+    Affine { shift: f64, scale: f64 },
+}
+
+/// Data of a factor of a Wilkinson formula. Categorical factors are encoded only after it is known
+/// whether they need full rank.
+#[derive(Clone, Debug)]
+pub(crate) enum FactorData {
+    Numerical {
+        names: Vec<String>,
+        data: Array2d<DefaultOrder>,
+        state: Option<FactorState>,
+    },
+    Categorical {
+        feature_name: String,
+        cats: Vec<String>,
+        levels: Vec<String>,
+        contrast: Contrast,
+        /// Only relevant if the levels are known in advance
+        unseen: UnseenLevels,
+    },
+}
+impl FactorData {
+    /// Levels and contrast of the encoding including the bucket for unseen values if there is one
+    fn coding(
+        levels: &[String],
+        contrast: &Contrast,
+        unseen: UnseenLevels,
+    ) -> RoResult<(Vec<String>, Contrast)> {
+        match unseen {
+            UnseenLevels::Other => contrast.with_other_level(levels),
+            _ => Ok((levels.to_vec(), contrast.clone())),
+        }
+    }
+    pub(crate) fn is_categorical(&self) -> bool {
+        matches!(self, FactorData::Categorical { .. })
+    }
+    pub(crate) fn n_cols(&self, full_rank: bool) -> RoResult<usize> {
+        match self {
+            FactorData::Numerical { data, .. } => Ok(data.n_cols()),
+            FactorData::Categorical {
+                levels,
+                contrast,
+                unseen,
+                ..
+            } => {
+                let (levels, _) = Self::coding(levels, contrast, *unseen)?;
+                if full_rank {
+                    Ok(levels.len())
+                } else {
+                    Ok(levels.len().saturating_sub(1))
+                }
+            }
+        }
+    }
+    /// Returns the state that is needed to encode new data the same way
+    pub(crate) fn state(&self) -> Option<FactorState> {
+        match self {
+            FactorData::Numerical { state, .. } => state.clone(),
+            FactorData::Categorical { levels, .. } => Some(FactorState::Levels(levels.clone())),
+        }
+    }
+    pub(crate) fn encoding(&self, full_rank: bool) -> RoResult<FactorEncoding> {
+        match self {
+            FactorData::Numerical { data, .. } => Ok(FactorEncoding::Numerical {
+                n_cols: data.n_cols(),
+            }),
+            FactorData::Categorical {
+                levels,
+                contrast,
+                unseen,
+                ..
+            } => {
+                let (levels, contrast) = Self::coding(levels, contrast, *unseen)?;
+                Ok(FactorEncoding::Categorical {
+                    contrast,
+                    levels,
+                    full_rank,
+                })
+            }
+        }
+    }
+    /// Names of the columns of the encoding
+    pub(crate) fn col_names(&self, full_rank: bool) -> RoResult<Vec<String>> {
+        match self {
+            FactorData::Numerical { names, .. } => Ok(names.clone()),
+            FactorData::Categorical {
+                feature_name,
+                levels,
+                contrast,
+                unseen,
+                ..
+            } => {
+                let (levels, contrast) = Self::coding(levels, contrast, *unseen)?;
+                contrast.col_names(feature_name, &levels, full_rank)
+            }
+        }
+    }
+    /// Returns the encoded columns together with their names if not skipped.
+    pub(crate) fn encode(
+        self,
+        full_rank: bool,
+        skip_names: bool,
+    ) -> RoResult<Columns<DefaultOrder>> {
+        let names = if skip_names {
+            None
+        } else {
+            Some(self.col_names(full_rank)?)
+        };
+        match self {
+            FactorData::Numerical { data, .. } => Ok(Columns::new(names, data)),
+            FactorData::Categorical {
+                feature_name,
+                cats,
+                levels,
+                contrast,
+                unseen,
+            } => {
+                let (levels, contrast) = Self::coding(&levels, &contrast, unseen)?;
+                let encoded = contrast
+                    .encode(&cats, &levels, full_rank, unseen)
+                    .map_err(|e| roerr!("column {}, {}", feature_name, e.msg()))?;
+                Ok(Columns::new(names, encoded))
+            }
+        }
+    }
+    /// Encodes the factor column by column without materializing zeros of categoricals.
+    pub(crate) fn encode_sparse(&self, full_rank: bool) -> RoResult<Vec<SparseCol>> {
+        match self {
+            FactorData::Numerical { data, .. } => Ok((0..data.n_cols())
+                .map(|col| SparseCol::from_dense((0..data.n_rows()).map(|row| data.get(row, col))))
+                .collect()),
+            FactorData::Categorical {
+                feature_name,
+                cats,
+                levels,
+                contrast,
+                unseen,
+            } => {
+                let (levels, contrast) = Self::coding(levels, contrast, *unseen)?;
+                contrast
+                    .encode_sparse(cats, &levels, full_rank, *unseen)
+                    .map_err(|e| roerr!("column {}, {}", feature_name, e.msg()))
+            }
+        }
+    }
+}
+
+/// Missing values that are kept form the level `na_level` that hence must not be present in the
+/// data as well, see [`NA_LEVEL`](crate::data::NA_LEVEL) for the default.
+fn fill_missing(
+    feature_name: &str,
+    cats: Vec<Option<String>>,
+    na_level: &str,
+) -> RoResult<Vec<String>> {
+    if cats.iter().any(Option::is_none) && cats.iter().any(|c| c.as_deref() == Some(na_level)) {
+        return Err(roerr!(
+            "column {}, missing values collide with the level {}, choose another level for \
+             missing values",
+            feature_name,
+            na_level
+        ));
+    }
+    Ok(cats
+        .into_iter()
+        .map(|c| c.unwrap_or_else(|| na_level.to_string()))
+        .collect())
+}
+
+/// Categorical factor whose levels are taken from the state if available, then from the levels
+/// declared by the data source, and finally from the values that are present. The policy for
+/// unseen values only applies to levels that are known in advance.
+fn categorical(
+    feature_name: &str,
+    cats: Vec<Option<String>>,
+    contrast: Contrast,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+    unseen: UnseenLevels,
+    na_level: &str,
+) -> RoResult<FactorData> {
+    let cats = fill_missing(feature_name, cats, na_level)?;
+    let (levels, levels_unseen) = match state {
+        Some(FactorState::Levels(levels)) => (levels.clone(), unseen),
+        Some(_) => return Err(roerr!("state of {} does not contain levels", feature_name)),
+        None => match data.levels(feature_name)? {
+            Some(levels) => (levels, unseen),
+            None => (unique_levels(&cats), UnseenLevels::Error),
+        },
+    };
+    // also levels that are fitted now must not collide with the bucket for unseen values later
+    if unseen == UnseenLevels::Other {
+        contrast
+            .with_other_level(&levels)
+            .map_err(|e| roerr!("column {}, {}", feature_name, e.msg()))?;
+    }
+    Ok(FactorData::Categorical {
+        feature_name: feature_name.to_string(),
+        cats,
+        levels,
+        contrast,
+        unseen: levels_unseen,
+    })
+}
+
+/// A categorical variable with the given contrast, e.g., `C(x, Sum)`. Without contrast,
+/// treatment coding is used.
+fn eval_contrast(
+    args: &str,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+    unseen: UnseenLevels,
+    na_level: &str,
+) -> RoResult<FactorData> {
+    let (var_name, contrast) = match split_args(args)[..] {
+        [var_name] => (var_name, Contrast::default()),
+        [var_name, contrast] => (var_name, parse_contrast(contrast)?),
+        _ => return Err(roerr!("C({}) expects a variable and a contrast", args)),
+    };
+    let cats = data
+        .categorical(var_name)?
+        .ok_or_else(|| roerr!("did not find categorical Variable {} in the data", var_name))?;
+    categorical(var_name, cats, contrast, data, state, unseen, na_level)
+}
+
+/// Removes whitespace outside of string literals such that the name of a column does not depend
+/// on the formatting of the formula, e.g., `temp ^ 2` becomes `temp^2`. Between identifiers like in
+/// `site in ("a")`, a single space is kept.
+fn normalize_whitespace(expr: &str) -> String {
+    let mut normalized = String::with_capacity(expr.len());
+    let mut quote = None;
+    let mut pending_space = false;
+    for c in expr.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c.is_whitespace() => {
+                pending_space = true;
+                continue;
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => (),
+        }
+        if pending_space
+            && is_ident_char(c)
+            && normalized.chars().next_back().is_some_and(is_ident_char)
+        {
+            normalized.push(' ');
+        }
+        pending_space = false;
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Evaluates an arithmetic expression on the numerical columns of the data. Comparisons use the
+/// given tolerance. The resulting column is named by the expression without formatting
+/// whitespace, see [`normalize_whitespace`].
+fn eval_identity(args: &str, data: &impl DataSource, tolerance: Tolerance) -> RoResult<FactorData> {
+    let expr = parse_arithmetic(args, tolerance)?;
+    if expr.has_row_change_op() {
+        return Err(roerr!("I({}) must not change the number of rows", args));
+    }
+    let vars = load_vars(&expr, data)?;
+    let n_rows = data.n_rows();
+    let col = match expr.eval_vec(vars)? {
+        Value::Array(a) if a.n_cols() == 1 && a.n_rows() == n_rows => a,
+        Value::Scalar(s) => Array2d::from_vec(vec![s; n_rows], n_rows, 1)?,
+        Value::Error(e) => return Err(roerr!("computation of I({}) failed, {}", args, e)),
+        _ => return Err(roerr!("I({}) needs to result in one column", args)),
+    };
+    Ok(FactorData::Numerical {
+        names: vec![normalize_whitespace(args)],
+        data: col,
+        state: None,
+    })
+}
+
+/// Orthogonal polynomials of a numerical variable up to the given degree, e.g., `poly(temp, 3)`.
+/// The coefficients are taken from the state if available and from the data otherwise.
+fn eval_poly(
+    args: &str,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+) -> RoResult<FactorData> {
+    let (var_name, degree) = match split_args(args)[..] {
+        [var_name, degree] => (
+            var_name,
+            degree
+                .parse::<usize>()
+                .map_err(|_| roerr!("degree of poly({}) needs to be a positive integer", args))?,
+        ),
+        _ => return Err(roerr!("poly({}) expects a variable and a degree", args)),
+    };
+    let x = data
+        .numerical(var_name)?
+        .ok_or_else(|| roerr!("did not find numerical Variable {} in the data", var_name))?;
+    let (alpha, norm2) = match state {
+        Some(FactorState::Poly { alpha, norm2 }) => (alpha.clone(), norm2.clone()),
+        Some(_) => {
+            return Err(roerr!(
+                "state of poly({}) does not contain coefficients",
+                args
+            ));
+        }
+        None => orthogonal_poly_coefs(&x, degree)?,
+    };
+    Ok(FactorData::Numerical {
+        names: (1..=degree)
+            .map(|d| format!("poly({var_name},{degree})[{d}]"))
+            .collect(),
+        data: orthogonal_poly(&x, &alpha, &norm2),
+        state: Some(FactorState::Poly { alpha, norm2 }),
+    })
+}
+
+/// Returns the keyword argument `key`, e.g., `df=5`, or the positional argument at `pos`.
+fn find_arg<'a>(args: &[&'a str], pos: usize, key: &str) -> Option<&'a str> {
+    args.iter()
+        .find_map(|a| {
+            a.strip_prefix(key)
+                .and_then(|a| a.trim_start().strip_prefix('='))
+                .map(str::trim)
+        })
+        .or_else(|| args.get(pos).filter(|a| !a.contains('=')).copied())
+}
+
+/// Spline basis of a numerical variable, e.g., `bs(x, df=5, degree=3)`, `ns(x, df=4)`, or
+/// `cr(x, df=4, constraints="center")`. The knots are placed at quantiles of the data unless
+/// they are contained in the state.
+fn eval_spline(
+    fn_name: &str,
+    args: &str,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+) -> RoResult<FactorData> {
+    let args_split = split_args(args);
+    let keys: &[&str] = match fn_name {
+        "bs" => &["df", "degree"],
+        "cr" => &["df", "constraints"],
+        _ => &["df"],
+    };
+    if let Some((key, _)) = args_split[1..]
+        .iter()
+        .filter_map(|a| a.split_once('='))
+        .find(|(key, _)| !keys.contains(&key.trim()))
+    {
+        return Err(roerr!(
+            "unknown argument {} of {}({})",
+            key.trim(),
+            fn_name,
+            args
+        ));
+    }
+    let parse_usize = |arg: &str, what: &str| {
+        arg.parse::<usize>().map_err(|_| {
+            roerr!(
+                "{} of {}({}) needs to be a positive integer",
+                what,
+                fn_name,
+                args
+            )
+        })
+    };
+    let df = find_arg(&args_split, 1, "df")
+        .ok_or_else(|| roerr!("{}({}) needs the degrees of freedom df", fn_name, args))?;
+    let df = parse_usize(df, "df")?;
+    let kind = match fn_name {
+        "bs" => SplineKind::BSpline {
+            degree: match find_arg(&args_split, 2, "degree") {
+                Some(degree) => parse_usize(degree, "degree")?,
+                None => 3,
+            },
+        },
+        "cr" => SplineKind::CubicRegression {
+            center: match find_arg(&args_split, 2, "constraints")
+                .map(|c| c.trim_matches(['"', '\'']))
+            {
+                None => false,
+                Some("center") => true,
+                Some(c) => return Err(roerr!("unknown constraints {} of cr({})", c, args)),
+            },
+        },
+        _ => SplineKind::Natural,
+    };
+    let var_name = args_split[0];
+    let x = data
+        .numerical(var_name)?
+        .ok_or_else(|| roerr!("did not find numerical Variable {} in the data", var_name))?;
+    let basis = match state {
+        Some(FactorState::Spline(basis)) => basis.clone(),
+        Some(_) => {
+            return Err(roerr!(
+                "state of {}({}) does not contain knots",
+                fn_name,
+                args
+            ));
+        }
+        None => SplineBasis::fit(kind, &x, df)?,
+    };
+    let name = format!("{fn_name}({})", args.split_whitespace().collect::<String>());
+    Ok(FactorData::Numerical {
+        names: (1..=basis.n_cols())
+            .map(|i| format!("{name}[{i}]"))
+            .collect(),
+        data: basis.eval(&x)?,
+        state: Some(FactorState::Spline(basis)),
+    })
+}
+
+/// Mean and sample standard deviation ignoring NaN
+fn mean_std(x: &[f64]) -> (f64, f64) {
+    let x = x.iter().filter(|x| !x.is_nan()).collect::<Vec<_>>();
+    let n = x.len() as f64;
+    let mean = x.iter().copied().sum::<f64>() / n;
+    let var = x.iter().map(|x| (*x - mean) * (*x - mean)).sum::<f64>() / (n - 1.0);
+    (mean, var.sqrt())
+}
+
+/// Affine transformation `(x - shift) / scale` of a numerical variable, i.e., `center(x)`, `scale(x)`
+/// that centers and divides by the standard deviation like in R, its alias `standardize(x)`, or
+/// the transformation to coded units `coded(x, low, high)` that maps `low` to -1 and `high` to 1.
+/// Without `low` and `high`, the minimum and the maximum of the data are used. The parameters are
+/// taken from the state if available.
+fn eval_affine(
+    fn_name: &str,
+    args: &str,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+) -> RoResult<FactorData> {
+    let args_split = split_args(args);
+    let var_name = args_split[0];
+    let x = data
+        .numerical(var_name)?
+        .ok_or_else(|| roerr!("did not find numerical Variable {} in the data", var_name))?;
+    let (shift, scale) = match state {
+        Some(FactorState::Affine { shift, scale }) => (*shift, *scale),
+        Some(_) => {
+            return Err(roerr!(
+                "state of {}({}) does not contain shift and scale",
+                fn_name,
+                args
+            ));
+        }
+        None => {
+            let (low, high) = match (fn_name, &args_split[1..]) {
+                ("coded", [low, high]) => {
+                    let parse = |bound: &str| {
+                        bound
+                            .parse::<f64>()
+                            .map_err(|_| roerr!("bounds of coded({}) need to be numbers", args))
+                    };
+                    (parse(low)?, parse(high)?)
+                }
+                ("coded", []) => x
+                    .iter()
+                    .filter(|x| !x.is_nan())
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+                        (lo.min(*x), hi.max(*x))
+                    }),
+                (_, []) => (f64::NAN, f64::NAN),
+                _ => return Err(roerr!("unexpected arguments of {}({})", fn_name, args)),
+            };
+            let (mean, std) = mean_std(&x);
+            let (shift, scale) = match fn_name {
+                "center" => (mean, 1.0),
+                // like in R, patsy, and formulaic, scale centers as well
+                "scale" | "standardize" => (mean, std),
+                _ => ((low + high) / 2.0, (high - low) / 2.0),
+            };
+            if !shift.is_finite() || !scale.is_finite() || scale == 0.0 {
+                return Err(roerr!(
+                    "cannot compute {}({}) with shift {} and scale {}",
+                    fn_name,
+                    args,
+                    shift,
+                    scale
+                ));
+            }
+            (shift, scale)
+        }
+    };
+    let n_rows = x.len();
+    let transformed = x.into_iter().map(|x| (x - shift) / scale).collect();
+    Ok(FactorData::Numerical {
+        names: vec![format!(
+            "{fn_name}({})",
+            args.split_whitespace().collect::<String>()
+        )],
+        data: Array2d::from_vec(transformed, n_rows, 1)?,
+        state: Some(FactorState::Affine { shift, scale }),
+    })
+}
+
+/// Looks up the data of a variable of a Wilkinson formula. Calls of factor functions like `I(...)`
+/// are evaluated, comparisons within `I(...)` with the given tolerance. Missing categoricals form
+/// the level `na_level`. The state is the result of a previous fit, see
+/// [`ModelSpec`](super::ModelSpec).
+pub(crate) fn load_factor(
+    var_name: &str,
+    data: &impl DataSource,
+    state: Option<&FactorState>,
+    unseen: UnseenLevels,
+    tolerance: Tolerance,
+    na_level: &str,
+) -> RoResult<FactorData> {
+    if let Some((fn_name, args)) = split_call(var_name) {
+        match fn_name {
+            "I" => eval_identity(args, data, tolerance),
+            "C" => eval_contrast(args, data, state, unseen, na_level),
+            "poly" => eval_poly(args, data, state),
+            "bs" | "ns" | "cr" => eval_spline(fn_name, args, data, state),
+            "center" | "scale" | "standardize" | "coded" => eval_affine(fn_name, args, data, state),
+            _ => Err(roerr!("unknown function {}", fn_name)),
+        }
+    } else if let Some(col) = data.numerical(var_name)? {
+        let n_rows = col.len();
+        Ok(FactorData::Numerical {
+            names: vec![var_name.to_string()],
+            data: timing!(Array2d::from_vec(col, n_rows, 1)?, "arr from vec"),
+            state: None,
+        })
+    } else if let Some(cats) = data.categorical(var_name)? {
+        categorical(
+            var_name,
+            cats,
+            Contrast::default(),
+            data,
+            state,
+            unseen,
+            na_level,
+        )
+    } else {
+        Err(roerr!("did not find Variable {} in the data", var_name))
+    }
+}
+
+#[test]
+fn test_normalize_whitespace() {
+    assert_eq!(normalize_whitespace("temp ^ 2"), "temp^2");
+    assert_eq!(
+        normalize_whitespace(" log( pressure )/2 "),
+        "log(pressure)/2"
+    );
+    assert_eq!(
+        normalize_whitespace("x | site in ('a  b', \"c \")"),
+        "x|site in('a  b',\"c \")"
+    );
+    assert_eq!(
+        normalize_whitespace("where(temp > 1, temp - 1, 0)"),
+        "where(temp>1,temp-1,0)"
+    );
+}
+
+#[test]
+fn test_wrap_calls() {
+    assert_eq!(
+        wrap_calls("temp + I(temp^2) + I(log(pressure)/2)").unwrap(),
+        "temp + {I(temp^2)} + {I(log(pressure)/2)}"
+    );
+    assert_eq!(wrap_calls("a:I(b*c) - 1").unwrap(), "a:{I(b*c)} - 1");
+    assert_eq!(wrap_calls("xI(a) + {I(b)}").unwrap(), "xI(a) + {I(b)}");
+    assert_eq!(wrap_calls("a + (b + c)").unwrap(), "a + (b + c)");
+    assert!(wrap_calls("I(a + (b)").is_err());
+    assert_eq!(split_call("I(log(a)/2)"), Some(("I", "log(a)/2")));
+    assert_eq!(split_call("log(a)"), None);
+    assert_eq!(split_call("a"), None);
+    assert_eq!(
+        column_names("I(log(a)/b)").unwrap(),
+        vec!["a".to_string(), "b".to_string()]
+    );
+    assert_eq!(column_names("C(x, Sum)").unwrap(), vec!["x".to_string()]);
+    assert_eq!(column_names("poly(x, 2)").unwrap(), vec!["x".to_string()]);
+}
+
+#[test]
+fn test_parse_contrast() {
+    assert_eq!(
+        split_args("x, Treatment(reference=\"ctrl\")"),
+        vec!["x", "Treatment(reference=\"ctrl\")"]
+    );
+    let ctrl = Contrast::Treatment {
+        reference: Some("ctrl".to_string()),
+    };
+    assert_eq!(
+        parse_contrast("Treatment(reference=\"ctrl\")").unwrap(),
+        ctrl
+    );
+    assert_eq!(
+        parse_contrast("Treatment(reference = 'ctrl')").unwrap(),
+        ctrl
+    );
+    assert_eq!(parse_contrast("Treatment(\"ctrl\")").unwrap(), ctrl);
+    assert_eq!(parse_contrast("Treatment").unwrap(), Contrast::default());
+    assert_eq!(parse_contrast("Sum").unwrap(), Contrast::Sum);
+    assert_eq!(parse_contrast("Poly()").unwrap(), Contrast::Poly);
+    assert!(parse_contrast("Helmert(1)").is_err());
+    assert!(parse_contrast("Dummy").is_err());
+}
@@ -0,0 +1,78 @@
+use std::ops::Range;
+
+use super::Contrast;
+
+/// Encoding of a factor in the model matrix
+#[derive(Clone, Debug, PartialEq)]
+pub enum FactorEncoding {
+    /// Numerical factor with its number of columns, e.g., 3 for `poly(x, 3)`
+    Numerical { n_cols: usize },
+    /// Categorical factor coded with the contrast. With full rank, there is one column per level.
+    Categorical {
+        contrast: Contrast,
+        levels: Vec<String>,
+        full_rank: bool,
+    },
+}
+impl FactorEncoding {
+    pub fn n_cols(&self) -> usize {
+        match self {
+            FactorEncoding::Numerical { n_cols } => *n_cols,
+            FactorEncoding::Categorical {
+                levels, full_rank, ..
+            } => {
+                if *full_rank {
+                    levels.len()
+                } else {
+                    levels.len().saturating_sub(1)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FactorInfo {
+    /// Variable name of the factor, e.g., `x` or `C(x, Sum)`
+    pub name: String,
+    pub encoding: FactorEncoding,
+}
+
+/// Part of a term with its own encoding. Categorical factors that are spanned by lower-order
+/// terms are missing, e.g., `a:b` with categorical `a` and `b` and intercept consists of the
+/// subterms `b` and `a:b` where `a` has full rank.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtermInfo {
+    pub factors: Vec<FactorInfo>,
+    pub columns: Range<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermInfo {
+    /// Name of the term like `a:b` or `Intercept`
+    pub name: String,
+    pub factors: Vec<String>,
+    /// Columns of the term in the model matrix
+    pub columns: Range<usize>,
+    pub subterms: Vec<SubtermInfo>,
+}
+
+/// Description of the columns of a model matrix, i.e., which term and which encoding of its
+/// factors leads to which columns, see [`Wilkinson::design_info`](super::Wilkinson::design_info).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DesignInfo {
+    pub column_names: Vec<String>,
+    pub terms: Vec<TermInfo>,
+}
+impl DesignInfo {
+    pub fn term(&self, name: &str) -> Option<&TermInfo> {
+        self.terms.iter().find(|t| t.name == name)
+    }
+    pub fn term_names(&self) -> Vec<&str> {
+        self.terms.iter().map(|t| t.name.as_str()).collect()
+    }
+    /// Columns of the term with the given name
+    pub fn term_columns(&self, name: &str) -> Option<Range<usize>> {
+        self.term(name).map(|t| t.columns.clone())
+    }
+}
@@ -2,14 +2,22 @@ use exmex::BinOp;
 use exmex::Express;
 use exmex::FlatEx;
 use exmex::MakeOperators;
+use exmex::MatchLiteral;
+use exmex::NumberMatcher;
 use exmex::Operator;
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::mem;
 
 use super::Value;
 use super::ops_common;
+use super::value::ValueSet;
 use crate::array::Array2d;
 use crate::array::DefaultOrder;
 use crate::array::MemOrder;
+use crate::data::{DataSource, NaAction, RowSubset};
+use crate::result::{RoResult, to_ro};
+use crate::roerr;
 fn apply_op<M: MemOrder>(
     mut a: Value<M>,
     mut b: Value<M>,
@@ -50,7 +58,7 @@ pub fn op_unary<M: MemOrder>(a: Value<M>, op: &impl Fn(f64) -> f64) -> Value<M>
             arr.elt_mutate(op);
             Value::Array(arr)
         }
-        Value::Scalar(s) => Value::Scalar(s),
+        Value::Scalar(s) => Value::Scalar(op(s)),
         _ => Value::Error("can only apply unary operator to numerical values".to_string()),
     }
 }
@@ -85,56 +93,155 @@ fn floats_almost_equals(a: f64, b: f64, epsilon: f64) -> bool {
     }
 }
 
-fn floats_ge(a: f64, b: f64, epsilon: f64) -> bool {
-    floats_almost_equals(a, b, epsilon) || a > b
+/// Tolerance of the comparison operators like `==` or `>=` of arithmetic expressions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tolerance {
+    /// Values are only equal if they are exactly the same
+    Exact,
+    /// Values are equal if their absolute difference is at most the given value
+    Absolute(f64),
+    /// Values are equal if their difference relative to their magnitude is less than the given
+    /// value
+    Relative(f64),
+}
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance::Relative(1e-8)
+    }
+}
+impl Tolerance {
+    /// Creates the tolerance from its mode `"exact"`, `"absolute"`, or `"relative"`. The value is
+    /// ignored for exact comparisons.
+    pub fn from_mode(mode: &str, value: f64) -> RoResult<Self> {
+        if !(value >= 0.0 && value.is_finite()) {
+            return Err(roerr!(
+                "tolerance needs to be finite and non-negative, got {}",
+                value
+            ));
+        }
+        match mode {
+            "exact" => Ok(Tolerance::Exact),
+            "absolute" => Ok(Tolerance::Absolute(value)),
+            "relative" => Ok(Tolerance::Relative(value)),
+            _ => Err(roerr!(
+                "unknown tolerance mode {}, expected exact, absolute, or relative",
+                mode
+            )),
+        }
+    }
+
+    pub fn is_close(&self, a: f64, b: f64) -> bool {
+        match self {
+            Tolerance::Exact => a == b,
+            Tolerance::Absolute(epsilon) => a == b || (a - b).abs() <= *epsilon,
+            Tolerance::Relative(epsilon) => floats_almost_equals(a, b, *epsilon),
+        }
+    }
+}
+
+thread_local! {
+    /// Tolerance of the [`Arithmetic`] that is evaluated on this thread
+    static TOLERANCE: Cell<Tolerance> = Cell::new(Tolerance::default());
+}
+
+/// Restores the previous tolerance also if the evaluation panics
+struct ToleranceGuard(Tolerance);
+impl Drop for ToleranceGuard {
+    fn drop(&mut self) {
+        TOLERANCE.with(|t| t.set(self.0));
+    }
+}
+
+/// Runs `f` such that comparisons use the tolerance of an [`Arithmetic`]. Only the methods of
+/// [`Arithmetic`] set the tolerance, plain [`ExprArithmetic`]s compare with the default.
+fn with_tolerance<T>(tolerance: Tolerance, f: impl FnOnce() -> T) -> T {
+    let _guard = ToleranceGuard(TOLERANCE.with(|t| t.replace(tolerance)));
+    f()
 }
 
-fn floats_gt(a: f64, b: f64, epsilon: f64) -> bool {
-    !floats_almost_equals(a, b, epsilon) && a > b
+fn floats_ge(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    tolerance.is_close(a, b) || a > b
 }
-fn floats_le(a: f64, b: f64, epsilon: f64) -> bool {
-    floats_almost_equals(a, b, epsilon) || a < b
+
+fn floats_gt(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    !tolerance.is_close(a, b) && a > b
+}
+fn floats_le(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    tolerance.is_close(a, b) || a < b
+}
+fn floats_lt(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    !tolerance.is_close(a, b) && a < b
+}
+fn floats_eq(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    tolerance.is_close(a, b)
 }
-fn floats_lt(a: f64, b: f64, epsilon: f64) -> bool {
-    !floats_almost_equals(a, b, epsilon) && a < b
+fn floats_ne(a: f64, b: f64, tolerance: Tolerance) -> bool {
+    !tolerance.is_close(a, b)
 }
 
 macro_rules! op_compare {
-    ($a:expr, $b:expr, $comp_exact:expr, $comp_float:expr) => {
+    ($a:expr, $b:expr, $comp_exact:expr, $comp_float:expr) => {{
+        let tolerance = TOLERANCE.with(Cell::get);
         match ($a, $b) {
-            (Value::Scalar(s), Value::Array(a)) => Value::RowInds(
-                a.iter()
+            (Value::Scalar(s), Value::Array(a)) => Value::RowInds {
+                inds: a
+                    .iter()
                     .enumerate()
-                    .filter(|(_, ai)| $comp_float(*ai, s, 1e-8))
+                    .filter(|(_, ai)| $comp_float(s, *ai, tolerance))
                     .map(|(i, _)| i)
                     .collect(),
-            ),
-            (Value::Array(a), Value::Scalar(s)) => Value::RowInds(
-                a.iter()
+                n_rows: a.n_rows(),
+            },
+            (Value::Array(a), Value::Scalar(s)) => Value::RowInds {
+                inds: a
+                    .iter()
                     .enumerate()
-                    .filter(|(_, ai)| $comp_float(*ai, s, 1e-8))
+                    .filter(|(_, ai)| $comp_float(*ai, s, tolerance))
                     .map(|(i, _)| i)
                     .collect(),
-            ),
-            (Value::Array(a), Value::Array(b)) => Value::RowInds(
-                a.iter()
+                n_rows: a.n_rows(),
+            },
+            (Value::Array(a), Value::Array(b)) => Value::RowInds {
+                inds: a
+                    .iter()
                     .zip(b.iter())
                     .enumerate()
-                    .filter(|(_, (ai, bi))| $comp_float(*ai, *bi, 1e-8))
+                    .filter(|(_, (ai, bi))| $comp_float(*ai, *bi, tolerance))
                     .map(|(i, _)| i)
                     .collect(),
-            ),
-            (Value::Cats(c1), Value::Cats(c2)) => {
-                Value::RowInds(compare_slices(&c1, &c2, $comp_exact))
-            }
-            (Value::RowInds(ri1), Value::RowInds(ri2)) => {
-                Value::RowInds(compare_slices(&ri1, &ri2, $comp_exact))
+                n_rows: a.n_rows().min(b.n_rows()),
+            },
+            // like NA in R, missing categoricals do not satisfy any comparison
+            (Value::Cats(c1), Value::Cats(c2)) => Value::RowInds {
+                inds: compare_slices(&c1, &c2, |c1, c2| match (c1, c2) {
+                    (Some(c1), Some(c2)) => $comp_exact(c1, c2),
+                    _ => false,
+                }),
+                n_rows: c1.len().min(c2.len()),
+            },
+            (Value::Cats(c), Value::Str(s)) => Value::RowInds {
+                inds: (0..c.len())
+                    .filter(|i| c[*i].as_ref().is_some_and(|c| $comp_exact(c, &s)))
+                    .collect(),
+                n_rows: c.len(),
+            },
+            (Value::Str(s), Value::Cats(c)) => Value::RowInds {
+                inds: (0..c.len())
+                    .filter(|i| c[*i].as_ref().is_some_and(|c| $comp_exact(&s, c)))
+                    .collect(),
+                n_rows: c.len(),
+            },
+            (Value::RowInds { inds: ri1, .. }, Value::RowInds { inds: ri2, .. }) => {
+                Value::RowInds {
+                    inds: compare_slices(&ri1, &ri2, $comp_exact),
+                    n_rows: ri1.len().min(ri2.len()),
+                }
             }
             (Value::Error(e), _) => Value::Error(e),
             (_, Value::Error(e)) => Value::Error(e),
             _ => Value::Error("cannot compare values".to_string()),
         }
-    };
+    }};
 }
 
 pub fn op_compare_ge<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
@@ -150,12 +257,15 @@ pub fn op_compare_lt<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
     op_compare!(a, b, |v1, v2| v1 < v2, floats_lt)
 }
 pub fn op_compare_equals<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
-    op_compare!(a, b, |v1, v2| v1 == v2, floats_almost_equals)
+    op_compare!(a, b, |v1, v2| v1 == v2, floats_eq)
+}
+pub fn op_compare_not_equals<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+    op_compare!(a, b, |v1, v2| v1 != v2, floats_ne)
 }
 
 pub fn op_restrict<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
     match (a, b) {
-        (Value::Array(a), Value::RowInds(ris)) => {
+        (Value::Array(a), Value::RowInds { inds: ris, .. }) => {
             let max = ris.iter().max();
             if let Some(max) = max {
                 if *max >= a.n_rows() {
@@ -177,18 +287,201 @@ pub fn op_restrict<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
                 Value::Array(Array2d::ones(0, a.n_cols()))
             }
         }
-        (Value::Cats(mut c), Value::RowInds(ris)) => {
+        (Value::Cats(mut c), Value::RowInds { inds: ris, .. }) => {
             Value::Cats(ris.iter().map(|i| mem::take(&mut c[*i])).collect())
         }
-        (Value::RowInds(a), Value::RowInds(ris)) => {
-            Value::RowInds(ris.iter().map(|i| a[*i]).collect())
-        }
+        (Value::RowInds { inds: a, n_rows }, Value::RowInds { inds: ris, .. }) => Value::RowInds {
+            inds: ris.iter().map(|i| a[*i]).collect(),
+            n_rows,
+        },
         (Value::Error(e), _) => Value::Error(e),
         (_, Value::Error(e)) => Value::Error(e),
         _ => Value::Error("can only restrict arrays, categories or row indices".to_string()),
     }
 }
 
+/// Numbers of a list literal prepared for fast lookups
+struct NumberSet {
+    /// Bits of the numbers where `-0.0` is replaced by `0.0`
+    exact: HashSet<u64>,
+    sorted: Vec<f64>,
+    tolerance: Tolerance,
+}
+impl NumberSet {
+    fn new(numbers: &[f64], tolerance: Tolerance) -> Self {
+        let mut sorted = numbers
+            .iter()
+            .copied()
+            .filter(|x| !x.is_nan())
+            .collect::<Vec<_>>();
+        sorted.sort_by(f64::total_cmp);
+        NumberSet {
+            exact: sorted.iter().map(|x| (x + 0.0).to_bits()).collect(),
+            sorted,
+            tolerance,
+        }
+    }
+    fn contains(&self, x: f64) -> bool {
+        if self.exact.contains(&(x + 0.0).to_bits()) {
+            true
+        } else if self.tolerance == Tolerance::Exact || x.is_nan() {
+            false
+        } else {
+            // only the closest numbers below and above can be equal within the tolerance
+            let idx = self.sorted.partition_point(|y| *y < x);
+            [idx.checked_sub(1), Some(idx)]
+                .into_iter()
+                .flatten()
+                .filter_map(|idx| self.sorted.get(idx))
+                .any(|y| self.tolerance.is_close(x, *y))
+        }
+    }
+}
+
+/// Selects the rows whose values are contained in a list like `x %in% [3, 5]`. Strings are
+/// compared exactly, numbers with the tolerance of the [`Arithmetic`].
+pub fn op_in<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+    let select = |n_rows: usize, is_member: &dyn Fn(usize) -> bool| Value::RowInds {
+        inds: (0..n_rows).filter(|i| is_member(*i)).collect(),
+        n_rows,
+    };
+    match (a, b) {
+        (Value::Array(a), Value::Set(set)) if set.is_empty() => select(a.n_rows(), &|_| false),
+        (Value::Cats(c), Value::Set(set)) if set.is_empty() => select(c.len(), &|_| false),
+        (Value::Array(a), Value::Set(ValueSet::Numbers(numbers))) => {
+            if a.n_cols() != 1 {
+                return Value::Error("membership needs a single column".to_string());
+            }
+            let set = NumberSet::new(&numbers, TOLERANCE.with(Cell::get));
+            select(a.n_rows(), &|i| set.contains(a.get(i, 0)))
+        }
+        (Value::Cats(c), Value::Set(ValueSet::Strings(strings))) => {
+            let set = strings.iter().map(String::as_str).collect::<HashSet<_>>();
+            select(c.len(), &|i| {
+                c[i].as_deref().is_some_and(|c| set.contains(c))
+            })
+        }
+        (Value::Error(e), _) => Value::Error(e),
+        (_, Value::Error(e)) => Value::Error(e),
+        (Value::Array(_), Value::Set(_)) => {
+            Value::Error("numerical values can only be members of numbers".to_string())
+        }
+        (Value::Cats(_), Value::Set(_)) => {
+            Value::Error("categorical values can only be members of strings".to_string())
+        }
+        _ => Value::Error("membership needs a column and a list like [1, 2]".to_string()),
+    }
+}
+
+/// Pairs the branches of a conditional `cond ? a : b` as the two columns of an array. A scalar
+/// branch is repeated for each row of the other branch, two scalars form a single row.
+fn op_branches<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+    let n_rows = match (&a, &b) {
+        (Value::Array(arr), _) | (_, Value::Array(arr)) => arr.n_rows(),
+        _ => 1,
+    };
+    let to_column = |branch: Value<M>| match branch {
+        Value::Array(arr) if arr.n_cols() == 1 => Ok(arr),
+        Value::Scalar(s) => {
+            Array2d::from_vec(vec![s; n_rows], n_rows, 1).map_err(|e| e.to_string())
+        }
+        Value::Error(e) => Err(e),
+        _ => Err("branches of a conditional need to be scalars or single columns".to_string()),
+    };
+    let res =
+        to_column(a).and_then(|a| a.concatenate_cols(to_column(b)?).map_err(|e| e.to_string()));
+    match res {
+        Ok(branches) => Value::Array(branches),
+        Err(e) => Value::Error(e),
+    }
+}
+
+/// Conditional `cond ? a : b` that keeps all rows. Rows selected by the condition are taken from
+/// the first column of the branches, all other rows from the second, see [`op_branches`].
+fn op_where<M: MemOrder>(cond: Value<M>, branches: Value<M>) -> Value<M> {
+    match (cond, branches) {
+        (Value::RowInds { inds, n_rows }, Value::Array(branches))
+            if branches.n_cols() == 2 && [1, n_rows].contains(&branches.n_rows()) =>
+        {
+            let mask = row_mask(&inds, n_rows);
+            let data = (0..n_rows)
+                .map(|row| {
+                    let branch_row = row.min(branches.n_rows() - 1);
+                    branches.get(branch_row, if mask[row] { 0 } else { 1 })
+                })
+                .collect();
+            match Array2d::from_vec(data, n_rows, 1) {
+                Ok(arr) => Value::Array(arr),
+                Err(e) => Value::Error(e.to_string()),
+            }
+        }
+        (Value::Error(e), _) => Value::Error(e),
+        (_, Value::Error(e)) => Value::Error(e),
+        (Value::RowInds { n_rows, .. }, _) => Value::Error(format!(
+            "branches of a conditional need to be scalars or columns with {n_rows} rows"
+        )),
+        _ => Value::Error("condition needs a row selection like x>1".to_string()),
+    }
+}
+
+fn row_mask(inds: &[usize], n_rows: usize) -> Vec<bool> {
+    let mut mask = vec![false; n_rows];
+    for i in inds.iter().filter(|i| **i < n_rows) {
+        mask[*i] = true;
+    }
+    mask
+}
+
+/// Combines two selections of the same rows. The resulting indices are in ascending order.
+fn op_logical<M: MemOrder>(a: Value<M>, b: Value<M>, op: fn(bool, bool) -> bool) -> Value<M> {
+    match (a, b) {
+        (
+            Value::RowInds { inds: a, n_rows },
+            Value::RowInds {
+                inds: b,
+                n_rows: n_rows_b,
+            },
+        ) => {
+            if n_rows != n_rows_b {
+                return Value::Error(format!(
+                    "cannot combine selections of {n_rows} and {n_rows_b} rows"
+                ));
+            }
+            let (a, b) = (row_mask(&a, n_rows), row_mask(&b, n_rows));
+            Value::RowInds {
+                inds: (0..n_rows).filter(|i| op(a[*i], b[*i])).collect(),
+                n_rows,
+            }
+        }
+        (Value::Error(e), _) => Value::Error(e),
+        (_, Value::Error(e)) => Value::Error(e),
+        _ => Value::Error("logical operators need row selections like x>1 as operands".to_string()),
+    }
+}
+
+/// Intersection of row selections
+pub fn op_and<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+    op_logical(a, b, |a, b| a && b)
+}
+/// Union of row selections
+pub fn op_or<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+    op_logical(a, b, |a, b| a || b)
+}
+/// Complement of a row selection
+pub fn op_not<M: MemOrder>(a: Value<M>) -> Value<M> {
+    match a {
+        Value::RowInds { inds, n_rows } => {
+            let mask = row_mask(&inds, n_rows);
+            Value::RowInds {
+                inds: (0..n_rows).filter(|i| !mask[*i]).collect(),
+                n_rows,
+            }
+        }
+        Value::Error(e) => Value::Error(e),
+        _ => Value::Error("negation needs a row selection like x>1 as operand".to_string()),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ArithmeticOpsFactory;
 impl<M> MakeOperators<Value<M>> for ArithmeticOpsFactory
@@ -201,7 +494,7 @@ where
                 "^",
                 BinOp {
                     apply: ops_common::op_power,
-                    prio: 6,
+                    prio: 8,
                     is_commutative: false,
                 },
             ),
@@ -209,7 +502,7 @@ where
                 "*",
                 BinOp {
                     apply: op_mul,
-                    prio: 4,
+                    prio: 6,
                     is_commutative: false,
                 },
             ),
@@ -217,7 +510,7 @@ where
                 "+",
                 BinOp {
                     apply: op_add,
-                    prio: 2,
+                    prio: 4,
                     is_commutative: false,
                 },
             ),
@@ -225,7 +518,7 @@ where
                 "/",
                 BinOp {
                     apply: op_div,
-                    prio: 5,
+                    prio: 7,
                     is_commutative: false,
                 },
             ),
@@ -233,7 +526,7 @@ where
                 "-",
                 BinOp {
                     apply: op_sub,
-                    prio: 3,
+                    prio: 5,
                     is_commutative: false,
                 },
                 |a| op_unary(a, &|a| -a),
@@ -242,10 +535,26 @@ where
                 "==",
                 BinOp {
                     apply: op_compare_equals,
-                    prio: 1,
+                    prio: 3,
+                    is_commutative: true,
+                },
+            ),
+            Operator::make_bin(
+                "!=",
+                BinOp {
+                    apply: op_compare_not_equals,
+                    prio: 3,
                     is_commutative: true,
                 },
             ),
+            Operator::make_bin(
+                "%in%",
+                BinOp {
+                    apply: op_in,
+                    prio: 3,
+                    is_commutative: false,
+                },
+            ),
             Operator::make_bin(
                 "|",
                 BinOp {
@@ -254,11 +563,28 @@ where
                     is_commutative: false,
                 },
             ),
+            Operator::make_bin(
+                "&&",
+                BinOp {
+                    apply: op_and,
+                    prio: 2,
+                    is_commutative: true,
+                },
+            ),
+            Operator::make_bin(
+                "||",
+                BinOp {
+                    apply: op_or,
+                    prio: 1,
+                    is_commutative: true,
+                },
+            ),
+            Operator::make_unary("!", op_not),
             Operator::make_bin(
                 "<",
                 BinOp {
                     apply: op_compare_lt,
-                    prio: 1,
+                    prio: 3,
                     is_commutative: false,
                 },
             ),
@@ -266,7 +592,7 @@ where
                 "<=",
                 BinOp {
                     apply: op_compare_le,
-                    prio: 1,
+                    prio: 3,
                     is_commutative: false,
                 },
             ),
@@ -274,7 +600,7 @@ where
                 ">",
                 BinOp {
                     apply: op_compare_gt,
-                    prio: 1,
+                    prio: 3,
                     is_commutative: false,
                 },
             ),
@@ -282,7 +608,7 @@ where
                 ">=",
                 BinOp {
                     apply: op_compare_ge,
-                    prio: 1,
+                    prio: 3,
                     is_commutative: false,
                 },
             ),
@@ -309,15 +635,275 @@ where
     }
 }
 
+/// Operators of [`ArithmeticOpsFactory`] together with the conditional `cond ? a : b`. `?` and `:`
+/// are only created by rewriting `where(cond, a, b)` in [`parse_arithmetic`] such that all
+/// operands are in parentheses, see [`rewrite_syntax`].
+#[derive(Clone, Debug)]
+struct ConditionalOpsFactory;
+impl<M> MakeOperators<Value<M>> for ConditionalOpsFactory
+where
+    M: Clone + MemOrder,
+{
+    fn make<'b>() -> Vec<Operator<'b, Value<M>>> {
+        let mut ops = <ArithmeticOpsFactory as MakeOperators<Value<M>>>::make();
+        ops.push(Operator::make_bin(
+            "?",
+            BinOp {
+                apply: op_where,
+                prio: -2,
+                is_commutative: false,
+            },
+        ));
+        ops.push(Operator::make_bin(
+            ":",
+            BinOp {
+                apply: op_branches,
+                prio: -1,
+                is_commutative: false,
+            },
+        ));
+        ops
+    }
+}
+
 const ROW_CHANGE_OPS: [&str; 1] = ["|"];
 
+fn is_row_change_op(repr: &str) -> bool {
+    ROW_CHANGE_OPS.contains(&repr)
+}
+
 pub fn has_row_change_op(expr: &ExprArithmetic) -> bool {
-    expr.operator_reprs()
+    expr.operator_reprs().iter().any(|o| is_row_change_op(o))
+}
+
+/// Position of the first `c` in the text that is not within quotes
+fn find_unquoted(text: &str, c: char) -> Option<usize> {
+    let mut quote = None;
+    for (i, ci) in text.char_indices() {
+        match (quote, ci) {
+            (None, _) if ci == c => return Some(i),
+            (None, '"' | '\'') => quote = Some(ci),
+            (Some(q), _) if q == ci => quote = None,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Matches numbers, string literals in double or single quotes like `"Pt"`, and lists of them
+/// in brackets like `["Pt", "Pd"]`
+#[derive(Clone, Debug)]
+pub struct ArithmeticLiteralMatcher;
+impl MatchLiteral for ArithmeticLiteralMatcher {
+    fn is_literal(text: &str) -> Option<&str> {
+        match text.chars().next() {
+            Some(quote @ ('"' | '\'')) => text[1..].find(quote).map(|end| &text[..end + 2]),
+            Some('[') => find_unquoted(text, ']').map(|end| &text[..=end]),
+            _ => NumberMatcher::is_literal(text),
+        }
+    }
+}
+
+/// Arithmetic expression on columns. Memberships like `x in (a, b)` and conditionals like
+/// `where(cond, a, b)` are only understood by [`parse_arithmetic`].
+pub type ExprArithmetic<M = DefaultOrder> =
+    FlatEx<Value<M>, ArithmeticOpsFactory, ArithmeticLiteralMatcher>;
+
+type ExprConditional<M> = FlatEx<Value<M>, ConditionalOpsFactory, ArithmeticLiteralMatcher>;
+
+fn is_var_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits the arguments of a call at commas that are neither nested, quoted, nor in braces. The
+/// text starts after the opening parenthesis. Returns the arguments and the position of the
+/// closing parenthesis.
+fn split_call_args(text: &str) -> Option<(Vec<&str>, usize)> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    let mut args = vec![];
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => quote = Some('}'),
+            (None, '(' | '[') => depth += 1,
+            (None, ')') if depth == 0 => {
+                args.push(&text[start..i]);
+                return Some((args, i));
+            }
+            (None, ')' | ']') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                args.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Rewrites syntax that exmex cannot parse directly, i.e., memberships like `site in ("a", "b")`
+/// to `site %in% ["a", "b"]` and conditionals like `where(x > 1, x, 0)` to
+/// `((x > 1) ? (x) : (0))`. Only an `in` that is followed by a parenthesis is a membership such that
+/// columns can be named `in`. Within quotes, braces, and `%in%`, the `in` is kept. The operators `?`
+/// and `:` of conditionals cannot be used directly since exmex would parse nested conditionals
+/// like `a ? b : c ? d : e` from left to right.
+fn rewrite_syntax(text: &str) -> RoResult<String> {
+    let mut rewritten = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev = None;
+    while let Some(c) = rest.chars().next() {
+        let skip_to = match c {
+            '"' | '\'' => rest[1..].find(c).map(|end| end + 2),
+            '{' => rest.find('}').map(|end| end + 1),
+            '?' | ':' => {
+                return Err(roerr!(
+                    "{} is not supported, conditionals are written as where(cond, a, b)",
+                    c
+                ));
+            }
+            'i' if rest.starts_with("in")
+                && !prev.is_some_and(|c| is_var_char(c) || c == '%')
+                && rest[2..].trim_start().starts_with('(') =>
+            {
+                let list = rest[2..].trim_start();
+                let end = find_unquoted(&list[1..], ')').ok_or_else(|| {
+                    roerr!("in misses the closing parenthesis of its list of values")
+                })?;
+                rewritten.push_str(&format!("%in% [{}]", &list[1..end + 1]));
+                rest = &list[end + 2..];
+                prev = Some(')');
+                continue;
+            }
+            'w' if rest.starts_with("where")
+                && !prev.is_some_and(is_var_char)
+                && rest[5..].trim_start().starts_with('(') =>
+            {
+                let call = rest[5..].trim_start();
+                let (args, end) = split_call_args(&call[1..])
+                    .ok_or_else(|| roerr!("where misses a closing parenthesis"))?;
+                if args.len() != 3 {
+                    return Err(roerr!(
+                        "where needs 3 arguments like where(x > 1, x, 0) but got {}",
+                        args.len()
+                    ));
+                }
+                let args = args
+                    .into_iter()
+                    .map(|arg| rewrite_syntax(arg.trim()))
+                    .collect::<RoResult<Vec<_>>>()?;
+                rewritten.push_str(&format!("(({}) ? ({}) : ({}))", args[0], args[1], args[2]));
+                rest = &call[end + 2..];
+                prev = Some(')');
+                continue;
+            }
+            _ => None,
+        };
+        let n_bytes = skip_to.unwrap_or(c.len_utf8()).min(rest.len());
+        rewritten.push_str(&rest[..n_bytes]);
+        prev = rest[..n_bytes].chars().next_back();
+        rest = &rest[n_bytes..];
+    }
+    Ok(rewritten)
+}
+
+/// Arithmetic expression together with the tolerance of its comparisons, see
+/// [`parse_arithmetic`]
+#[derive(Clone, Debug)]
+pub struct Arithmetic<M: MemOrder = DefaultOrder> {
+    expr: ExprConditional<M>,
+    tolerance: Tolerance,
+}
+impl<M: MemOrder> Arithmetic<M> {
+    /// Whether the expression changes the number of rows like `x|y>1`
+    pub fn has_row_change_op(&self) -> bool {
+        self.expr
+            .operator_reprs()
+            .iter()
+            .any(|o| is_row_change_op(o))
+    }
+
+    pub fn tolerance(&self) -> Tolerance {
+        self.tolerance
+    }
+
+    /// Text of the expression with memberships and conditionals rewritten into operators
+    pub fn unparse(&self) -> &str {
+        self.expr.unparse()
+    }
+
+    pub fn var_names(&self) -> &[String] {
+        self.expr.var_names()
+    }
+
+    pub fn operator_reprs(&self) -> Vec<String> {
+        self.expr.operator_reprs().to_vec()
+    }
+
+    pub fn binary_reprs(&self) -> Vec<String> {
+        self.expr.binary_reprs().to_vec()
+    }
+
+    pub fn unary_reprs(&self) -> Vec<String> {
+        self.expr.unary_reprs().to_vec()
+    }
+
+    /// Evaluates the expression where comparisons use the tolerance of the expression
+    pub fn eval_vec(&self, vars: Vec<Value<M>>) -> RoResult<Value<M>> {
+        with_tolerance(self.tolerance, || self.expr.eval_vec(vars)).map_err(to_ro)
+    }
+}
+
+/// Parses an arithmetic expression whose comparisons use the given tolerance. Additionally to the
+/// syntax of [`ExprArithmetic`], the membership `x in (a, b)` is understood as `x %in% [a, b]`
+/// and the conditional `where(cond, a, b)` as `(cond) ? (a) : (b)`.
+pub fn parse_arithmetic<M: MemOrder>(text: &str, tolerance: Tolerance) -> RoResult<Arithmetic<M>> {
+    Ok(Arithmetic {
+        expr: ExprConditional::parse(&rewrite_syntax(text)?).map_err(to_ro)?,
+        tolerance,
+    })
+}
+
+/// Looks up the numerical or categorical columns of the variables of the expression.
+pub(crate) fn load_vars(
+    expr: &Arithmetic,
+    data: &impl DataSource,
+) -> RoResult<Vec<Value<DefaultOrder>>> {
+    expr.var_names()
         .iter()
-        .any(|o| ROW_CHANGE_OPS.contains(&o.as_str()))
+        .map(|vn| {
+            if let Some(col) = data.numerical(vn)? {
+                let n_rows = col.len();
+                Ok(Value::Array(Array2d::from_vec(col, n_rows, 1)?))
+            } else if let Some(cats) = data.categorical(vn)? {
+                Ok(Value::Cats(cats))
+            } else {
+                Err(roerr!("did not find Variable {} in the data", vn))
+            }
+        })
+        .collect()
 }
 
-pub type ExprArithmetic<M = DefaultOrder> = FlatEx<Value<M>, ArithmeticOpsFactory>;
+/// Evaluates the expression on the numerical and categorical columns of the data. Missing values
+/// are handled according to `na_action`. Returns the result together with the indices of the used
+/// rows, `None` if all rows have been used.
+pub fn eval_arithmetic(
+    expr: &Arithmetic,
+    data: &impl DataSource,
+    na_action: NaAction,
+) -> RoResult<(Value<DefaultOrder>, Option<Vec<usize>>)> {
+    match na_action.complete_rows(data, expr.var_names())? {
+        Some(rows) => {
+            let data = RowSubset::new(data, rows);
+            let res = expr.eval_vec(load_vars(expr, &data)?)?;
+            Ok((res, Some(data.into_rows())))
+        }
+        None => Ok((expr.eval_vec(load_vars(expr, data)?)?, None)),
+    }
+}
 
 #[cfg(test)]
 use crate::array::ColMajor;
@@ -333,19 +919,50 @@ fn keep_or_change_ops() {
     assert!(has_row_change_op(&x));
 }
 #[test]
+fn test_na_action() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[("x", &[1.0, f64::NAN, 3.0]), ("y", &[1.0, 2.0, 3.0])],
+        &[],
+    );
+    let expr = parse_arithmetic("x + y", Tolerance::default()).unwrap();
+    let (res, rows) = eval_arithmetic(&expr, &data, NaAction::Keep).unwrap();
+    assert!(rows.is_none());
+    match res {
+        Value::Array(a) => assert!(a.get(1, 0).is_nan()),
+        _ => panic!("need array as result"),
+    }
+    let (res, rows) = eval_arithmetic(&expr, &data, NaAction::Drop).unwrap();
+    assert_eq!(rows, Some(vec![0, 2]));
+    let a_ref = Array2d::from_iter([2.0, 6.0].iter(), 2, 1).unwrap();
+    assert_eq!(res, Value::Array(a_ref));
+    let err = eval_arithmetic(&expr, &data, NaAction::Raise).unwrap_err();
+    assert_eq!(err.msg(), "missing value in column x in row 1");
+    let expr = parse_arithmetic("2 * y", Tolerance::default()).unwrap();
+    let (_, rows) = eval_arithmetic(&expr, &data, NaAction::Raise).unwrap();
+    assert!(rows.is_none());
+}
+#[cfg(test)]
+fn row_inds(inds: &[usize], n_rows: usize) -> Value<ColMajor> {
+    Value::RowInds {
+        inds: inds.to_vec(),
+        n_rows,
+    }
+}
+#[test]
 fn test() {
     let a = Array2d::<ColMajor>::from_iter([0.0, 1.0, 2.0, 3.0, 4.0, 5.0].iter(), 3, 2).unwrap();
     let a_ref = Array2d::from_iter([1.0, 2.0, 3.0, 4.0, 5.0, 6.0].iter(), 3, 2).unwrap();
     let res = op_add(Value::Array(a.clone()), Value::Scalar(1.0));
     match res {
         Value::Array(a) => assert_eq!(a, a_ref.clone()),
-        _ => assert!(false),
+        _ => panic!("need array as result"),
     }
     let res = op_sub(Value::Scalar(1.0), Value::Array(a.clone()));
     let a_ref = Array2d::from_iter([1.0, 0.0, -1.0, -2.0, -3.0, -4.0].iter(), 3, 2).unwrap();
     match res {
         Value::Array(a) => assert_eq!(a, a_ref.clone()),
-        _ => assert!(false),
+        _ => panic!("need array as result"),
     }
     let a = Array2d::<ColMajor>::from_iter([0.0, 1.0, 2.0, 3.0, 4.0, 5.0].iter(), 6, 1).unwrap();
     let b = Array2d::from_iter([2.0, 1.0, 3.0, 5.0, 10.0, 9.0].iter(), 6, 1).unwrap();
@@ -353,7 +970,7 @@ fn test() {
     let a_ref = Array2d::from_iter([0.0, 1.0, 6.0, 15.0, 40.0, 45.0].iter(), 6, 1).unwrap();
     match res {
         Value::Array(a) => assert_eq!(a, a_ref.clone()),
-        _ => assert!(false),
+        _ => panic!("need array as result"),
     }
     let res = op_div(Value::Array(a.clone()), Value::Array(b.clone()));
     let a_ref = Array2d::from_iter(
@@ -364,52 +981,348 @@ fn test() {
     .unwrap();
     match res {
         Value::Array(a) => assert_eq!(a, a_ref.clone()),
-        _ => assert!(false),
+        _ => panic!("need array as result"),
     }
 
     let res = op_compare_ge(Value::Array(a.clone()), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![1]);
+    let a_ref = Value::RowInds {
+        inds: vec![1],
+        n_rows: 6,
+    };
     assert_eq!(res, a_ref);
     let res = op_compare_gt(Value::Array(a.clone()), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![]);
+    let a_ref = row_inds(&[], 6);
     assert_eq!(res, a_ref);
     let res = op_compare_le(Value::Array(a.clone()), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![0, 1, 2, 3, 4, 5]);
+    let a_ref = row_inds(&[0, 1, 2, 3, 4, 5], 6);
     assert_eq!(res, a_ref);
     let res = op_compare_lt(Value::Array(a.clone()), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![0, 2, 3, 4, 5]);
+    let a_ref = row_inds(&[0, 2, 3, 4, 5], 6);
     assert_eq!(res, a_ref);
 
     let res = op_compare_equals(Value::Scalar(1.0), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![1]);
+    let a_ref = row_inds(&[1], 6);
     assert_eq!(res, a_ref);
     let res = op_compare_equals(Value::Array(a.clone()), Value::Array(b.clone()));
-    let a_ref = Value::RowInds(vec![1]);
+    let a_ref = row_inds(&[1], 6);
     assert_eq!(res, a_ref);
     let res = op_compare_equals(
-        Value::<ColMajor>::Cats(vec!["a".to_string(), "b".to_string()]),
-        Value::Cats(vec!["a".to_string(), "c".to_string()]),
+        Value::<ColMajor>::Cats(vec![Some("a".to_string()), Some("b".to_string())]),
+        Value::Cats(vec![Some("a".to_string()), Some("c".to_string())]),
     );
-    let a_ref = Value::RowInds(vec![0]);
+    let a_ref = row_inds(&[0], 2);
     assert_eq!(res, a_ref);
-    let res: Value<ColMajor> =
-        op_compare_equals(Value::RowInds(vec![4, 3, 2]), Value::RowInds(vec![1, 3, 7]));
-    let a_ref = Value::RowInds(vec![1]);
+    let res: Value<ColMajor> = op_compare_equals(row_inds(&[4, 3, 2], 8), row_inds(&[1, 3, 7], 8));
+    let a_ref = row_inds(&[1], 3);
     assert_eq!(res, a_ref);
 
-    let res = op_restrict(Value::Array(a.clone()), Value::RowInds(vec![0, 2, 4]));
+    let res = op_restrict(Value::Array(a.clone()), row_inds(&[0, 2, 4], 6));
     let a_ref = Value::Array(Array2d::from_iter([0.0, 2.0, 4.0].iter(), 3, 1).unwrap());
     assert_eq!(res, a_ref);
     let res: Value<ColMajor> = op_restrict(
-        Value::Cats(vec!["a".to_string(), "b".to_string()]),
-        Value::RowInds(vec![1]),
+        Value::Cats(vec![Some("a".to_string()), Some("b".to_string())]),
+        row_inds(&[1], 2),
     );
-    let c_ref = Value::Cats(vec!["b".to_string()]);
+    let c_ref = Value::Cats(vec![Some("b".to_string())]);
     assert_eq!(res, c_ref);
-    let res: Value<ColMajor> = op_restrict(
-        Value::RowInds(vec![1, 2, 3, 4]),
-        Value::RowInds(vec![1, 2, 3]),
-    );
-    let r_ref = Value::RowInds(vec![2, 3, 4]);
+    let res: Value<ColMajor> = op_restrict(row_inds(&[1, 2, 3, 4], 5), row_inds(&[1, 2, 3], 4));
+    let r_ref = row_inds(&[2, 3, 4], 5);
     assert_eq!(res, r_ref);
 }
+#[test]
+fn test_logical() {
+    use crate::data::separated_data;
+    let res = op_and(row_inds(&[0, 2, 3], 5), row_inds(&[3, 4, 0], 5));
+    assert_eq!(res, row_inds(&[0, 3], 5));
+    let res = op_or(row_inds(&[0, 2, 3], 5), row_inds(&[4], 5));
+    assert_eq!(res, row_inds(&[0, 2, 3, 4], 5));
+    assert_eq!(op_not(row_inds(&[0, 2, 3], 5)), row_inds(&[1, 4], 5));
+    assert!(matches!(
+        op_and(row_inds(&[0], 5), row_inds(&[0], 4)),
+        Value::Error(_)
+    ));
+    assert!(matches!(
+        op_not(Value::<ColMajor>::Scalar(1.0)),
+        Value::Error(_)
+    ));
+
+    let data = separated_data(
+        &[
+            ("a", &[0.0, 2.0, 3.0, 4.0]),
+            ("b", &[1.0, 1.0, 3.0, 1.0]),
+            ("c", &[3.0, 3.0, 3.0, 1.0]),
+            ("x", &[10.0, 11.0, 12.0, 13.0]),
+        ],
+        &[],
+    );
+    let eval = |s: &str| {
+        let expr = parse_arithmetic(s, Tolerance::default()).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    let to_value = |x: &[f64]| Value::Array(Array2d::from_iter(x.iter(), x.len(), 1).unwrap());
+    assert_eq!(eval("x|(a>1 && b<2)"), to_value(&[11.0, 13.0]));
+    assert_eq!(eval("x|a>1 && b<2"), to_value(&[11.0, 13.0]));
+    assert_eq!(eval("x|a<1 || b>2 && c==3"), to_value(&[10.0, 12.0]));
+    assert_eq!(eval("x|(a<1 || b>2) && !(c==3)"), to_value(&[]));
+    assert_eq!(eval("x|!(c==3)"), to_value(&[13.0]));
+    assert_eq!(eval("x|!(c==3) || a+1==1"), to_value(&[10.0, 13.0]));
+    assert!(!has_row_change_op(
+        &ExprArithmetic::parse("a>1 && !(b<2)").unwrap()
+    ));
+}
+#[test]
+fn test_tolerance() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[
+            ("x", &[1.0, 1.0 + 1e-10, 1.05, 2.0]),
+            ("y", &[1.0, 2.0, 3.0, 4.0]),
+        ],
+        &[],
+    );
+    let eval = |s: &str, tolerance| {
+        let expr = parse_arithmetic(s, tolerance).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    let to_value = |x: &[f64]| Value::Array(Array2d::from_iter(x.iter(), x.len(), 1).unwrap());
+    assert_eq!(eval("y|x==1", Tolerance::default()), to_value(&[1.0, 2.0]));
+    assert_eq!(eval("y|x!=1", Tolerance::default()), to_value(&[3.0, 4.0]));
+    assert_eq!(eval("y|x==1", Tolerance::Exact), to_value(&[1.0]));
+    assert_eq!(eval("y|x!=1", Tolerance::Exact), to_value(&[2.0, 3.0, 4.0]));
+    assert_eq!(
+        eval("y|x==1", Tolerance::Absolute(0.1)),
+        to_value(&[1.0, 2.0, 3.0])
+    );
+    assert_eq!(eval("y|x>1", Tolerance::Absolute(0.1)), to_value(&[4.0]));
+    assert_eq!(
+        eval("y|x<=1", Tolerance::Relative(0.1)),
+        to_value(&[1.0, 2.0, 3.0])
+    );
+    assert_eq!(eval("y|!(x!=2)", Tolerance::Exact), to_value(&[4.0]));
+    // the tolerance belongs to the parsed expression and is only used within its evaluation
+    let expr = parse_arithmetic("x==1", Tolerance::Exact).unwrap();
+    let vars = load_vars(&expr, &data).unwrap();
+    assert_eq!(expr.eval_vec(vars.clone()).unwrap(), row_inds(&[0], 4));
+    assert_eq!(expr.tolerance(), Tolerance::Exact);
+    assert_eq!(expr.unparse(), "x==1");
+    // expressions that are parsed without tolerance compare with the default
+    let expr = ExprArithmetic::parse("x==1").unwrap();
+    assert_eq!(expr.eval_vec(vars).unwrap(), row_inds(&[0, 1], 4));
+
+    assert_eq!(
+        Tolerance::from_mode("absolute", 0.5).unwrap(),
+        Tolerance::Absolute(0.5)
+    );
+    assert_eq!(
+        Tolerance::from_mode("exact", 0.0).unwrap(),
+        Tolerance::Exact
+    );
+    assert!(Tolerance::from_mode("relative", -1.0).is_err());
+    assert!(Tolerance::from_mode("approximate", 1e-3).is_err());
+}
+#[test]
+fn test_categorical() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[("yield", &[0.5, 0.7, 0.9, 0.1])],
+        &[
+            ("catalyst", &["Pt", "Pd", "Pt", "none"]),
+            ("batch", &["b 1", "b 2", "b 1", "b 1"]),
+        ],
+    );
+    let eval = |s: &str| {
+        let expr = parse_arithmetic(s, Tolerance::default()).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    let to_value = |x: &[f64]| Value::Array(Array2d::from_iter(x.iter(), x.len(), 1).unwrap());
+    assert_eq!(eval(r#"yield | catalyst == "Pt""#), to_value(&[0.5, 0.9]));
+    assert_eq!(eval("yield|catalyst != 'none'"), to_value(&[0.5, 0.7, 0.9]));
+    assert_eq!(eval(r#"yield|"Pt" == catalyst"#), to_value(&[0.5, 0.9]));
+    assert_eq!(
+        eval(r#"yield|catalyst == "Pt" && batch == "b 1""#),
+        to_value(&[0.5, 0.9])
+    );
+    assert_eq!(eval(r#"yield|catalyst < "Pt""#), to_value(&[0.7]));
+    assert_eq!(
+        eval(r#"catalyst|yield > 0.6"#),
+        Value::Cats(vec![Some("Pd".to_string()), Some("Pt".to_string())])
+    );
+    assert_eq!(eval("yield|catalyst == batch"), to_value(&[]));
+    assert!(matches!(eval(r#"yield + "Pt""#), Value::Error(_)));
+    let expr: ExprArithmetic = ExprArithmetic::parse(r#"yield|catalyst=="Pt""#).unwrap();
+    assert_eq!(expr.var_names(), ["catalyst", "yield"]);
+    assert!(ExprArithmetic::<ColMajor>::parse(r#"yield|catalyst=="Pt"#).is_err());
+    assert_eq!(
+        "'Pt'".parse::<Value<ColMajor>>().unwrap(),
+        Value::Str("Pt".to_string())
+    );
+    assert!("'".parse::<Value<ColMajor>>().is_err());
+
+    // like NA in R, missing values satisfy no comparison, not even with the string NA
+    let data = data
+        .clone()
+        .with_missing("catalyst", &[false, true, false, false])
+        .unwrap();
+    let eval = |s: &str| {
+        let expr = parse_arithmetic(s, Tolerance::default()).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    assert_eq!(eval("yield|catalyst != 'Pt'"), to_value(&[0.1]));
+    assert_eq!(eval("yield|catalyst == 'NA'"), to_value(&[]));
+    assert_eq!(eval("yield|catalyst %in% ['Pd', 'none']"), to_value(&[0.1]));
+}
+#[test]
+fn test_membership() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[
+            ("x", &[1.0, 2.0, 3.0, 4.0]),
+            ("batch", &[3.0, 4.0, 5.0 + 1e-12, 7.0]),
+            ("intensity", &[0.1, 0.2, 0.3, 0.4]),
+        ],
+        &[("site", &["Ludwigshafen", "Antwerp", "Geismar", "Antwerp"])],
+    );
+    let eval = |s: &str, tolerance| {
+        let expr = parse_arithmetic(s, tolerance).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    let to_value = |x: &[f64]| Value::Array(Array2d::from_iter(x.iter(), x.len(), 1).unwrap());
+    let tol = Tolerance::default();
+    assert_eq!(
+        eval(r#"x | site in ("Ludwigshafen", "Antwerp")"#, tol),
+        to_value(&[1.0, 2.0, 4.0])
+    );
+    assert_eq!(
+        eval("x | batch in (3, 5, 7)", tol),
+        to_value(&[1.0, 3.0, 4.0])
+    );
+    assert_eq!(
+        eval("x|batch in(3,5,7)", Tolerance::Exact),
+        to_value(&[1.0, 4.0])
+    );
+    assert_eq!(
+        eval("x | batch %in% [3, 5, 7]", tol),
+        to_value(&[1.0, 3.0, 4.0])
+    );
+    assert_eq!(eval("x|intensity in (0.2, -1)", tol), to_value(&[2.0]));
+    assert_eq!(
+        eval("x|!(site in ('Antwerp')) && batch in (3, 4, 5)", tol),
+        to_value(&[1.0, 3.0])
+    );
+    assert_eq!(eval("x|site in ()", tol), to_value(&[]));
+    assert_eq!(eval("x|site in ('a, in (b)')", tol), to_value(&[]));
+    assert!(matches!(eval("x|site in (1, 2)", tol), Value::Error(_)));
+    assert!(matches!(eval("x|batch in ('3')", tol), Value::Error(_)));
+    assert!(parse_arithmetic::<ColMajor>("x|site in ('a', 1)", Tolerance::default()).is_err());
+    assert!(parse_arithmetic::<ColMajor>("x|site in 'a'", Tolerance::default()).is_err());
+    assert_eq!(
+        rewrite_syntax("{in} + x_in|a in ('in (', \"b\")").unwrap(),
+        "{in} + x_in|a %in% ['in (', \"b\"]"
+    );
+    // a column can be named in
+    assert_eq!(
+        rewrite_syntax("in + 1 | in in (1)").unwrap(),
+        "in + 1 | in %in% [1]"
+    );
+    assert_eq!(
+        parse_arithmetic::<ColMajor>("in + 1", Tolerance::default())
+            .unwrap()
+            .var_names(),
+        ["in"]
+    );
+    assert!(parse_arithmetic::<ColMajor>("x|site in ('a'", Tolerance::default()).is_err());
+
+    let numbers = (0..10000).map(|i| i as f64 * 0.5).collect::<Vec<_>>();
+    let set = NumberSet::new(&numbers, Tolerance::Absolute(0.01));
+    assert!(set.contains(-0.0));
+    assert!(set.contains(4999.505));
+    assert!(!set.contains(4999.52));
+    assert!(!set.contains(f64::NAN));
+}
+
+#[test]
+fn test_where() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[
+            ("temp", &[90.0, 110.0, 100.0, 130.0]),
+            ("pressure", &[1.0, 2.0, 3.0, 4.0]),
+        ],
+        &[("site", &["a", "b", "a", "c"])],
+    );
+    let eval = |s: &str| {
+        let expr = parse_arithmetic(s, Tolerance::default()).unwrap();
+        eval_arithmetic(&expr, &data, NaAction::Keep).unwrap().0
+    };
+    let to_value = |x: &[f64]| Value::Array(Array2d::from_iter(x.iter(), x.len(), 1).unwrap());
+    assert_eq!(
+        eval("where(temp > 100, temp - 100, 0)"),
+        to_value(&[0.0, 10.0, 0.0, 30.0])
+    );
+    assert_eq!(
+        eval("where(site == 'a', pressure, -pressure) * 2"),
+        to_value(&[2.0, -4.0, 6.0, -8.0])
+    );
+    assert_eq!(
+        eval("where(temp < 100, 1, where(site in ('b', 'c'), pressure, 0))"),
+        to_value(&[1.0, 2.0, 0.0, 4.0])
+    );
+    assert_eq!(
+        eval("where(temp >= 100 && pressure < 4, temp, pressure)"),
+        to_value(&[1.0, 110.0, 100.0, 4.0])
+    );
+    assert_eq!(
+        eval("where(temp < 100, 10, where(temp < 120, 20, 30))"),
+        to_value(&[10.0, 20.0, 20.0, 30.0])
+    );
+    assert_eq!(
+        eval("where(temp > 100, -1, 1)"),
+        to_value(&[1.0, -1.0, 1.0, -1.0])
+    );
+    assert_eq!(
+        eval("where(pressure > -1, abs(-2), -pressure)"),
+        to_value(&[2.0; 4])
+    );
+    assert_eq!(eval("pressure | temp - 200 > -100"), to_value(&[2.0, 4.0]));
+    assert_eq!(eval("pressure | -100 < temp - 200"), to_value(&[2.0, 4.0]));
+    // the condition and the branches are not affected by operators outside of where
+    assert_eq!(
+        eval("where(temp > 100, temp, 0) | temp > 100"),
+        to_value(&[110.0, 130.0])
+    );
+    assert_eq!(
+        eval("where(temp > 100, temp, 0) | pressure > 2"),
+        to_value(&[0.0, 130.0])
+    );
+    assert!(matches!(eval("where(temp, 1, 0)"), Value::Error(_)));
+    assert!(matches!(
+        eval("where(temp > 100, temp | pressure > 2, 0)"),
+        Value::Error(_)
+    ));
+    assert!(
+        parse_arithmetic::<ColMajor>(
+            "temp < 100 ? 10 : temp < 120 ? 20 : 30",
+            Tolerance::default()
+        )
+        .is_err()
+    );
+    assert!(
+        parse_arithmetic::<ColMajor>("temp > 100 ? temp : 0 | temp > 120", Tolerance::default())
+            .is_err()
+    );
+    assert!(
+        parse_arithmetic::<ColMajor>("where(temp > 100, temp ? 1 : 0, 0)", Tolerance::default())
+            .is_err()
+    );
+    assert_eq!(
+        rewrite_syntax("where(site == 'a:b?', {x:y}, 0)").unwrap(),
+        "((site == 'a:b?') ? ({x:y}) : (0))"
+    );
+    assert!(ExprArithmetic::<ColMajor>::parse("(temp > 100) ? (1) : (0)").is_err());
+    assert!(parse_arithmetic::<ColMajor>("where(temp > 100, 1)", Tolerance::default()).is_err());
+    assert!(parse_arithmetic::<ColMajor>("where(temp > 100, 1, 0", Tolerance::default()).is_err());
+    assert_eq!(
+        rewrite_syntax("nowhere + where (x > f(1, 2), 'a, b', {where(})").unwrap(),
+        "nowhere + ((x > f(1, 2)) ? ('a, b') : ({where(}))"
+    );
+}
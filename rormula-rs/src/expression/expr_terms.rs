@@ -0,0 +1,568 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use exmex::{BinOp, Express, FlatEx, MakeOperators, Operator};
+
+use crate::{
+    result::{RoErr, RoResult, to_ro},
+    roerr,
+};
+
+use super::calls::{split_call, wrap_calls};
+
+/// Variable names that cannot be parsed by Exmex directly need to be wrapped in curly braces.
+pub(super) fn unparse_var_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_plain {
+        name.to_string()
+    } else {
+        format!("{{{name}}}")
+    }
+}
+
+/// Variable name of a factor, i.e., the factor without curly braces
+pub(super) fn factor_var_name(factor: &str) -> &str {
+    factor
+        .strip_prefix('{')
+        .and_then(|f| f.strip_suffix('}'))
+        .unwrap_or(factor)
+}
+
+/// A term of a Wilkinson formula is the interaction of its factors, e.g., the term `a:b` has the
+/// factors `a` and `b`. Factors are sub-expressions that can be evaluated by
+/// [`ExprWilkinson`](super::ExprWilkinson).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Term {
+    factors: Vec<String>,
+}
+impl Term {
+    fn from_var_name(name: &str) -> Self {
+        Term {
+            factors: vec![unparse_var_name(name)],
+        }
+    }
+    pub fn factors(&self) -> &[String] {
+        &self.factors
+    }
+    /// Two terms are the same if they consist of the same set of factors, i.e., `a:b` is the
+    /// same as `b:a` and as `a:b:a`.
+    pub fn is_same(&self, other: &Term) -> bool {
+        self.factors.iter().all(|f| other.factors.contains(f))
+            && other.factors.iter().all(|f| self.factors.contains(f))
+    }
+    /// Removes repeated factors, e.g., `a:b:a` becomes `a:b`.
+    fn unique_factors(mut self) -> Term {
+        let mut seen = Vec::with_capacity(self.factors.len());
+        self.factors.retain(|f| {
+            if seen.contains(f) {
+                false
+            } else {
+                seen.push(f.clone());
+                true
+            }
+        });
+        self
+    }
+    /// Interaction of both terms where repeated factors are used once, e.g., `a:b` and `b:c`
+    /// interact to `a:b:c` and `a` with itself is `a`.
+    fn interact(&self, other: &Term) -> Term {
+        let mut factors = self.factors.clone();
+        factors.extend(other.factors.iter().cloned());
+        Term { factors }.unique_factors()
+    }
+    /// Number of factors, e.g., 2 for `a:b`
+    pub fn degree(&self) -> usize {
+        self.factors.len()
+    }
+    pub fn unparse(&self) -> String {
+        self.factors.join(":")
+    }
+}
+/// Formats the term like it is written in formulas, i.e., calls like `I(x^2)` are not wrapped in
+/// curly braces.
+impl Display for Term {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let factors = self
+            .factors
+            .iter()
+            .map(|factor| {
+                let vn = factor_var_name(factor);
+                if split_call(vn).is_some() {
+                    vn
+                } else {
+                    factor.as_str()
+                }
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", factors.join(":"))
+    }
+}
+
+/// The terms of an expanded Wilkinson formula, e.g., `a*b - a:b - 1` expands to `a + b`
+/// without intercept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Terms {
+    terms: Vec<Term>,
+    /// `None` if the intercept has not been mentioned explicitly via `+1`, `+0`, or `-1`
+    intercept: Option<bool>,
+}
+impl Terms {
+    fn from_intercept(intercept: bool) -> Self {
+        Terms {
+            terms: vec![],
+            intercept: Some(intercept),
+        }
+    }
+    /// Sets the intercept explicitly
+    pub(super) fn with_intercept(&self, intercept: bool) -> Self {
+        Terms {
+            terms: self.terms.clone(),
+            intercept: Some(intercept),
+        }
+    }
+    /// Replaces the terms and keeps the intercept
+    pub(super) fn with_terms(&self, terms: Vec<Term>) -> Self {
+        Terms {
+            terms,
+            intercept: self.intercept,
+        }
+    }
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+    /// The intercept is part of a model unless it has been removed explicitly.
+    pub fn has_intercept(&self) -> bool {
+        self.intercept.unwrap_or(true)
+    }
+    /// Removes all but the first occurrence of each term. Terms are the same if they consist of
+    /// the same factors, see [`Term::is_same`](Term::is_same).
+    pub fn deduplicated(mut self) -> Self {
+        let mut unique: Vec<Term> = Vec::with_capacity(self.terms.len());
+        for term in self.terms {
+            if !unique.iter().any(|u| u.is_same(&term)) {
+                unique.push(term);
+            }
+        }
+        self.terms = unique;
+        self
+    }
+    /// Formula without intercept that consists only of `+` and `:`, e.g., `a + b + a:b`.
+    pub fn unparse(&self) -> String {
+        self.terms
+            .iter()
+            .map(|t| t.unparse())
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TermsValue {
+    Terms(Terms),
+    Scalar(f64),
+    /// String is the error message
+    Error(String),
+}
+impl TermsValue {
+    pub fn from_var_name(name: &str) -> Self {
+        Self::Terms(Terms {
+            terms: vec![Term::from_var_name(name)],
+            intercept: None,
+        })
+    }
+}
+impl Default for TermsValue {
+    fn default() -> Self {
+        Self::Error("default".to_string())
+    }
+}
+impl FromStr for TermsValue {
+    type Err = RoErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(TermsValue::Scalar(
+            s.parse::<f64>()
+                .map_err(|_| roerr!("could not parse {}", s))?,
+        ))
+    }
+}
+
+/// Within sums and differences, the scalars `0` and `1` refer to the intercept.
+fn to_terms(v: TermsValue) -> Result<Terms, String> {
+    match v {
+        TermsValue::Terms(t) => Ok(t),
+        TermsValue::Scalar(0.0) => Ok(Terms::from_intercept(false)),
+        TermsValue::Scalar(1.0) => Ok(Terms::from_intercept(true)),
+        TermsValue::Scalar(s) => Err(format!("{s} is neither a term nor an intercept")),
+        TermsValue::Error(e) => Err(e),
+    }
+}
+
+fn apply_terms_op(
+    a: TermsValue,
+    b: TermsValue,
+    op: fn(Terms, Terms) -> Result<Terms, String>,
+) -> TermsValue {
+    match (to_terms(a), to_terms(b)) {
+        (Ok(a), Ok(b)) => match op(a, b) {
+            Ok(t) => TermsValue::Terms(t),
+            Err(e) => TermsValue::Error(e),
+        },
+        (Err(e), _) => TermsValue::Error(e),
+        (_, Err(e)) => TermsValue::Error(e),
+    }
+}
+
+fn op_terms_plus(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |mut a, mut b| {
+        a.terms.append(&mut b.terms);
+        Ok(Terms {
+            terms: a.terms,
+            intercept: b.intercept.or(a.intercept),
+        })
+    })
+}
+
+/// Removes all terms of `b` from `a`. Removing the intercept `1` switches the intercept off,
+/// removing `0` switches it back on.
+fn op_terms_minus(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |mut a, b| {
+        a.terms
+            .retain(|ta| !b.terms.iter().any(|tb| ta.is_same(tb)));
+        Ok(Terms {
+            terms: a.terms,
+            intercept: b.intercept.map(|i| !i).or(a.intercept),
+        })
+    })
+}
+
+fn op_terms_unary_minus(a: TermsValue) -> TermsValue {
+    op_terms_minus(TermsValue::Terms(Terms::default()), a)
+}
+
+fn interact(a: &Terms, b: &Terms) -> Result<Vec<Term>, String> {
+    if a.intercept.is_some() || b.intercept.is_some() {
+        Err("the intercept cannot be part of an interaction".to_string())
+    } else {
+        Ok(b.terms
+            .iter()
+            .flat_map(|tb| a.terms.iter().map(move |ta| ta.interact(tb)))
+            .collect())
+    }
+}
+
+fn op_terms_colon(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |a, b| {
+        Ok(Terms {
+            terms: interact(&a, &b)?,
+            intercept: None,
+        })
+    })
+}
+
+fn op_terms_cross(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |mut a, mut b| {
+        let mut interaction = interact(&a, &b)?;
+        a.terms.append(&mut b.terms);
+        a.terms.append(&mut interaction);
+        Ok(Terms {
+            terms: a.terms,
+            intercept: None,
+        })
+    })
+}
+
+/// All factors of all terms as single term, e.g., `a + b:c` becomes `a:b:c`
+fn all_factors(a: &Terms) -> Result<Term, String> {
+    if a.intercept.is_some() {
+        Err("the intercept cannot be part of a nesting".to_string())
+    } else {
+        Ok(a.terms.iter().fold(Term::default(), |f, t| f.interact(t)))
+    }
+}
+
+fn nest(outer: &Terms, inner: &Terms) -> Result<Vec<Term>, String> {
+    if inner.intercept.is_some() {
+        return Err("the intercept cannot be part of a nesting".to_string());
+    }
+    let outer = all_factors(outer)?;
+    Ok(inner.terms.iter().map(|t| outer.interact(t)).collect())
+}
+
+/// Nesting `a/b` expands to `a + a:b`, e.g., `supplier/batch` for batches nested in suppliers.
+fn op_terms_nest(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |mut a, b| {
+        let mut nested = nest(&a, &b)?;
+        a.terms.append(&mut nested);
+        Ok(Terms {
+            terms: a.terms,
+            intercept: None,
+        })
+    })
+}
+
+/// `b %in% a` expands to `a:b`, such that `a + b %in% a` is the same as `a/b`.
+fn op_terms_in(a: TermsValue, b: TermsValue) -> TermsValue {
+    apply_terms_op(a, b, |a, b| {
+        Ok(Terms {
+            terms: nest(&b, &a)?,
+            intercept: None,
+        })
+    })
+}
+
+/// Interaction power, e.g., `(a + b + c)^2` expands to all main effects and all two-way
+/// interactions `a + b + c + a:b + a:c + b:c`.
+fn op_terms_power(a: TermsValue, b: TermsValue) -> TermsValue {
+    match (a, b) {
+        (TermsValue::Terms(a), TermsValue::Scalar(sc))
+            if a.intercept.is_none() && sc >= 1.0 && sc.fract() == 0.0 =>
+        {
+            let mut base = Vec::<Term>::new();
+            for t in a.terms {
+                let t = t.unique_factors();
+                if !base.iter().any(|bt| bt.is_same(&t)) {
+                    base.push(t);
+                }
+            }
+            let mut terms = base.clone();
+            // combinations of previous order as indices into the base terms
+            let mut combinations = (0..base.len()).map(|i| vec![i]).collect::<Vec<_>>();
+            for _ in 1..(sc as usize).min(base.len()) {
+                combinations = combinations
+                    .into_iter()
+                    .flat_map(|comb| {
+                        let last = *comb.last().unwrap();
+                        (last + 1..base.len()).map(move |i| {
+                            let mut comb = comb.clone();
+                            comb.push(i);
+                            comb
+                        })
+                    })
+                    .collect();
+                for comb in &combinations {
+                    let interaction = comb
+                        .iter()
+                        .skip(1)
+                        .fold(base[comb[0]].clone(), |t, i| t.interact(&base[*i]));
+                    if !terms.iter().any(|t| t.is_same(&interaction)) {
+                        terms.push(interaction);
+                    }
+                }
+            }
+            TermsValue::Terms(Terms {
+                terms,
+                intercept: None,
+            })
+        }
+        (TermsValue::Error(e), _) | (_, TermsValue::Error(e)) => TermsValue::Error(e),
+        (TermsValue::Terms(_), TermsValue::Scalar(sc)) => TermsValue::Error(format!(
+            "the interaction power needs a positive integer exponent, got {sc}, use ^^ for elementwise powers"
+        )),
+        _ => TermsValue::Error("power can only be applied to terms and scalar".to_string()),
+    }
+}
+
+/// Elementwise power, each term becomes a single factor, e.g., `(a + b:c)^^2` is expanded to
+/// `a^^2 + (b:c)^^2`.
+fn op_terms_elementwise_power(a: TermsValue, b: TermsValue) -> TermsValue {
+    match (a, b) {
+        (TermsValue::Terms(a), TermsValue::Scalar(sc)) if a.intercept.is_none() => {
+            let terms = a
+                .terms
+                .into_iter()
+                .map(|t| {
+                    let base = if t.factors.len() > 1 {
+                        format!("({})", t.unparse())
+                    } else {
+                        t.unparse()
+                    };
+                    Term {
+                        factors: vec![format!("{base}^^{sc}")],
+                    }
+                })
+                .collect();
+            TermsValue::Terms(Terms {
+                terms,
+                intercept: None,
+            })
+        }
+        (TermsValue::Error(e), _) | (_, TermsValue::Error(e)) => TermsValue::Error(e),
+        _ => TermsValue::Error("power can only be applied to terms and scalar".to_string()),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TermsOpsFactory;
+impl MakeOperators<TermsValue> for TermsOpsFactory {
+    fn make<'a>() -> Vec<Operator<'a, TermsValue>> {
+        vec![
+            Operator::make_bin(
+                "^^",
+                BinOp {
+                    apply: op_terms_elementwise_power,
+                    prio: 4,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                "^",
+                BinOp {
+                    apply: op_terms_power,
+                    prio: 4,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                ":",
+                BinOp {
+                    apply: op_terms_colon,
+                    prio: 3,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                "%in%",
+                BinOp {
+                    apply: op_terms_in,
+                    prio: 2,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                "*",
+                BinOp {
+                    apply: op_terms_cross,
+                    prio: 1,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                "/",
+                BinOp {
+                    apply: op_terms_nest,
+                    prio: 1,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin(
+                "+",
+                BinOp {
+                    apply: op_terms_plus,
+                    prio: 0,
+                    is_commutative: false,
+                },
+            ),
+            Operator::make_bin_unary(
+                "-",
+                BinOp {
+                    apply: op_terms_minus,
+                    prio: 0,
+                    is_commutative: false,
+                },
+                op_terms_unary_minus,
+            ),
+        ]
+    }
+}
+
+pub type ExprTerms = FlatEx<TermsValue, TermsOpsFactory>;
+
+/// Expands a Wilkinson formula into its terms. Subtraction works on the set of terms, e.g.,
+/// `a*b - a:b` expands to `a + b`, and `-1` or `0 +` remove the intercept. Repeated terms are kept
+/// literally, e.g., `a + b + a` expands to `a + b + a`.
+pub fn expand_terms_literal(formula: &str) -> RoResult<Terms> {
+    let expr = ExprTerms::parse(&wrap_calls(formula)?).map_err(to_ro)?;
+    let vars = expr
+        .var_names()
+        .iter()
+        .map(|vn| TermsValue::from_var_name(vn))
+        .collect::<Vec<_>>();
+    to_terms(expr.eval_vec(vars).map_err(to_ro)?).map_err(|e| RoErr::new(&e))
+}
+
+/// Expands a Wilkinson formula into its set of terms, i.e., like
+/// [`expand_terms_literal`](expand_terms_literal) but only the first occurrence of repeated
+/// terms is kept, e.g., `a:b + b + b:a` expands to `a:b + b`.
+pub fn expand_terms(formula: &str) -> RoResult<Terms> {
+    expand_terms_literal(formula).map(Terms::deduplicated)
+}
+
+#[cfg(test)]
+fn assert_expansion(formula: &str, expected: &str, has_intercept: bool) {
+    let terms = expand_terms(formula).unwrap();
+    println!("{formula} -> {terms:?}");
+    assert_eq!(terms.unparse(), expected);
+    assert_eq!(terms.has_intercept(), has_intercept);
+}
+
+#[test]
+fn test_expand() {
+    assert_expansion("a+b+a:b", "a + b + a:b", true);
+    assert_expansion("(a+b+c)*d", "a + b + c + d + a:d + b:d + c:d", true);
+    assert_expansion("a*b - a:b", "a + b", true);
+    assert_expansion("a*b - b:a", "a + b", true);
+    assert_expansion("a + b - 1", "a + b", false);
+    assert_expansion("-1 + a + b", "a + b", false);
+    assert_expansion("0 + a + b", "a + b", false);
+    assert_expansion("a + b + 0", "a + b", false);
+    assert_expansion("a + b - 1 + 1", "a + b", true);
+    assert_expansion("a*b*c - a:b:c - 1", "a + b + a:b + c + a:c + b:c", false);
+    assert_expansion("(a + b):c - b:c", "a:c", true);
+    assert_expansion("a + {second.var}", "a + {second.var}", true);
+    assert_expansion("a^^2 + (b:c)^^3", "a^^2 + (b:c)^^3", true);
+    assert_expansion("(a+b+c)^2", "a + b + c + a:b + a:c + b:c", true);
+    assert_expansion("(a+b+c)^3", "a + b + c + a:b + a:c + b:c + a:b:c", true);
+    assert_expansion("(a+b+c)^5", "a + b + c + a:b + a:c + b:c + a:b:c", true);
+    assert_expansion("(a+b)^1", "a + b", true);
+    assert_expansion("(a+b+a)^2", "a + b + a:b", true);
+    assert_expansion("(a+b:c)^2", "a + b:c + a:b:c", true);
+    assert_expansion("(a+b+c)^2 - a:c", "a + b + c + a:b + b:c", true);
+    assert_expansion("a^2", "a", true);
+    assert_expansion("supplier/batch", "supplier + supplier:batch", true);
+    assert_expansion(
+        "supplier + batch %in% supplier",
+        "supplier + supplier:batch",
+        true,
+    );
+    assert_expansion("(a+b)/c", "a + b + a:b:c", true);
+    assert_expansion("a/(b+c)", "a + a:b + a:c", true);
+    assert_expansion("a/b/c", "a + a:b + a:b:c", true);
+    assert_expansion("a:b %in% c", "c:a:b", true);
+    assert_expansion("x + a/b - 1", "x + a + a:b", false);
+    assert!(expand_terms("a/1").is_err());
+    assert!(expand_terms("(a+b)^1.5").is_err());
+    assert!(expand_terms("(a+b)^0").is_err());
+    assert_expansion("1", "", true);
+    assert_expansion("0", "", false);
+    assert!(expand_terms("a + 2").is_err());
+    assert!(expand_terms("a:1").is_err());
+    assert_expansion("n + o + n", "n + o", true);
+    assert_expansion("a:b + b + b:a", "a:b + b", true);
+    assert_expansion("a*b + a + b:a", "a + b + a:b", true);
+    assert_expansion("a/b + a:b", "a + a:b", true);
+    assert_expansion("a:a", "a", true);
+    assert_expansion("(a+b):(a+b)", "a + b:a + b", true);
+    assert_expansion("(a+b)*(a+c)", "a + b + c + b:a + a:c + b:c", true);
+    assert_expansion("a/a", "a", true);
+    assert_expansion("a:b + a:b:a", "a:b", true);
+    let term = |factors: &[&str]| Term {
+        factors: factors.iter().map(|f| f.to_string()).collect(),
+    };
+    assert!(term(&["a", "b"]).is_same(&term(&["b", "a", "b"])));
+    assert!(!term(&["a", "b"]).is_same(&term(&["a", "b", "c"])));
+    assert!(!term(&["a", "a"]).is_same(&term(&["a", "b"])));
+    assert_eq!(
+        expand_terms_literal("n + o + n").unwrap().unparse(),
+        "n + o + n"
+    );
+    assert_eq!(
+        expand_terms_literal("a:b + b:a").unwrap().unparse(),
+        "a:b + b:a"
+    );
+}
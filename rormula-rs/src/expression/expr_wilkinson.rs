@@ -1,80 +1,272 @@
 use std::fmt::Debug;
 
-use exmex::{BinOp, FlatEx, MakeOperators, Operator, ops_factory};
+use exmex::{BinOp, FlatEx, MakeOperators, Operator};
 
 use crate::array::{Array2d, DefaultOrder, MemOrder};
-use crate::expression::{ops_common, value::Value};
 use crate::result::RoResult;
 use crate::timing;
 
-use super::value::NameValue;
+use super::{
+    Terms,
+    expr_terms::factor_var_name,
+    value::{Columns, WilkinsonValue},
+};
 
-fn cat_to_dummy_name(c: NameValue) -> RoResult<NameValue> {
-    if let NameValue::Cats((feature_name, cats)) = c {
-        let (unique, _) = ops_common::unique_cats(&cats)?;
-        let names = unique
-            .into_iter()
-            .map(|cat| format!("{feature_name}_{cat}"))
-            .collect::<Vec<_>>();
-        Ok(NameValue::Array(names))
-    } else {
-        Ok(c)
+/// Factor of a term after deciding on the rank of the categoricals, see [`code_categoricals`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CodedFactor<'a> {
+    pub factor: &'a str,
+    /// Categoricals with full rank are encoded with one column per level
+    pub full_rank: bool,
+}
+
+/// Factors of a term after deciding on the rank of the categoricals
+pub(crate) type CodedTerm<'a> = Vec<CodedFactor<'a>>;
+
+/// Categorical factors of a subterm together with the flag whether they have full rank
+type Subterm<'a> = Vec<(&'a str, bool)>;
+
+/// Replaces the first subterm that is contained in a subterm with one more factor. The
+/// remaining factor of the larger subterm gets full rank, e.g., `a` and `a:b` become `a:b` where
+/// `b` has full rank.
+/// Returns false if there is nothing to simplify.
+fn simplify_one_subterm(subterms: &mut Vec<Subterm>) -> bool {
+    for short_idx in 0..subterms.len() {
+        for long_idx in short_idx + 1..subterms.len() {
+            let (short, long) = (&subterms[short_idx], &subterms[long_idx]);
+            if long.len() == short.len() + 1 && short.iter().all(|f| long.contains(f)) {
+                let absorbed = long
+                    .iter()
+                    .map(|f| if short.contains(f) { *f } else { (f.0, true) })
+                    .collect();
+                subterms[long_idx] = absorbed;
+                subterms.remove(short_idx);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// All subsets sorted by their size, e.g., `[], [a], [b], [a, b]`
+fn subsets_sorted<'a>(factors: &[&'a str]) -> Vec<Vec<&'a str>> {
+    let mut subsets = (0..1usize << factors.len())
+        .map(|mask| {
+            (0..factors.len())
+                .filter(|i| mask & (1 << i) != 0)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    subsets.sort_by(|s1, s2| s1.len().cmp(&s2.len()).then(s1.cmp(s2)));
+    subsets
+        .into_iter()
+        .map(|s| s.into_iter().map(|i| factors[i]).collect())
+        .collect()
+}
+
+/// Decides like Patsy which categorical factors are encoded with full rank. A categorical factor
+/// gets full rank if no lower-order term with the same numerical factors spans its space already.
+/// Thereby, a term might be split into several subterms, e.g., `a:b` with intercept becomes
+/// `b + a:b` where `b` has full rank in the interaction for categorical `a` and `b`.
+pub(crate) fn code_categoricals(
+    terms: &Terms,
+    is_categorical: impl Fn(&str) -> bool,
+) -> Vec<CodedTerm<'_>> {
+    code_categoricals_by_term(terms, is_categorical)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Like [`code_categoricals`] but returns the subterms of each term separately
+pub(crate) fn code_categoricals_by_term(
+    terms: &Terms,
+    is_categorical: impl Fn(&str) -> bool,
+) -> Vec<Vec<CodedTerm<'_>>> {
+    // lower-order terms need to be processed first
+    let mut order = (0..terms.terms().len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| terms.terms()[*i].factors().len());
+
+    // used subterms for each set of numerical factors, the intercept is the empty subterm
+    let mut used: Vec<(Vec<&str>, Vec<Vec<&str>>)> = vec![];
+    if terms.has_intercept() {
+        used.push((vec![], vec![vec![]]));
+    }
+    let mut coded = vec![vec![]; terms.terms().len()];
+    for term_idx in order {
+        let term = &terms.terms()[term_idx];
+        let (mut numerical, categorical): (Vec<&str>, Vec<&str>) = term
+            .factors()
+            .iter()
+            .map(|f| factor_var_name(f))
+            .partition(|vn| !is_categorical(vn));
+        numerical.sort();
+        let bucket_idx = used
+            .iter()
+            .position(|(num, _)| *num == numerical)
+            .unwrap_or_else(|| {
+                used.push((numerical, vec![]));
+                used.len() - 1
+            });
+        let used_subterms = &mut used[bucket_idx].1;
+        let mut subterms = vec![];
+        for subset in subsets_sorted(&categorical) {
+            let mut key = subset.clone();
+            key.sort();
+            if !used_subterms.contains(&key) {
+                used_subterms.push(key);
+                subterms.push(subset.into_iter().map(|f| (f, false)).collect());
+            }
+        }
+        while simplify_one_subterm(&mut subterms) {}
+        coded[term_idx] = if categorical.is_empty() || subterms.is_empty() {
+            vec![
+                term.factors()
+                    .iter()
+                    .map(|f| CodedFactor {
+                        factor: f,
+                        full_rank: false,
+                    })
+                    .collect(),
+            ]
+        } else {
+            subterms
+                .iter()
+                .map(|subterm| {
+                    term.factors()
+                        .iter()
+                        .filter_map(|f| {
+                            let vn = factor_var_name(f);
+                            if !is_categorical(vn) {
+                                Some(CodedFactor {
+                                    factor: f,
+                                    full_rank: false,
+                                })
+                            } else {
+                                subterm.iter().find(|(sf, _)| *sf == vn).map(|(_, full)| {
+                                    CodedFactor {
+                                        factor: f,
+                                        full_rank: *full,
+                                    }
+                                })
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        };
     }
+    coded
 }
 
+/// Names of the interaction columns where the columns of `a` vary fastest
+pub(crate) fn interaction_names(a: &[String], b: &[String]) -> Vec<String> {
+    b.iter()
+        .flat_map(|b| a.iter().map(move |a| format!("{a}:{b}")))
+        .collect()
+}
+
+/// Applies an operator to shape, names, and data of both operands. Names and data are only
+/// computed if both operands have them.
 fn apply_op<M: MemOrder>(
-    a: Value<M>,
-    b: Value<M>,
-    op: &impl Fn(Array2d<M>, Array2d<M>) -> RoResult<Array2d<M>>,
-) -> Value<M> {
-    let a = match ops_common::cat_to_dummy(a) {
-        Ok(arr) => arr,
-        Err(e) => Value::Error(e.msg().to_string()),
-    };
-    let b = match ops_common::cat_to_dummy(b) {
-        Ok(arr) => arr,
-        Err(e) => Value::Error(e.msg().to_string()),
-    };
+    a: WilkinsonValue<M>,
+    b: WilkinsonValue<M>,
+    n_cols: fn(usize, usize) -> usize,
+    names: fn(&[String], &[String]) -> Vec<String>,
+    data: &impl Fn(Array2d<M>, Array2d<M>) -> RoResult<Array2d<M>>,
+) -> WilkinsonValue<M> {
     match (a, b) {
-        (Value::Array(a), Value::Array(b)) => {
-            let new_val = op(a, b);
-            match new_val {
-                Ok(a) => Value::Array(a),
-                Err(e) => Value::Error(e.to_string()),
-            }
+        (WilkinsonValue::Columns(a), WilkinsonValue::Columns(b)) => {
+            let data = match (a.data, b.data) {
+                (Some(a), Some(b)) => match data(a, b) {
+                    Ok(d) => Some(d),
+                    Err(e) => return WilkinsonValue::Error(e.msg().to_string()),
+                },
+                _ => None,
+            };
+            let names = match (a.names, b.names) {
+                (Some(a), Some(b)) => Some(names(&a, &b)),
+                _ => None,
+            };
+            WilkinsonValue::Columns(Columns {
+                n_cols: n_cols(a.n_cols, b.n_cols),
+                names,
+                data,
+            })
         }
-        (Value::Error(e), _) => Value::Error(e),
-        (_, Value::Error(e)) => Value::Error(e),
-        _ => Value::Error("some error during operation".to_string()),
+        (WilkinsonValue::Error(e), _) => WilkinsonValue::Error(e),
+        (_, WilkinsonValue::Error(e)) => WilkinsonValue::Error(e),
+        _ => WilkinsonValue::Error("operators need columns as operands".to_string()),
     }
 }
 
-pub fn op_concat<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
-    timing!(apply_op(a, b, &|a, b| a.concatenate_cols(b)), "plus op")
+pub fn op_concat<M: MemOrder>(a: WilkinsonValue<M>, b: WilkinsonValue<M>) -> WilkinsonValue<M> {
+    timing!(
+        apply_op(a, b, |a, b| a + b, |a, b| [a, b].concat(), &|a, b| {
+            a.concatenate_cols(b)
+        }),
+        "plus op"
+    )
 }
 
-pub fn op_multiply<M: MemOrder>(a: Value<M>, b: Value<M>) -> Value<M> {
+pub fn op_multiply<M: MemOrder>(a: WilkinsonValue<M>, b: WilkinsonValue<M>) -> WilkinsonValue<M> {
     timing!(
-        apply_op(a, b, &|a: Array2d<M>, b: Array2d<M>| {
+        apply_op(a, b, |a, b| a * b, interaction_names, &|a, b| {
             a.componentwise(b, &|x, y| x * y)
         }),
         "multiply op"
     )
 }
 
+/// Crossing `a*b` expands to the main effects and their interaction, i.e., `a + b + a:b`.
+pub fn op_cross<M: MemOrder>(a: WilkinsonValue<M>, b: WilkinsonValue<M>) -> WilkinsonValue<M> {
+    timing!(
+        {
+            let interaction = op_multiply(a.clone(), b.clone());
+            op_concat(op_concat(a, b), interaction)
+        },
+        "cross op"
+    )
+}
+
+/// Elementwise power `a^^2`
+pub fn op_power<M: MemOrder>(a: WilkinsonValue<M>, b: WilkinsonValue<M>) -> WilkinsonValue<M> {
+    match (a, b) {
+        (WilkinsonValue::Columns(a), WilkinsonValue::Scalar(exponent)) => {
+            WilkinsonValue::Columns(Columns {
+                n_cols: a.n_cols,
+                names: a.names.map(|names| {
+                    names
+                        .into_iter()
+                        .map(|n| format!("{n}^^{exponent}"))
+                        .collect()
+                }),
+                data: a.data.map(|mut data| {
+                    data.elt_mutate(&|x| x.powf(exponent));
+                    data
+                }),
+            })
+        }
+        (WilkinsonValue::Error(e), _) => WilkinsonValue::Error(e),
+        _ => WilkinsonValue::Error("power can only be applied to columns and scalar".to_string()),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct WilkinsonOpsFactory;
-impl<M> MakeOperators<Value<M>> for WilkinsonOpsFactory
+impl<M> MakeOperators<WilkinsonValue<M>> for WilkinsonOpsFactory
 where
     M: MemOrder + Clone,
 {
-    fn make<'b>() -> Vec<Operator<'b, Value<M>>> {
+    fn make<'b>() -> Vec<Operator<'b, WilkinsonValue<M>>> {
+        // The interaction power `^` needs to be expanded beforehand, see
+        // [`expand_terms`](super::expand_terms). Here, we only have the elementwise power `^^`.
         vec![
             Operator::make_bin(
-                "^",
+                "^^",
                 BinOp {
-                    apply: ops_common::op_power,
-                    prio: 2,
+                    apply: op_power,
+                    prio: 3,
                     is_commutative: false,
                 },
             ),
@@ -82,122 +274,14 @@ where
                 ":",
                 BinOp {
                     apply: op_multiply,
-                    prio: 1,
-                    is_commutative: false,
-                },
-            ),
-            Operator::make_bin(
-                "+",
-                BinOp {
-                    apply: op_concat,
-                    prio: 0,
-                    is_commutative: false,
-                },
-            ),
-        ]
-    }
-}
-
-pub type ExprWilkinson = FlatEx<Value<DefaultOrder>, WilkinsonOpsFactory>;
-
-fn apply_name_op(
-    a: NameValue,
-    b: NameValue,
-    op: fn(Vec<String>, Vec<String>) -> Vec<String>,
-) -> NameValue {
-    let a = match cat_to_dummy_name(a) {
-        Ok(arr) => arr,
-        Err(e) => NameValue::Error(e.msg().to_string()),
-    };
-    let b = match cat_to_dummy_name(b) {
-        Ok(arr) => arr,
-        Err(e) => NameValue::Error(e.msg().to_string()),
-    };
-    match (a, b) {
-        (NameValue::Array(a), NameValue::Array(b)) => {
-            let new_val = op(a, b);
-            NameValue::Array(new_val)
-        }
-        (NameValue::Error(e), _) => NameValue::Error(e),
-        (_, NameValue::Error(e)) => NameValue::Error(e),
-        _ => NameValue::Error("some error during operation".to_string()),
-    }
-}
-
-fn op_name_plus(a: NameValue, b: NameValue) -> NameValue {
-    apply_name_op(a, b, |mut a, mut b| {
-        a.append(&mut b);
-        a
-    })
-}
-
-fn op_name_colon(a: NameValue, b: NameValue) -> NameValue {
-    apply_name_op(a, b, |a, b| {
-        b.into_iter()
-            .flat_map(move |b_| a.clone().into_iter().map(move |a_| format!("{a_}:{b_}")))
-            .collect::<Vec<String>>()
-    })
-}
-fn op_name_power(a: NameValue, b: NameValue) -> NameValue {
-    match (a, b) {
-        (NameValue::Array(old_names), NameValue::Scalar(sc)) => {
-            let new_names = old_names
-                .into_iter()
-                .map(|on| format!("{on}^{sc}"))
-                .collect();
-            NameValue::Array(new_names)
-        }
-        _ => NameValue::Error("power can only be applied to matrix and skalar".to_string()),
-    }
-}
-
-ops_factory!(
-    NameOps,
-    NameValue,
-    Operator::make_bin(
-        "+",
-        BinOp {
-            apply: op_name_plus,
-            prio: 0,
-            is_commutative: false,
-        },
-    ),
-    Operator::make_bin(
-        ":",
-        BinOp {
-            apply: op_name_colon,
-            prio: 1,
-            is_commutative: false
-        }
-    ),
-    Operator::make_bin(
-        "^",
-        BinOp {
-            apply: op_name_power,
-            prio: 2,
-            is_commutative: false
-        }
-    )
-);
-pub type ExprNames = FlatEx<NameValue, NameOps>;
-#[derive(Clone, Debug)]
-pub struct ColCountOps;
-
-impl MakeOperators<usize> for ColCountOps {
-    fn make<'a>() -> Vec<Operator<'a, usize>> {
-        vec![
-            Operator::make_bin(
-                "^",
-                BinOp {
-                    apply: |a, _| a,
                     prio: 2,
                     is_commutative: false,
                 },
             ),
             Operator::make_bin(
-                ":",
+                "*",
                 BinOp {
-                    apply: |a, b| a * b,
+                    apply: op_cross,
                     prio: 1,
                     is_commutative: false,
                 },
@@ -205,7 +289,7 @@ impl MakeOperators<usize> for ColCountOps {
             Operator::make_bin(
                 "+",
                 BinOp {
-                    apply: |a, b| a + b,
+                    apply: op_concat,
                     prio: 0,
                     is_commutative: false,
                 },
@@ -214,30 +298,9 @@ impl MakeOperators<usize> for ColCountOps {
     }
 }
 
-pub type ExprColCount = FlatEx<usize, ColCountOps>;
-
-#[cfg(test)]
-fn get_cat_fortest<M: MemOrder + Clone>(n_rows: Option<usize>) -> (Vec<String>, String, Value<M>) {
-    let feature_name = "animal".to_string();
-    let cats_all = ["zebra", "alpaca", "boa", "dragon", "zebra", "dragon"];
-    let cats = if let Some(n_rows) = n_rows {
-        &["zebra", "alpaca", "boa", "dragon", "zebra", "dragon"][0..n_rows]
-    } else {
-        &cats_all
-    };
-    let mut cats = cats.iter().map(|c| c.to_string()).collect::<Vec<_>>();
-    let val = Value::Cats(cats.clone());
-    cats.sort();
-    cats.dedup();
-    cats.pop();
-    (
-        cats.iter()
-            .map(|c| format!("{feature_name}_{c}"))
-            .collect::<Vec<_>>(),
-        feature_name,
-        val,
-    )
-}
+/// Evaluates an expanded formula to the columns of the model matrix, their names, and their
+/// number in one pass
+pub type ExprWilkinson = FlatEx<WilkinsonValue<DefaultOrder>, WilkinsonOpsFactory>;
 
 #[cfg(test)]
 fn names_equal(names: &[String], expected: &[&str]) {
@@ -260,28 +323,46 @@ fn array_almost_equal(a1: Array2d<ColMajor>, a2: Array2d<ColMajor>) {
 }
 
 #[test]
-fn test_cat_to_dummy() {
-    let (reference_names, feature_name, cats) = get_cat_fortest::<ColMajor>(None);
-    let cat_name = NameValue::cats_from_value(feature_name, cats.clone()).unwrap();
-    if let NameValue::Array(names) = cat_to_dummy_name(cat_name).unwrap() {
-        println!("{names:?}");
-        println!("{reference_names:?}");
-        assert_eq!(names, reference_names);
-    } else {
-        panic!("could not get names");
-    }
-    if let Value::Array(arr) = ops_common::cat_to_dummy(cats.clone()).unwrap() {
-        println!("{arr:?}");
-        assert_eq!(arr.n_cols(), 3);
-        assert_eq!(arr.get(0, 0), 0.0);
-        assert_eq!(arr.get(1, 0), 1.0);
-        assert_eq!(arr.get(2, 1), 1.0);
-        assert_eq!(arr.get(3, 2), 1.0);
-        assert_eq!(arr.get(4, 2), 0.0);
-        assert_eq!(arr.get(5, 2), 1.0);
-    } else {
-        assert!(false);
-    }
+fn test_code_categoricals() {
+    use super::expand_terms;
+    // factors with full rank are marked by [full]
+    let assert_coding = |formula: &str, expected: &str| {
+        let terms = expand_terms(formula).unwrap();
+        let coded = code_categoricals(&terms, |vn| ["a", "b", "c"].contains(&vn))
+            .iter()
+            .map(|term| {
+                term.iter()
+                    .map(|f| match f.full_rank {
+                        true => format!("{}[full]", f.factor),
+                        false => f.factor.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(":")
+            })
+            .collect::<Vec<_>>()
+            .join(" + ");
+        assert_eq!(coded, expected);
+    };
+    // numerical factors are not touched
+    assert_coding("x + y + x:y", "x + y + x:y");
+    assert_coding("x + y - 1", "x + y");
+    // the intercept spans the space of the dropped level
+    assert_coding("a", "a");
+    assert_coding("0 + a", "a[full]");
+    assert_coding("a + b - 1", "a[full] + b");
+    // interactions without main effects
+    assert_coding("a:b", "b + a:b[full]");
+    assert_coding("0 + a:b", "a[full]:b[full]");
+    assert_coding("a*b", "a + b + a:b");
+    assert_coding("a/b", "a + a[full]:b");
+    // lower-order terms are coded first but the order of the terms is kept
+    assert_coding("a:b + a + b", "a:b + a + b");
+    assert_coding("a:b:c + a:b", "a[full]:b[full]:c + b + a:b[full]");
+    // numerical factors span a space of their own
+    assert_coding("x:a", "x:a[full]");
+    assert_coding("x + x:a", "x + x:a");
+    assert_coding("x + a + x:a", "x + a + x:a");
+    assert_coding("a + x:a:b", "a + x:a[full]:b[full]");
 }
 
 #[rustfmt::skip]
@@ -296,85 +377,54 @@ fn test_ops() {
     b.set(0, 0, 0.5);
     b.set(1, 0, 1.0);
     println!("{b:?}");
-    let na = NameValue::Array(vec!["a1".to_string(),"a2".to_string(),"a3".to_string()]);
-    let nb = NameValue::Array(vec!["b".to_string()]);
-    let a = Value::Array(a);
-    let b = Value::Array(b);
+    let to_names = |names: &[&str]| Some(names.iter().map(|n| n.to_string()).collect());
+    let a = WilkinsonValue::Columns(Columns::new(to_names(&["a1", "a2", "a3"]), a));
+    let b = WilkinsonValue::Columns(Columns::new(to_names(&["b"]), b));
+    let assert_columns = |value: WilkinsonValue<ColMajor>, names: &[&str], expected: Array2d<ColMajor>| {
+        if let WilkinsonValue::Columns(Columns { n_cols, names: Some(n), data: Some(data) }) = value {
+            println!("{n:?}");
+            names_equal(&n, names);
+            assert_eq!(n_cols, names.len());
+            array_almost_equal(data, expected);
+        } else {
+            panic!("need columns with names and data as result");
+        }
+    };
 
     // colon
     let expected = Array2d::from_iter([
         0.0, 0.25, 0.5, 
         2.0, 0.0, 0.0
     ].iter(), 2, 3).unwrap();
-    if let NameValue::Array(n) = op_name_colon(na.clone(), nb.clone()) {
-        println!("{n:?}");
-        names_equal(&n, &["a1:b", "a2:b", "a3:b"]);
-    } else {
-        panic!("couldn't get names for colon op")
-    }
-    if let Value::Array(colon) = op_multiply(a.clone(), b.clone()) {
-        array_almost_equal(colon, expected); 
-    } else {
-        assert!(false);
-    }
+    assert_columns(op_multiply(a.clone(), b.clone()), &["a1:b", "a2:b", "a3:b"], expected);
 
     // Plus
     let expected = Array2d::from_iter([
         0.0, 0.5, 1.0, 0.5, 
         2.0, 0.0, 0.0, 1.0
     ].iter(), 2, 4).unwrap();
-    if let NameValue::Array(n) = op_name_plus(na.clone(), nb.clone()) {
-        println!("{n:?}");
-        names_equal(&n, &["a1", "a2", "a3", "b"]);
-    } else {
-        panic!("couldn't get names for plus op")
-    }
-    if let Value::Array(plus) = op_concat(a.clone(), b.clone()){
-        array_almost_equal(plus, expected); 
-    } else {
-        assert!(false);
-    }
+    assert_columns(op_concat(a.clone(), b.clone()), &["a1", "a2", "a3", "b"], expected);
 
     // Power2
     let expected = Array2d::from_iter([
         0.0, 0.25, 1.0,  
         4.0, 0.0, 0.0
     ].iter(), 2, 3).unwrap();
-    if let NameValue::Array(n) = op_name_power(na.clone(), NameValue::Scalar("2".to_string())) {
-        println!("{n:?}");
-        names_equal(&n, &["a1^2", "a2^2", "a3^2"]);
-    } else {
-        panic!("couldn't get names for power op")
-    }
-    let power_value = ops_common::op_power(a.clone(), Value::Scalar(2.0));
-    if let Value::Array(power2) = power_value {
-        array_almost_equal(power2, expected); 
-    } else {
-        assert!(false);
-    }
+    assert_columns(op_power(a.clone(), WilkinsonValue::Scalar(2.0)), &["a1^^2", "a2^^2", "a3^^2"], expected);
 
-    // Plus cat
-    let (reference_names, feature_name, c) = get_cat_fortest(Some(2));
-    let mut reference_names = reference_names.iter().map(|n|n.as_str()).collect::<Vec<_>>();
+    // Cross
     let expected = Array2d::from_iter([
-        0.0, 0.5, 1.0, 0.0, 
-        2.0, 0.0, 0.0, 1.0
-    ].iter(), 2, 4).unwrap();
-    let plus_names = op_name_plus(na, NameValue::cats_from_value(feature_name, c.clone()).unwrap());
-    
-    if let NameValue::Array(n) = plus_names {
-        let mut all_names = vec!["a1", "a2", "a3"];
-        all_names.append(&mut reference_names);
-        println!("{n:?}");
-        names_equal(&n, &all_names);
-    } else {
-        panic!("couldn't get names for power op")
-    }
-    let plus = op_concat(a.clone(), c.clone());
-    println!("{plus:?}");
-    if let Value::Array(plus) = plus {
-        array_almost_equal(plus, expected); 
-    } else {
-        assert!(false);
-    }
+        0.0, 0.5, 1.0, 0.5, 0.0, 0.25, 0.5,
+        2.0, 0.0, 0.0, 1.0, 2.0, 0.0, 0.0
+    ].iter(), 2, 7).unwrap();
+    assert_columns(
+        op_cross(a.clone(), b.clone()),
+        &["a1", "a2", "a3", "b", "a1:b", "a2:b", "a3:b"],
+        expected,
+    );
+
+    // shape only
+    let shape = op_cross(WilkinsonValue::<ColMajor>::Columns(Columns::shape(3)), WilkinsonValue::Columns(Columns::shape(2)));
+    assert_eq!(shape, WilkinsonValue::Columns(Columns::shape(11)));
+    assert!(matches!(op_power(a, b), WilkinsonValue::Error(_)));
 }
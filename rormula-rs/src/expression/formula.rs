@@ -0,0 +1,216 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::{result::RoResult, roerr};
+
+use super::{
+    Term, Terms,
+    expr_terms::{expand_terms, expand_terms_literal, factor_var_name},
+};
+
+/// Finds the position of `~` that separates response and predictors, if there is any.
+fn find_tilde(formula: &str) -> RoResult<Option<usize>> {
+    let mut in_braces = false;
+    let mut tilde = None;
+    for (i, c) in formula.char_indices() {
+        match c {
+            '{' => in_braces = true,
+            '}' => in_braces = false,
+            '~' if !in_braces => {
+                if tilde.is_some() {
+                    return Err(roerr!("formula {} contains more than one ~", formula));
+                }
+                tilde = Some(i);
+            }
+            _ => (),
+        }
+    }
+    Ok(tilde)
+}
+
+fn fmt_terms(terms: &[Term]) -> String {
+    terms
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Parsed Wilkinson formula, i.e., the expanded terms of the response and the predictors. The
+/// formula can be inspected and edited and is evaluated with
+/// [`Wilkinson::from_formula`](super::Wilkinson::from_formula).
+/// ```rust
+/// # use std::error::Error;
+/// use rormula_rs::expression::Formula;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let formula = Formula::parse("y ~ a*b + I(c^2)")?;
+/// assert_eq!(formula.factors(), vec!["a", "b", "I(c^2)"]);
+/// let degrees = formula.terms().iter().map(|t| t.degree()).collect::<Vec<_>>();
+/// assert_eq!(degrees, vec![1, 1, 2, 1]);
+/// let formula = formula.without_terms("a:b")?.with_intercept(false);
+/// assert_eq!(formula.to_string(), "y ~ a + b + I(c^2) - 1");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Formula {
+    /// `None` if the formula has no left-hand side
+    response: Option<Terms>,
+    predictors: Terms,
+}
+impl Formula {
+    /// Parses the formula. Repeated terms like in `a + b + a` are only used once.
+    pub fn parse(formula: &str) -> RoResult<Self> {
+        Self::parse_impl(formula, expand_terms)
+    }
+
+    /// Parses the formula and keeps repeated terms.
+    pub fn parse_literal(formula: &str) -> RoResult<Self> {
+        Self::parse_impl(formula, expand_terms_literal)
+    }
+
+    fn parse_impl(formula: &str, expand: fn(&str) -> RoResult<Terms>) -> RoResult<Self> {
+        let (lhs, rhs) = match find_tilde(formula)? {
+            Some(idx) => (Some(&formula[..idx]), &formula[idx + 1..]),
+            None => (None, formula),
+        };
+        let response = match lhs.filter(|lhs| !lhs.trim().is_empty()) {
+            Some(lhs) => {
+                let terms = expand(lhs)?;
+                if terms.terms().is_empty() {
+                    return Err(roerr!("response {} does not contain variables", lhs));
+                }
+                Some(terms)
+            }
+            None => None,
+        };
+        Ok(Formula {
+            response,
+            predictors: expand(rhs)?,
+        })
+    }
+
+    pub fn response(&self) -> Option<&Terms> {
+        self.response.as_ref()
+    }
+
+    pub fn predictors(&self) -> &Terms {
+        &self.predictors
+    }
+
+    /// Terms of the right-hand side without the intercept
+    pub fn terms(&self) -> &[Term] {
+        self.predictors.terms()
+    }
+
+    /// Distinct factors of the right-hand side in the order of their first occurrence
+    pub fn factors(&self) -> Vec<&str> {
+        let mut factors = vec![];
+        for factor in self.terms().iter().flat_map(|t| t.factors()) {
+            let factor = factor_var_name(factor);
+            if !factors.contains(&factor) {
+                factors.push(factor);
+            }
+        }
+        factors
+    }
+
+    pub fn has_intercept(&self) -> bool {
+        self.predictors.has_intercept()
+    }
+
+    pub fn with_intercept(self, intercept: bool) -> Self {
+        Formula {
+            predictors: self.predictors.with_intercept(intercept),
+            ..self
+        }
+    }
+
+    /// Appends the terms of the given right-hand side that are not yet part of the formula, e.g.,
+    /// `with_terms("a*c")` adds `a`, `c`, and `a:c`.
+    pub fn with_terms(self, terms: &str) -> RoResult<Self> {
+        let mut all = self.terms().to_vec();
+        for term in expand_terms(terms)?.terms() {
+            if !all.iter().any(|t| t.is_same(term)) {
+                all.push(term.clone());
+            }
+        }
+        Ok(Formula {
+            predictors: self.predictors.with_terms(all),
+            ..self
+        })
+    }
+
+    /// Removes the terms of the given right-hand side, e.g., `without_terms("a:b")`.
+    pub fn without_terms(self, terms: &str) -> RoResult<Self> {
+        let removed = expand_terms(terms)?;
+        let remaining = self
+            .terms()
+            .iter()
+            .filter(|t| !removed.terms().iter().any(|r| r.is_same(t)))
+            .cloned()
+            .collect();
+        Ok(Formula {
+            predictors: self.predictors.with_terms(remaining),
+            ..self
+        })
+    }
+}
+
+/// Canonical form of the formula with expanded terms, e.g., `y ~ a*b - 1` becomes
+/// `y ~ a + b + a:b - 1`. The canonical form can be parsed again.
+impl Display for Formula {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(response) = &self.response {
+            write!(f, "{} ~ ", fmt_terms(response.terms()))?;
+        }
+        match (self.terms().is_empty(), self.has_intercept()) {
+            (true, true) => write!(f, "1"),
+            (true, false) => write!(f, "0"),
+            (false, true) => write!(f, "{}", fmt_terms(self.terms())),
+            (false, false) => write!(f, "{} - 1", fmt_terms(self.terms())),
+        }
+    }
+}
+
+#[test]
+fn test_formula() {
+    let formula = Formula::parse("y1 + y2 ~ (a + b)^2 + C(x, Sum):a - 1").unwrap();
+    assert_eq!(
+        formula.to_string(),
+        "y1 + y2 ~ a + b + a:b + C(x, Sum):a - 1"
+    );
+    assert_eq!(Formula::parse(&formula.to_string()).unwrap(), formula);
+    assert_eq!(formula.factors(), vec!["a", "b", "C(x, Sum)"]);
+    assert!(!formula.has_intercept());
+    assert_eq!(formula.response().unwrap().terms().len(), 2);
+    let formula = formula
+        .with_intercept(true)
+        .without_terms("a:b + C(x, Sum):a")
+        .unwrap()
+        .with_terms("b*c")
+        .unwrap();
+    assert_eq!(formula.to_string(), "y1 + y2 ~ a + b + c + b:c");
+    assert_eq!(
+        formula
+            .terms()
+            .iter()
+            .map(|t| t.degree())
+            .collect::<Vec<_>>(),
+        vec![1, 1, 1, 2]
+    );
+    assert_eq!(
+        Formula::parse("0 + a")
+            .unwrap()
+            .without_terms("a")
+            .unwrap()
+            .to_string(),
+        "0"
+    );
+    assert_eq!(Formula::parse("1").unwrap().to_string(), "1");
+    assert_eq!(
+        Formula::parse_literal("a + a").unwrap().to_string(),
+        "a + a"
+    );
+    assert!(Formula::parse("a ~ b ~ c").is_err());
+    assert!(Formula::parse("-1 ~ a").is_err());
+}
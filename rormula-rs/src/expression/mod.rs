@@ -1,8 +1,24 @@
+mod calls;
+mod design_info;
 mod expr_arithmetic;
+mod expr_terms;
 mod expr_wilkinson;
+mod formula;
 mod ops_common;
+mod splines;
 mod value;
+mod wilkinson;
 
-pub use expr_arithmetic::{ExprArithmetic, has_row_change_op};
-pub use expr_wilkinson::{ExprColCount, ExprNames, ExprWilkinson};
-pub use value::{NameValue, Value};
+pub use calls::FactorState;
+pub use design_info::{DesignInfo, FactorEncoding, FactorInfo, SubtermInfo, TermInfo};
+pub use expr_arithmetic::{
+    Arithmetic, ArithmeticLiteralMatcher, ExprArithmetic, Tolerance, eval_arithmetic,
+    has_row_change_op, parse_arithmetic,
+};
+pub use expr_terms::{Term, Terms, expand_terms, expand_terms_literal};
+pub use expr_wilkinson::ExprWilkinson;
+pub use formula::Formula;
+pub use ops_common::{Contrast, OTHER_LEVEL, UnseenLevels};
+pub use splines::{SplineBasis, SplineKind};
+pub use value::{Columns, Value, ValueSet, WilkinsonValue};
+pub use wilkinson::{ModelMatrix, ModelSpec, SparseModelMatrix, Wilkinson};
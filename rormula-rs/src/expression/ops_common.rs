@@ -1,35 +1,437 @@
 use crate::{
     array::{Array2d, MemOrder},
-    result::RoResult,
+    result::{RoErr, RoResult},
     roerr,
+    sparse::SparseCol,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    mem,
+    str::FromStr,
 };
-use std::mem;
 
 use super::Value;
 
-pub fn unique_cats(cats: &[String]) -> RoResult<(Vec<&String>, &String)> {
+/// Level that collects the values which are not among the known levels, see
+/// [`UnseenLevels::Other`]
+pub const OTHER_LEVEL: &str = "other";
+
+/// Sorted levels of a categorical variable
+pub fn unique_levels(cats: &[String]) -> Vec<String> {
     let mut unique = cats.iter().collect::<Vec<_>>();
     unique.sort();
     unique.dedup();
-    let removed_cat = unique.pop().ok_or_else(|| roerr!("cats are empty?",))?;
-    Ok((unique, removed_cat))
+    unique.into_iter().cloned().collect()
 }
 
-pub fn cat_to_dummy<M: MemOrder>(c: Value<M>) -> RoResult<Value<M>> {
-    if let Value::Cats(cats) = c {
-        let (unique, removed_cat) = unique_cats(&cats)?;
-        let (n_rows, n_cols) = (cats.len(), unique.len());
-        let mut dummy_encoding = Array2d::zeros(n_rows, n_cols);
-        for (row, cat) in cats.iter().enumerate() {
-            if cat != removed_cat {
-                let col = unique.iter().position(|cat_name| *cat_name == cat).unwrap();
-                dummy_encoding.set(row, col, 1.0);
+/// Policy for values of a categorical variable that are not among the levels known in advance,
+/// i.e., the levels that have been declared or recorded by a fit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnseenLevels {
+    /// Unseen values are an error that names the column and the value
+    #[default]
+    Error,
+    /// Unseen values are encoded as a row of zeros. Not possible for reduced-rank treatment
+    /// coding whose row of zeros is the reference level.
+    Zeros,
+    /// Unseen values are mapped to the additional level [`OTHER_LEVEL`]
+    Other,
+}
+impl FromStr for UnseenLevels {
+    type Err = RoErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(UnseenLevels::Error),
+            "zeros" => Ok(UnseenLevels::Zeros),
+            "other" => Ok(UnseenLevels::Other),
+            _ => Err(roerr!(
+                "unknown policy {} for unseen levels, expected error, zeros, or other",
+                s
+            )),
+        }
+    }
+}
+
+/// Coding of a categorical variable with `k` levels into `k - 1` columns
+#[derive(Clone, Debug, PartialEq)]
+pub enum Contrast {
+    /// Dummy coding against the reference level that defaults to the last level
+    Treatment { reference: Option<String> },
+    /// Deviation coding, i.e., each level except the last is compared to the grand mean
+    Sum,
+    /// Each level is compared to the mean of the previous levels
+    Helmert,
+    /// Backward difference coding, i.e., each level is compared to the previous level
+    Diff,
+    /// Orthogonal polynomials for ordered levels
+    Poly,
+}
+/// Formats the contrast like it is written in formulas, e.g., `Treatment(reference="ctrl")`
+impl Display for Contrast {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Contrast::Treatment {
+                reference: Some(reference),
+            } => write!(f, "Treatment(reference=\"{reference}\")"),
+            Contrast::Treatment { reference: None } => write!(f, "Treatment"),
+            Contrast::Sum => write!(f, "Sum"),
+            Contrast::Helmert => write!(f, "Helmert"),
+            Contrast::Diff => write!(f, "Diff"),
+            Contrast::Poly => write!(f, "Poly"),
+        }
+    }
+}
+impl Default for Contrast {
+    fn default() -> Self {
+        Contrast::Treatment { reference: None }
+    }
+}
+impl Contrast {
+    fn reference_idx(levels: &[String], reference: &Option<String>) -> RoResult<usize> {
+        match reference {
+            Some(reference) => levels
+                .iter()
+                .position(|l| l == reference)
+                .ok_or_else(|| roerr!("reference level {} not found", reference)),
+            None => Ok(levels.len() - 1),
+        }
+    }
+
+    /// Returns the contrast matrix with one row per level and one column per resulting column
+    fn reduced_matrix(&self, levels: &[String]) -> RoResult<Vec<Vec<f64>>> {
+        let k = levels.len();
+        if k == 0 {
+            return Err(roerr!("cats are empty?"));
+        }
+        let mut matrix = vec![vec![0.0; k - 1]; k];
+        match self {
+            Contrast::Treatment { reference } => {
+                let ref_idx = Self::reference_idx(levels, reference)?;
+                for (row, level_row) in matrix.iter_mut().enumerate() {
+                    if row != ref_idx {
+                        let col = if row < ref_idx { row } else { row - 1 };
+                        level_row[col] = 1.0;
+                    }
+                }
+            }
+            Contrast::Sum => {
+                for (row, level_row) in matrix.iter_mut().enumerate() {
+                    if row < k - 1 {
+                        level_row[row] = 1.0;
+                    } else {
+                        level_row.fill(-1.0);
+                    }
+                }
+            }
+            Contrast::Helmert => {
+                for col in 0..k - 1 {
+                    for (row, level_row) in matrix.iter_mut().enumerate().take(col + 2) {
+                        level_row[col] = if row <= col { -1.0 } else { (col + 1) as f64 };
+                    }
+                }
+            }
+            Contrast::Diff => {
+                for col in 0..k - 1 {
+                    for (row, level_row) in matrix.iter_mut().enumerate() {
+                        level_row[col] = if row <= col {
+                            -((k - col - 1) as f64) / k as f64
+                        } else {
+                            (col + 1) as f64 / k as f64
+                        };
+                    }
+                }
+            }
+            Contrast::Poly => {
+                // Gram-Schmidt on the powers of the centered scores of the levels
+                let center = (k - 1) as f64 / 2.0;
+                let scores = (0..k).map(|i| i as f64 - center).collect::<Vec<_>>();
+                let mut basis: Vec<Vec<f64>> = vec![vec![1.0 / (k as f64).sqrt(); k]];
+                for degree in 1..k {
+                    let mut v = scores
+                        .iter()
+                        .map(|s| s.powi(degree as i32))
+                        .collect::<Vec<_>>();
+                    for b in &basis {
+                        let dot = v.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f64>();
+                        v.iter_mut().zip(b.iter()).for_each(|(x, y)| *x -= dot * y);
+                    }
+                    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                    v.iter_mut().for_each(|x| *x /= norm);
+                    for (level_row, x) in matrix.iter_mut().zip(v.iter()) {
+                        level_row[degree - 1] = *x;
+                    }
+                    basis.push(v);
+                }
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Returns the coding matrix with one row per level. With full rank, there is one column per
+    /// level. Otherwise, there is one column less.
+    fn matrix(&self, levels: &[String], full_rank: bool) -> RoResult<Vec<Vec<f64>>> {
+        let mut matrix = self.reduced_matrix(levels)?;
+        if full_rank {
+            let k = levels.len();
+            let constant = match self {
+                Contrast::Treatment { .. } => None,
+                Contrast::Poly => Some(1.0 / (k as f64).sqrt()),
+                _ => Some(1.0),
+            };
+            for (row, level_row) in matrix.iter_mut().enumerate() {
+                if let Some(constant) = constant {
+                    level_row.insert(0, constant);
+                } else {
+                    *level_row = vec![0.0; k];
+                    level_row[row] = 1.0;
+                }
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Column names of the encoded variable
+    pub fn col_names(
+        &self,
+        feature_name: &str,
+        levels: &[String],
+        full_rank: bool,
+    ) -> RoResult<Vec<String>> {
+        let k = levels.len();
+        let constant_name = match self {
+            Contrast::Treatment { .. } => {
+                if full_rank {
+                    return Ok(levels
+                        .iter()
+                        .map(|l| format!("{feature_name}_{l}"))
+                        .collect());
+                }
+                None
+            }
+            Contrast::Poly => Some(format!("{feature_name}_.Constant")),
+            _ => Some(format!("{feature_name}_mean")),
+        };
+        let reduced_names: Vec<String> = match self {
+            Contrast::Treatment { reference } => {
+                let ref_idx = Self::reference_idx(levels, reference)?;
+                levels
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != ref_idx)
+                    .map(|(_, l)| format!("{feature_name}_{l}"))
+                    .collect()
             }
+            Contrast::Sum => levels[..k.saturating_sub(1)]
+                .iter()
+                .map(|l| format!("{feature_name}_S.{l}"))
+                .collect(),
+            Contrast::Helmert => levels
+                .iter()
+                .skip(1)
+                .map(|l| format!("{feature_name}_H.{l}"))
+                .collect(),
+            Contrast::Diff => levels
+                .iter()
+                .skip(1)
+                .map(|l| format!("{feature_name}_D.{l}"))
+                .collect(),
+            Contrast::Poly => (1..k)
+                .map(|degree| match degree {
+                    1 => format!("{feature_name}_.L"),
+                    2 => format!("{feature_name}_.Q"),
+                    3 => format!("{feature_name}_.C"),
+                    _ => format!("{feature_name}_^{degree}"),
+                })
+                .collect(),
+        };
+        Ok(constant_name
+            .filter(|_| full_rank)
+            .into_iter()
+            .chain(reduced_names)
+            .collect())
+    }
+
+    /// Returns levels and contrast with the additional level [`OTHER_LEVEL`] at the end. The
+    /// default reference of the treatment coding stays the last of the given levels. A level
+    /// [`OTHER_LEVEL`] among the given levels is an error since unseen values would silently be
+    /// merged into it.
+    pub fn with_other_level(&self, levels: &[String]) -> RoResult<(Vec<String>, Contrast)> {
+        if levels.iter().any(|l| l == OTHER_LEVEL) {
+            return Err(roerr!(
+                "level {} collides with the level for unseen values",
+                OTHER_LEVEL
+            ));
         }
-        Ok(Value::Array(dummy_encoding))
-    } else {
-        Ok(c)
+        let contrast = match self {
+            Contrast::Treatment { reference: None } => Contrast::Treatment {
+                reference: levels.last().cloned(),
+            },
+            _ => self.clone(),
+        };
+        let mut levels = levels.to_vec();
+        levels.push(OTHER_LEVEL.to_string());
+        Ok((levels, contrast))
     }
+
+    /// Index of the level of each value, `None` for unseen values that are encoded with zeros.
+    /// With reduced-rank treatment coding, zeros encode the reference level such that unseen
+    /// values cannot be encoded with zeros.
+    fn level_indices(
+        &self,
+        cats: &[String],
+        levels: &[String],
+        full_rank: bool,
+        unseen: UnseenLevels,
+    ) -> RoResult<Vec<Option<usize>>> {
+        let level_indices = levels
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (l.as_str(), i))
+            .collect::<HashMap<_, _>>();
+        let other_idx = level_indices.get(OTHER_LEVEL).copied();
+        let zeros_are_reference = !full_rank && matches!(self, Contrast::Treatment { .. });
+        cats.iter()
+            .enumerate()
+            .map(
+                |(row, cat)| match (level_indices.get(cat.as_str()), unseen, other_idx) {
+                    (Some(idx), _, _) => Ok(Some(*idx)),
+                    (None, UnseenLevels::Zeros, _) if zeros_are_reference => Err(roerr!(
+                        "unknown level {} in row {} cannot be encoded as zeros since zeros are \
+                         the reference level of the treatment coding, use unseen levels other \
+                         instead",
+                        cat,
+                        row
+                    )),
+                    (None, UnseenLevels::Zeros, _) => Ok(None),
+                    (None, UnseenLevels::Other, Some(other_idx)) => Ok(Some(other_idx)),
+                    _ => Err(roerr!("unknown level {} in row {}", cat, row)),
+                },
+            )
+            .collect()
+    }
+
+    /// Encodes the categorical values with one column per level if `full_rank` is true and with
+    /// one column less otherwise. Values that are not among the levels are handled according to
+    /// `unseen`.
+    pub fn encode<M: MemOrder>(
+        &self,
+        cats: &[String],
+        levels: &[String],
+        full_rank: bool,
+        unseen: UnseenLevels,
+    ) -> RoResult<Array2d<M>> {
+        let matrix = self.matrix(levels, full_rank)?;
+        let (n_rows, n_cols) = (cats.len(), matrix[0].len());
+        let mut encoding = Array2d::zeros(n_rows, n_cols);
+        for (row, level_idx) in self
+            .level_indices(cats, levels, full_rank, unseen)?
+            .into_iter()
+            .enumerate()
+        {
+            let Some(level_idx) = level_idx else {
+                continue;
+            };
+            for (col, x) in matrix[level_idx].iter().enumerate() {
+                if *x != 0.0 {
+                    encoding.set(row, col, *x);
+                }
+            }
+        }
+        Ok(encoding)
+    }
+
+    /// Sparse version of [`Contrast::encode`](Contrast::encode) that returns the encoded columns.
+    /// The rows are bucketed by level once such that each column only visits the rows of the
+    /// levels with non-zero coding, e.g., one level per column for the treatment coding.
+    pub(crate) fn encode_sparse(
+        &self,
+        cats: &[String],
+        levels: &[String],
+        full_rank: bool,
+        unseen: UnseenLevels,
+    ) -> RoResult<Vec<SparseCol>> {
+        let matrix = self.matrix(levels, full_rank)?;
+        let mut rows_by_level = vec![vec![]; levels.len()];
+        for (row, level_idx) in self
+            .level_indices(cats, levels, full_rank, unseen)?
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(level_idx) = level_idx {
+                rows_by_level[level_idx].push(row);
+            }
+        }
+        Ok((0..matrix[0].len())
+            .map(|col| {
+                SparseCol::from_entries(
+                    rows_by_level
+                        .iter()
+                        .zip(matrix.iter())
+                        .filter(|(_, level_row)| level_row[col] != 0.0)
+                        .flat_map(|(rows, level_row)| rows.iter().map(|row| (*row, level_row[col])))
+                        .collect(),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Coefficients of orthogonal polynomials like in R's `poly`. The polynomials follow the
+/// recurrence `p[k+1](x) = (x - alpha[k]) * p[k](x) - norm2[k] / norm2[k-1] * p[k-1](x)` with
+/// `p[0] = 1`, where `norm2[k]` is the squared norm of `p[k]` on the data. NaN is ignored.
+pub fn orthogonal_poly_coefs(x: &[f64], degree: usize) -> RoResult<(Vec<f64>, Vec<f64>)> {
+    let x = x
+        .iter()
+        .copied()
+        .filter(|x| !x.is_nan())
+        .collect::<Vec<_>>();
+    let mut unique = x.clone();
+    unique.sort_by(|a, b| a.total_cmp(b));
+    unique.dedup();
+    if degree == 0 || degree >= unique.len() {
+        return Err(roerr!(
+            "degree {} of poly needs to be positive and less than the number of unique points {}",
+            degree,
+            unique.len()
+        ));
+    }
+    let mut alpha = Vec::with_capacity(degree);
+    let mut norm2 = Vec::with_capacity(degree + 1);
+    let mut p_prev = vec![0.0; x.len()];
+    let mut p = vec![1.0; x.len()];
+    for k in 0..=degree {
+        norm2.push(p.iter().map(|p| p * p).sum::<f64>());
+        if k == degree {
+            break;
+        }
+        let a = x.iter().zip(p.iter()).map(|(x, p)| x * p * p).sum::<f64>() / norm2[k];
+        let ratio = if k == 0 { 0.0 } else { norm2[k] / norm2[k - 1] };
+        let p_next = x
+            .iter()
+            .zip(p.iter().zip(p_prev.iter()))
+            .map(|(x, (p, p_prev))| (x - a) * p - ratio * p_prev)
+            .collect();
+        alpha.push(a);
+        p_prev = mem::replace(&mut p, p_next);
+    }
+    Ok((alpha, norm2))
+}
+
+/// Evaluates the normed orthogonal polynomials of degree `1..=alpha.len()` with one column per
+/// degree, see [`orthogonal_poly_coefs`].
+pub fn orthogonal_poly<M: MemOrder>(x: &[f64], alpha: &[f64], norm2: &[f64]) -> Array2d<M> {
+    let mut polys = Array2d::zeros(x.len(), alpha.len());
+    for (row, x) in x.iter().enumerate() {
+        let (mut p_prev, mut p) = (0.0, 1.0);
+        for k in 0..alpha.len() {
+            let ratio = if k == 0 { 0.0 } else { norm2[k] / norm2[k - 1] };
+            let p_next = (x - alpha[k]) * p - ratio * p_prev;
+            (p_prev, p) = (p, p_next);
+            polys.set(row, k, p / norm2[k + 1].sqrt());
+        }
+    }
+    polys
 }
 
 pub fn op_componentwise_array<M: MemOrder>(
@@ -68,3 +470,201 @@ pub fn op_scalar<M: MemOrder + Default>(
 pub fn op_power<M: MemOrder + Default>(a: Value<M>, b: Value<M>) -> Value<M> {
     op_scalar(a, b, &|x, y| x.powf(y))
 }
+
+#[test]
+fn test_orthogonal_poly() {
+    use crate::array::ColMajor;
+    let x = [1.0, 2.0, 3.0, 4.0, 5.0];
+    let (alpha, norm2) = orthogonal_poly_coefs(&x, 2).unwrap();
+    assert_eq!(alpha, vec![3.0, 3.0]);
+    assert_eq!(norm2, vec![5.0, 10.0, 14.0]);
+    // reference values from R's poly(1:5, 2)
+    let polys = orthogonal_poly::<ColMajor>(&x, &alpha, &norm2);
+    let linear = [-2.0, -1.0, 0.0, 1.0, 2.0].map(|p| p / 10f64.sqrt());
+    let quadratic = [2.0, -1.0, -2.0, -1.0, 2.0].map(|p| p / 14f64.sqrt());
+    for row in 0..x.len() {
+        assert!((polys.get(row, 0) - linear[row]).abs() < 1e-12);
+        assert!((polys.get(row, 1) - quadratic[row]).abs() < 1e-12);
+    }
+    let new = orthogonal_poly::<ColMajor>(&[6.0, f64::NAN], &alpha, &norm2);
+    assert!((new.get(0, 0) - 3.0 / 10f64.sqrt()).abs() < 1e-12);
+    assert!(new.get(1, 1).is_nan());
+    assert!(orthogonal_poly_coefs(&[1.0, 2.0, 2.0], 2).is_err());
+    assert!(orthogonal_poly_coefs(&x, 0).is_err());
+}
+
+#[test]
+fn test_contrasts() {
+    use crate::array::ColMajor;
+    let cats = ["b", "a", "c", "ctrl", "a"].map(|c| c.to_string()).to_vec();
+    let levels = unique_levels(&cats);
+    let assert_contrast = |contrast: Contrast, names: &[&str], rows: &[&[f64]]| {
+        assert_eq!(contrast.col_names("x", &levels, false).unwrap(), names);
+        let encoded = contrast
+            .encode::<ColMajor>(&cats, &levels, false, UnseenLevels::Error)
+            .unwrap();
+        assert_eq!(encoded.n_cols(), names.len());
+        // the sparse encoding matches the dense encoding also for unseen values
+        let cats_unseen = [&cats[..], &["new".to_string()]].concat();
+        for full_rank in [false, true] {
+            let sparse =
+                contrast.encode_sparse(&cats_unseen, &levels, full_rank, UnseenLevels::Zeros);
+            let dense =
+                contrast.encode::<ColMajor>(&cats_unseen, &levels, full_rank, UnseenLevels::Zeros);
+            match (sparse, dense) {
+                (Ok(sparse), Ok(dense)) => assert_eq!(
+                    crate::sparse::CscMatrix::from_cols(cats_unseen.len(), sparse).to_dense(),
+                    dense
+                ),
+                // zeros are the reference level of the reduced-rank treatment coding
+                (Err(_), Err(_)) => {
+                    assert!(!full_rank && matches!(contrast, Contrast::Treatment { .. }))
+                }
+                _ => panic!("sparse and dense encoding need to fail alike"),
+            }
+        }
+        for (row, cat) in cats.iter().enumerate() {
+            let level_idx = levels.iter().position(|l| l == cat).unwrap();
+            for (col, expected) in rows[level_idx].iter().enumerate() {
+                assert!((encoded.get(row, col) - expected).abs() < 1e-12);
+            }
+        }
+    };
+    assert_contrast(
+        Contrast::default(),
+        &["x_a", "x_b", "x_c"],
+        &[
+            &[1.0, 0.0, 0.0],
+            &[0.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0],
+            &[0.0, 0.0, 0.0],
+        ],
+    );
+    assert_contrast(
+        Contrast::Treatment {
+            reference: Some("ctrl".to_string()),
+        },
+        &["x_a", "x_b", "x_c"],
+        &[
+            &[1.0, 0.0, 0.0],
+            &[0.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0],
+            &[0.0, 0.0, 0.0],
+        ],
+    );
+    assert_contrast(
+        Contrast::Treatment {
+            reference: Some("a".to_string()),
+        },
+        &["x_b", "x_c", "x_ctrl"],
+        &[
+            &[0.0, 0.0, 0.0],
+            &[1.0, 0.0, 0.0],
+            &[0.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0],
+        ],
+    );
+    assert_contrast(
+        Contrast::Sum,
+        &["x_S.a", "x_S.b", "x_S.c"],
+        &[
+            &[1.0, 0.0, 0.0],
+            &[0.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0],
+            &[-1.0, -1.0, -1.0],
+        ],
+    );
+    assert_contrast(
+        Contrast::Helmert,
+        &["x_H.b", "x_H.c", "x_H.ctrl"],
+        &[
+            &[-1.0, -1.0, -1.0],
+            &[1.0, -1.0, -1.0],
+            &[0.0, 2.0, -1.0],
+            &[0.0, 0.0, 3.0],
+        ],
+    );
+    assert_contrast(
+        Contrast::Diff,
+        &["x_D.b", "x_D.c", "x_D.ctrl"],
+        &[
+            &[-0.75, -0.5, -0.25],
+            &[0.25, -0.5, -0.25],
+            &[0.25, 0.5, -0.25],
+            &[0.25, 0.5, 0.75],
+        ],
+    );
+    // reference values from R's contr.poly(4)
+    let l = 0.6708203932499369;
+    let c = 0.22360679774997896;
+    assert_contrast(
+        Contrast::Poly,
+        &["x_.L", "x_.Q", "x_.C"],
+        &[&[-l, 0.5, -c], &[-c, -0.5, l], &[c, -0.5, -l], &[l, 0.5, c]],
+    );
+    assert!(
+        Contrast::Treatment {
+            reference: Some("dog".to_string())
+        }
+        .encode::<ColMajor>(&cats, &levels, false, UnseenLevels::Error)
+        .is_err()
+    );
+    let full = Contrast::default()
+        .encode::<ColMajor>(&cats, &levels, true, UnseenLevels::Error)
+        .unwrap();
+    assert_eq!(full.n_cols(), 4);
+    assert_eq!(full.get(0, 1), 1.0);
+    assert_eq!(full.get(3, 3), 1.0);
+    assert_eq!(full.get(4, 3), 0.0);
+    assert_eq!(
+        Contrast::Sum.col_names("x", &levels, true).unwrap(),
+        vec!["x_mean", "x_S.a", "x_S.b", "x_S.c"]
+    );
+    let full = Contrast::Sum
+        .encode::<ColMajor>(&cats, &levels, true, UnseenLevels::Error)
+        .unwrap();
+    assert_eq!(full.get(3, 0), 1.0);
+    assert_eq!(full.get(3, 1), -1.0);
+    assert_eq!(full.get(2, 3), 1.0);
+    assert_eq!(
+        Contrast::Poly.col_names("x", &levels, true).unwrap()[0],
+        "x_.Constant"
+    );
+    // known levels might not appear in the data
+    let levels_ext = ["a", "b", "c", "ctrl", "d"].map(|l| l.to_string());
+    let encoded = Contrast::default()
+        .encode::<ColMajor>(&cats, &levels_ext, false, UnseenLevels::Error)
+        .unwrap();
+    assert_eq!(encoded.n_cols(), 4);
+    assert_eq!(encoded.get(3, 3), 1.0);
+    assert!(
+        Contrast::default()
+            .encode::<ColMajor>(&cats, &levels[..2], false, UnseenLevels::Error)
+            .is_err()
+    );
+    let zeros = Contrast::Sum
+        .encode::<ColMajor>(&cats, &levels[..2], false, UnseenLevels::Zeros)
+        .unwrap();
+    assert_eq!(zeros.get(0, 0), -1.0);
+    assert_eq!(zeros.get(1, 0), 1.0);
+    assert_eq!(zeros.get(2, 0), 0.0);
+    let (levels_other, contrast) = Contrast::default().with_other_level(&levels[..2]).unwrap();
+    assert_eq!(levels_other, vec!["a", "b", OTHER_LEVEL]);
+    assert_eq!(
+        contrast.col_names("x", &levels_other, false).unwrap(),
+        vec!["x_a", "x_other"]
+    );
+    let other = contrast
+        .encode::<ColMajor>(&cats, &levels_other, false, UnseenLevels::Other)
+        .unwrap();
+    assert_eq!(other.get(0, 0), 0.0);
+    assert_eq!(other.get(1, 0), 1.0);
+    assert_eq!(other.get(2, 1), 1.0);
+    assert_eq!(other.get(3, 1), 1.0);
+    assert!(Contrast::Sum.with_other_level(&levels_other).is_err());
+    assert_eq!(
+        "zeros".parse::<UnseenLevels>().unwrap(),
+        UnseenLevels::Zeros
+    );
+    assert!("ignore".parse::<UnseenLevels>().is_err());
+}
@@ -0,0 +1,441 @@
+use crate::{
+    array::{Array2d, MemOrder},
+    result::RoResult,
+    roerr,
+};
+
+/// Quantiles with linear interpolation like R's default and Numpy's `percentile`. NaN is ignored.
+fn quantiles(x: &[f64], probs: &[f64]) -> RoResult<Vec<f64>> {
+    let mut sorted = x
+        .iter()
+        .copied()
+        .filter(|x| !x.is_nan())
+        .collect::<Vec<_>>();
+    if sorted.is_empty() {
+        return Err(roerr!("cannot place knots without data"));
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    Ok(probs
+        .iter()
+        .map(|p| {
+            let pos = p * (sorted.len() - 1) as f64;
+            let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+            sorted[lo] + (pos - lo as f64) * (sorted[hi] - sorted[lo])
+        })
+        .collect())
+}
+
+/// Equidistant probabilities from 0 to 1 including both
+fn equidistant_probs(n: usize) -> Vec<f64> {
+    (0..n).map(|i| i as f64 / (n - 1) as f64).collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+/// Values or derivatives of all B-splines of the given order on the knots at `x`. There are
+/// `knots.len() - order` B-splines. The last non-empty interval is closed on the right.
+fn bsplines(x: f64, knots: &[f64], order: usize, deriv: usize) -> Vec<f64> {
+    let last = knots[knots.len() - 1];
+    let mut b = knots
+        .windows(2)
+        .map(|t| {
+            let in_interval = (t[0] <= x && x < t[1]) || (x == last && t[0] < t[1] && t[1] == last);
+            if in_interval { 1.0 } else { 0.0 }
+        })
+        .collect::<Vec<_>>();
+    let ratio = |num: f64, den: f64| if den == 0.0 { 0.0 } else { num / den };
+    for m in 2..=order - deriv {
+        b = (0..knots.len() - m)
+            .map(|i| {
+                ratio(x - knots[i], knots[i + m - 1] - knots[i]) * b[i]
+                    + ratio(knots[i + m] - x, knots[i + m] - knots[i + 1]) * b[i + 1]
+            })
+            .collect();
+    }
+    for m in order - deriv + 1..=order {
+        b = (0..knots.len() - m)
+            .map(|i| {
+                (m - 1) as f64
+                    * (ratio(b[i], knots[i + m - 1] - knots[i])
+                        - ratio(b[i + 1], knots[i + m] - knots[i + 1]))
+            })
+            .collect();
+    }
+    b
+}
+
+/// Orthonormal basis of the orthogonal complement of the rows of `constraints` in `n` dimensions.
+/// The basis vectors are the last columns of `Q` of the Householder QR decomposition of the
+/// transposed constraints.
+fn constraint_complement(constraints: &[Vec<f64>], n: usize) -> Vec<Vec<f64>> {
+    let reflect = |x: &mut [f64], v: &[f64], v_norm2: f64| {
+        let factor = 2.0 * dot(x, v) / v_norm2;
+        x.iter_mut()
+            .zip(v.iter())
+            .for_each(|(x, v)| *x -= factor * v);
+    };
+    let mut columns = constraints.to_vec();
+    let mut reflections = vec![];
+    for k in 0..columns.len() {
+        let norm = dot(&columns[k][k..], &columns[k][k..]).sqrt();
+        let alpha = if columns[k][k] > 0.0 { -norm } else { norm };
+        let mut v = vec![0.0; n];
+        v[k..].copy_from_slice(&columns[k][k..]);
+        v[k] -= alpha;
+        let v_norm2 = dot(&v, &v);
+        if v_norm2 > 0.0 {
+            for col in columns.iter_mut().skip(k) {
+                reflect(col, &v, v_norm2);
+            }
+            reflections.push((v, v_norm2));
+        }
+    }
+    (constraints.len()..n)
+        .map(|j| {
+            let mut q_col = vec![0.0; n];
+            q_col[j] = 1.0;
+            for (v, v_norm2) in reflections.iter().rev() {
+                reflect(&mut q_col, v, *v_norm2);
+            }
+            q_col
+        })
+        .collect()
+}
+
+/// Second derivatives of a natural cubic spline at the knots as linear map of its values at the
+/// knots, i.e., a matrix with one row per knot. The rows of the boundary knots are zero.
+fn natural_second_derivs(knots: &[f64]) -> Vec<Vec<f64>> {
+    let n = knots.len();
+    let h = knots.windows(2).map(|k| k[1] - k[0]).collect::<Vec<_>>();
+    // tridiagonal system for the inner knots solved with the Thomas algorithm
+    let diag = (0..n - 2)
+        .map(|i| (h[i] + h[i + 1]) / 3.0)
+        .collect::<Vec<_>>();
+    let off = (0..n - 3).map(|i| h[i + 1] / 6.0).collect::<Vec<_>>();
+    let mut rhs = (0..n - 2)
+        .map(|i| {
+            let mut row = vec![0.0; n];
+            row[i] = 1.0 / h[i];
+            row[i + 1] = -1.0 / h[i] - 1.0 / h[i + 1];
+            row[i + 2] = 1.0 / h[i + 1];
+            row
+        })
+        .collect::<Vec<_>>();
+    let mut diag_elim = diag.clone();
+    for i in 1..n - 2 {
+        let factor = off[i - 1] / diag_elim[i - 1];
+        diag_elim[i] -= factor * off[i - 1];
+        let prev = rhs[i - 1].clone();
+        rhs[i]
+            .iter_mut()
+            .zip(prev.iter())
+            .for_each(|(r, p)| *r -= factor * p);
+    }
+    for i in (0..n - 2).rev() {
+        if i + 1 < n - 2 {
+            let next = rhs[i + 1].clone();
+            rhs[i]
+                .iter_mut()
+                .zip(next.iter())
+                .for_each(|(r, n)| *r -= off[i] * n);
+        }
+        rhs[i].iter_mut().for_each(|r| *r /= diag_elim[i]);
+    }
+    let mut second_derivs = vec![vec![0.0; n]];
+    second_derivs.extend(rhs);
+    second_derivs.push(vec![0.0; n]);
+    second_derivs
+}
+
+/// Kind of a spline basis
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplineKind {
+    /// B-splines of the given degree like `bs` in R and Patsy
+    BSpline { degree: usize },
+    /// Natural cubic splines like `ns` in R
+    Natural,
+    /// Natural cubic regression splines that are parameterized by their values at the knots like
+    /// `cr` in mgcv and Patsy, optionally centered such that they do not span the intercept
+    CubicRegression { center: bool },
+}
+
+/// Spline basis of a numerical variable. The knots are placed at quantiles of the training data
+/// and reused for new data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplineBasis {
+    kind: SplineKind,
+    /// Inner knots and boundary knots in ascending order
+    knots: Vec<f64>,
+    /// Orthonormal vectors the raw basis is projected onto to satisfy constraints
+    constraint: Option<Vec<Vec<f64>>>,
+}
+impl SplineBasis {
+    /// Places the knots such that the basis has `df` columns.
+    pub fn fit(kind: SplineKind, x: &[f64], df: usize) -> RoResult<Self> {
+        let n_knots = match kind {
+            SplineKind::BSpline { degree } if df >= degree.max(1) => df + 2 - degree,
+            SplineKind::Natural if df >= 1 => df + 1,
+            SplineKind::CubicRegression { center } if df + center as usize >= 3 => {
+                df + center as usize
+            }
+            _ => return Err(roerr!("df {} is too small for {:?}", df, kind)),
+        };
+        let knots = quantiles(x, &equidistant_probs(n_knots))?;
+        if knots.windows(2).any(|k| k[0] >= k[1]) {
+            return Err(roerr!(
+                "knots {:?} are not distinct, the data has too few unique values for df {}",
+                knots,
+                df
+            ));
+        }
+        let mut basis = SplineBasis {
+            kind,
+            knots,
+            constraint: None,
+        };
+        basis.constraint = match basis.kind {
+            SplineKind::Natural => {
+                // second derivatives vanish at the boundary knots
+                let (lower, upper) = basis.bounds();
+                let constraints = [lower, upper]
+                    .iter()
+                    .map(|b| {
+                        bsplines(*b, &basis.augmented_knots(4), 4, 2)
+                            .into_iter()
+                            .skip(1)
+                            .collect()
+                    })
+                    .collect::<Vec<_>>();
+                Some(constraint_complement(&constraints, df + 2))
+            }
+            SplineKind::CubicRegression { center: true } => {
+                let second_derivs = natural_second_derivs(&basis.knots);
+                let mut means = vec![0.0; n_knots];
+                let x = x.iter().filter(|x| !x.is_nan()).collect::<Vec<_>>();
+                for xi in &x {
+                    let row = basis.cubic_regression_row(**xi, &second_derivs);
+                    means.iter_mut().zip(row).for_each(|(m, r)| *m += r);
+                }
+                means.iter_mut().for_each(|m| *m /= x.len() as f64);
+                Some(constraint_complement(&[means], n_knots))
+            }
+            _ => None,
+        };
+        Ok(basis)
+    }
+
+    pub fn kind(&self) -> &SplineKind {
+        &self.kind
+    }
+
+    pub fn knots(&self) -> &[f64] {
+        &self.knots
+    }
+
+    pub fn n_cols(&self) -> usize {
+        match &self.constraint {
+            Some(constraint) => constraint.len(),
+            None => match self.kind {
+                SplineKind::BSpline { degree } => self.knots.len() + degree - 2,
+                _ => self.knots.len(),
+            },
+        }
+    }
+
+    fn bounds(&self) -> (f64, f64) {
+        (self.knots[0], self.knots[self.knots.len() - 1])
+    }
+
+    /// Knots where the boundary knots are repeated `order` times
+    fn augmented_knots(&self, order: usize) -> Vec<f64> {
+        let (lower, upper) = self.bounds();
+        let inner = &self.knots[1..self.knots.len() - 1];
+        let mut knots = vec![lower; order];
+        knots.extend(inner);
+        knots.extend(vec![upper; order]);
+        knots
+    }
+
+    fn cubic_regression_row(&self, x: f64, second_derivs: &[Vec<f64>]) -> Vec<f64> {
+        let knots = &self.knots;
+        let n = knots.len();
+        let mut row = vec![0.0; n];
+        let (lower, upper) = self.bounds();
+        // outside of the boundary knots, natural splines are linear
+        if x < lower || x > upper {
+            let (j, b, sign) = if x < lower {
+                (0, lower, -1.0)
+            } else {
+                (n - 2, upper, 1.0)
+            };
+            let h = knots[j + 1] - knots[j];
+            let (boundary_idx, inner_idx) = if x < lower { (0, 1) } else { (n - 1, n - 2) };
+            row[boundary_idx] = 1.0 + sign * (x - b) / h;
+            row[inner_idx] = -sign * (x - b) / h;
+            for (r, d) in row.iter_mut().zip(second_derivs[inner_idx].iter()) {
+                *r += sign * (x - b) * h / 6.0 * d;
+            }
+            return row;
+        }
+        let j = knots.windows(2).position(|k| x < k[1]).unwrap_or(n - 2);
+        let h = knots[j + 1] - knots[j];
+        let (dm, dp) = (knots[j + 1] - x, x - knots[j]);
+        row[j] += dm / h;
+        row[j + 1] += dp / h;
+        let cm = (dm * dm * dm / h - h * dm) / 6.0;
+        let cp = (dp * dp * dp / h - h * dp) / 6.0;
+        for (i, r) in row.iter_mut().enumerate() {
+            *r += cm * second_derivs[j][i] + cp * second_derivs[j + 1][i];
+        }
+        row
+    }
+
+    /// Values of the basis functions at `x` before constraints are applied
+    fn raw_row(&self, x: f64, second_derivs: &[Vec<f64>]) -> RoResult<Vec<f64>> {
+        let (lower, upper) = self.bounds();
+        match self.kind {
+            SplineKind::BSpline { degree } => {
+                if x < lower || x > upper {
+                    return Err(roerr!(
+                        "value {} is outside of the boundary knots {} and {}",
+                        x,
+                        lower,
+                        upper
+                    ));
+                }
+                let order = degree + 1;
+                Ok(bsplines(x, &self.augmented_knots(order), order, 0)[1..].to_vec())
+            }
+            SplineKind::Natural => {
+                let knots = self.augmented_knots(4);
+                if x < lower || x > upper {
+                    // linear extrapolation
+                    let b = if x < lower { lower } else { upper };
+                    let values = bsplines(b, &knots, 4, 0);
+                    let derivs = bsplines(b, &knots, 4, 1);
+                    Ok(values
+                        .iter()
+                        .zip(derivs.iter())
+                        .skip(1)
+                        .map(|(v, d)| v + (x - b) * d)
+                        .collect())
+                } else {
+                    Ok(bsplines(x, &knots, 4, 0)[1..].to_vec())
+                }
+            }
+            SplineKind::CubicRegression { .. } => Ok(self.cubic_regression_row(x, second_derivs)),
+        }
+    }
+
+    /// Evaluates the basis with one row per value and one column per basis function. NaN
+    /// results in a row of NaN.
+    pub fn eval<M: MemOrder>(&self, x: &[f64]) -> RoResult<Array2d<M>> {
+        let second_derivs = match self.kind {
+            SplineKind::CubicRegression { .. } => natural_second_derivs(&self.knots),
+            _ => vec![],
+        };
+        let n_cols = self.n_cols();
+        let mut basis = Array2d::zeros(x.len(), n_cols);
+        for (row, xi) in x.iter().enumerate() {
+            if xi.is_nan() {
+                for col in 0..n_cols {
+                    basis.set(row, col, f64::NAN);
+                }
+                continue;
+            }
+            let raw = self.raw_row(*xi, &second_derivs)?;
+            match &self.constraint {
+                Some(constraint) => {
+                    for (col, c) in constraint.iter().enumerate() {
+                        basis.set(row, col, dot(&raw, c));
+                    }
+                }
+                None => {
+                    for (col, r) in raw.iter().enumerate() {
+                        basis.set(row, col, *r);
+                    }
+                }
+            }
+        }
+        Ok(basis)
+    }
+}
+
+#[test]
+fn test_splines() {
+    use crate::array::ColMajor;
+    let x = (0..=10).map(|i| i as f64 / 10.0).collect::<Vec<_>>();
+    assert_eq!(
+        quantiles(&x, &[0.0, 0.25, 0.5, 1.0]).unwrap(),
+        vec![0.0, 0.25, 0.5, 1.0]
+    );
+
+    // without inner knots, cubic B-splines are Bernstein polynomials
+    let bs = SplineBasis::fit(SplineKind::BSpline { degree: 3 }, &x, 3).unwrap();
+    assert_eq!(bs.knots(), &[0.0, 1.0]);
+    let basis = bs.eval::<ColMajor>(&x).unwrap();
+    for (row, t) in x.iter().enumerate() {
+        let expected = [
+            3.0 * t * (1.0 - t) * (1.0 - t),
+            3.0 * t * t * (1.0 - t),
+            t * t * t,
+        ];
+        for (col, e) in expected.iter().enumerate() {
+            assert!((basis.get(row, col) - e).abs() < 1e-12);
+        }
+    }
+    let bs = SplineBasis::fit(SplineKind::BSpline { degree: 3 }, &x, 5).unwrap();
+    assert_eq!(bs.n_cols(), 5);
+    assert_eq!(bs.knots().len(), 4);
+    // together with the dropped first B-spline, the B-splines are a partition of unity
+    let basis = bs.eval::<ColMajor>(&[0.3, 1.0]).unwrap();
+    let sum = (0..5).map(|col| basis.get(1, col)).sum::<f64>();
+    assert!((sum - 1.0).abs() < 1e-12);
+    assert!(bs.eval::<ColMajor>(&[1.5]).is_err());
+    assert!(bs.eval::<ColMajor>(&[f64::NAN]).unwrap().get(0, 2).is_nan());
+
+    // natural splines are linear outside of the boundary knots
+    let ns = SplineBasis::fit(SplineKind::Natural, &x, 4).unwrap();
+    assert_eq!(ns.n_cols(), 4);
+    let basis = ns.eval::<ColMajor>(&[1.0, 1.5, 2.0, -1.0, 0.0]).unwrap();
+    for col in 0..4 {
+        let (at_1, at_1_5, at_2) = (basis.get(0, col), basis.get(1, col), basis.get(2, col));
+        assert!((at_1_5 - at_1 - (at_2 - at_1_5)).abs() < 1e-12);
+        assert!(basis.get(4, col).abs() < 1e-12);
+    }
+    // second derivatives vanish at the boundary knots
+    for b in [0.0, 1.0] {
+        let raw = bsplines(b, &ns.augmented_knots(4), 4, 2);
+        for c in ns.constraint.as_ref().unwrap() {
+            assert!(dot(&raw[1..], c).abs() < 1e-10);
+        }
+    }
+
+    // cubic regression splines interpolate their values at the knots
+    let cr = SplineBasis::fit(SplineKind::CubicRegression { center: false }, &x, 4).unwrap();
+    let knots = cr.knots().to_vec();
+    assert_eq!(knots.len(), 4);
+    let basis = cr.eval::<ColMajor>(&knots).unwrap();
+    for row in 0..4 {
+        for col in 0..4 {
+            let expected = if row == col { 1.0 } else { 0.0 };
+            assert!((basis.get(row, col) - expected).abs() < 1e-12);
+        }
+    }
+    let basis = cr.eval::<ColMajor>(&[0.2, 0.7, 1.5]).unwrap();
+    for row in 0..3 {
+        let sum = (0..4).map(|col| basis.get(row, col)).sum::<f64>();
+        assert!((sum - 1.0).abs() < 1e-12);
+    }
+    let cr = SplineBasis::fit(SplineKind::CubicRegression { center: true }, &x, 4).unwrap();
+    assert_eq!(cr.n_cols(), 4);
+    let basis = cr.eval::<ColMajor>(&x).unwrap();
+    for col in 0..4 {
+        let mean = (0..x.len()).map(|row| basis.get(row, col)).sum::<f64>() / x.len() as f64;
+        assert!(mean.abs() < 1e-12);
+    }
+    assert!(SplineBasis::fit(SplineKind::Natural, &[1.0, 1.0, 2.0], 3).is_err());
+    assert!(SplineBasis::fit(SplineKind::CubicRegression { center: false }, &x, 2).is_err());
+}
@@ -12,10 +12,18 @@ where
     M: MemOrder,
 {
     Array(Array2d<M>),
-    RowInds(Vec<usize>),
-    /// String is the name of the categorical
-    Cats(Vec<String>),
+    /// Indices of selected rows together with the number of rows they have been selected from
+    RowInds {
+        inds: Vec<usize>,
+        n_rows: usize,
+    },
+    /// Values of a categorical column, missing values are `None`
+    Cats(Vec<Option<String>>),
     Scalar(f64),
+    /// String literal like `"Pt"` without quotes
+    Str(String),
+    /// List literal like `["Pt", "Pd"]` or `[3, 5]` for the membership operator `%in%`
+    Set(ValueSet),
     /// String is the error message
     Error(String),
 }
@@ -24,44 +32,138 @@ impl<M: MemOrder> Default for Value<M> {
         Self::Error("default".to_string())
     }
 }
+/// Literals of a list, either all numbers or all strings
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueSet {
+    Numbers(Vec<f64>),
+    Strings(Vec<String>),
+}
+impl ValueSet {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ValueSet::Numbers(numbers) => numbers.is_empty(),
+            ValueSet::Strings(strings) => strings.is_empty(),
+        }
+    }
+}
+
+/// Splits the items of a list at commas that are not within quotes.
+fn split_list_items(list: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, ',') => {
+                items.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(&list[start..]);
+    items
+}
+
+/// Parses numbers, string literals in double or single quotes, and lists of them in brackets
 impl<M: MemOrder> FromStr for Value<M> {
     type Err = RoErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Value::Scalar(
-            s.parse::<f64>()
-                .map_err(|_| roerr!("could not parse {}", s))?,
-        ))
+        if let Some(list) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            if list.trim().is_empty() {
+                return Ok(Value::Set(ValueSet::Numbers(vec![])));
+            }
+            let items = split_list_items(list)
+                .into_iter()
+                .map(|item| item.trim().parse::<Value<M>>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let set = match items.first() {
+                Some(Value::Str(_)) => ValueSet::Strings(
+                    items
+                        .into_iter()
+                        .map(|item| match item {
+                            Value::Str(s) => Ok(s),
+                            _ => Err(roerr!("list {} mixes strings and other values", s)),
+                        })
+                        .collect::<Result<_, _>>()?,
+                ),
+                _ => ValueSet::Numbers(
+                    items
+                        .into_iter()
+                        .map(|item| match item {
+                            Value::Scalar(x) => Ok(x),
+                            _ => Err(roerr!("list {} mixes numbers and other values", s)),
+                        })
+                        .collect::<Result<_, _>>()?,
+                ),
+            };
+            return Ok(Value::Set(set));
+        }
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|q| s.strip_prefix(*q).and_then(|s| s.strip_suffix(*q)));
+        match unquoted {
+            Some(unquoted) if s.len() > 1 => Ok(Value::Str(unquoted.to_string())),
+            _ => Ok(Value::Scalar(
+                s.parse::<f64>()
+                    .map_err(|_| roerr!("could not parse {}", s))?,
+            )),
+        }
     }
 }
-#[derive(Clone, Debug)]
-pub enum NameValue {
-    Cats((String, Vec<String>)),
-    Array(Vec<String>),
-    Scalar(String),
-    Error(String),
+/// Columns of a Wilkinson term that carry their data, names, and shape together. The data is
+/// `None` if only the shape is evaluated, the names are `None` if they are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Columns<M>
+where
+    M: MemOrder,
+{
+    pub n_cols: usize,
+    pub names: Option<Vec<String>>,
+    pub data: Option<Array2d<M>>,
 }
-impl NameValue {
-    pub fn cats_from_value<M: MemOrder>(feature_name: String, cats: Value<M>) -> Option<Self> {
-        if let Value::Cats(c) = cats {
-            Some(Self::Cats((feature_name, c)))
-        } else {
-            None
+impl<M: MemOrder> Columns<M> {
+    pub fn new(names: Option<Vec<String>>, data: Array2d<M>) -> Self {
+        Columns {
+            n_cols: data.n_cols(),
+            names,
+            data: Some(data),
         }
     }
+    /// Columns without data and names to determine the shape of the result
+    pub fn shape(n_cols: usize) -> Self {
+        Columns {
+            n_cols,
+            names: None,
+            data: None,
+        }
+    }
+}
+
+/// Value of [`ExprWilkinson`](super::ExprWilkinson)
+#[derive(Clone, Debug, PartialEq)]
+pub enum WilkinsonValue<M>
+where
+    M: MemOrder,
+{
+    Columns(Columns<M>),
+    Scalar(f64),
+    /// String is the error message
+    Error(String),
 }
-impl Default for NameValue {
+impl<M: MemOrder> Default for WilkinsonValue<M> {
     fn default() -> Self {
         Self::Error("default".to_string())
     }
 }
-impl FromStr for NameValue {
+impl<M: MemOrder> FromStr for WilkinsonValue<M> {
     type Err = RoErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(NameValue::Scalar(
-            // we parse to make sure it is actually a number
+        Ok(WilkinsonValue::Scalar(
             s.parse::<f64>()
-                .map_err(|_| roerr!("could not parse {}", s))?
-                .to_string(),
+                .map_err(|_| roerr!("could not parse {}", s))?,
         ))
     }
 }
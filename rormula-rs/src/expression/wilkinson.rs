@@ -0,0 +1,1604 @@
+use std::collections::HashMap;
+
+use exmex::Express;
+
+use crate::{
+    array::{Array2d, DefaultOrder},
+    data::{DataSource, NA_LEVEL, NaAction, RowSubset},
+    result::{RoResult, to_ro},
+    roerr,
+    sparse::{CscMatrix, SparseCol},
+    timing,
+};
+
+use super::{
+    Columns, ExprWilkinson, Formula, Terms, Tolerance, UnseenLevels, WilkinsonValue,
+    calls::{FactorData, FactorState, column_names, load_factor},
+    design_info::{DesignInfo, FactorEncoding, FactorInfo, SubtermInfo, TermInfo},
+    expr_terms::{factor_var_name, unparse_var_name},
+    expr_wilkinson::{
+        CodedFactor, code_categoricals, code_categoricals_by_term, interaction_names, op_concat,
+        op_multiply,
+    },
+};
+
+/// Model matrix or response matrix together with its column names
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelMatrix {
+    /// `None` if names have been skipped
+    pub names: Option<Vec<String>>,
+    pub data: Array2d<DefaultOrder>,
+    /// Indices of the rows of the data that have been used, `None` if all rows have been used
+    pub rows: Option<Vec<usize>>,
+}
+
+/// Sparse model matrix together with its column names, see [`Wilkinson::eval_sparse`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseModelMatrix {
+    /// `None` if names have been skipped
+    pub names: Option<Vec<String>>,
+    pub data: CscMatrix,
+    /// Indices of the rows of the data that have been used, `None` if all rows have been used
+    pub rows: Option<Vec<usize>>,
+}
+
+fn result_columns(value: WilkinsonValue<DefaultOrder>) -> RoResult<Columns<DefaultOrder>> {
+    match value {
+        WilkinsonValue::Columns(columns) => Ok(columns),
+        WilkinsonValue::Scalar(s) => Err(roerr!("result cannot be skalar but got {}", s)),
+        WilkinsonValue::Error(e) => Err(roerr!("computation failed, {}", e)),
+    }
+}
+
+/// Expanded terms of one side of a formula. Factors that are not plain variables like `a^^2` are
+/// parsed once such that evaluating the terms does not need any parsing.
+#[derive(Clone, Debug)]
+struct WilkinsonExprs {
+    terms: Terms,
+    /// Expressions of the factors that are not plain variables
+    factor_exprs: Vec<(String, ExprWilkinson)>,
+    /// Variables of all factors in alphabetical order
+    var_names: Vec<String>,
+}
+impl WilkinsonExprs {
+    /// Returns `None` if there are no terms. Flat expressions are rather large, hence we box them.
+    fn parse(terms: Terms) -> RoResult<Option<Box<Self>>> {
+        if terms.terms().is_empty() {
+            return Ok(None);
+        }
+        let mut factor_exprs: Vec<(String, ExprWilkinson)> = vec![];
+        let mut var_names = vec![];
+        for factor in terms.terms().iter().flat_map(|t| t.factors()) {
+            let vn = factor_var_name(factor);
+            if unparse_var_name(vn) == *factor {
+                var_names.push(vn.to_string());
+            } else if !factor_exprs.iter().any(|(f, _)| f == factor) {
+                let expr = ExprWilkinson::parse(factor).map_err(to_ro)?;
+                var_names.extend(expr.var_names().iter().cloned());
+                factor_exprs.push((factor.clone(), expr));
+            }
+        }
+        var_names.sort();
+        var_names.dedup();
+        Ok(Some(Box::new(WilkinsonExprs {
+            terms,
+            factor_exprs,
+            var_names,
+        })))
+    }
+
+    /// Names of the data columns the terms refer to, possibly with repetitions
+    fn columns(&self) -> RoResult<Vec<String>> {
+        Ok(self
+            .var_names
+            .iter()
+            .map(|vn| column_names(vn))
+            .collect::<RoResult<Vec<_>>>()?
+            .concat())
+    }
+
+    /// Looks up the data of all variables. If states are passed, they are used, e.g., for the
+    /// levels of categoricals.
+    fn load_factors(
+        &self,
+        data: &impl DataSource,
+        states: Option<&HashMap<String, FactorState>>,
+        unseen: UnseenLevels,
+        tolerance: Tolerance,
+        na_level: &str,
+    ) -> RoResult<Vec<(&str, FactorData)>> {
+        Ok(timing!(
+            self.var_names
+                .iter()
+                .map(|vn| {
+                    let state = states.and_then(|states| states.get(vn));
+                    Ok((
+                        vn.as_str(),
+                        load_factor(vn, data, state, unseen, tolerance, na_level)?,
+                    ))
+                })
+                .collect::<RoResult<Vec<_>>>()?,
+            "vars"
+        ))
+    }
+
+    fn factor_expr(&self, factor: &str) -> Option<&ExprWilkinson> {
+        self.factor_exprs
+            .iter()
+            .find(|(f, _)| f == factor)
+            .map(|(_, expr)| expr)
+    }
+
+    /// Variables a factor of a coded term consists of
+    fn factor_vars<'a>(&'a self, factor: CodedFactor<'a>) -> Vec<&'a str> {
+        match self.factor_expr(factor.factor) {
+            Some(expr) => expr.var_names().iter().map(|vn| vn.as_str()).collect(),
+            None => vec![factor_var_name(factor.factor)],
+        }
+    }
+
+    /// Evaluates a factor of a coded term. Plain variables are passed to `encode` together with
+    /// their rank. Factors like `a^^2` are evaluated on the reduced-rank encodings of their
+    /// variables.
+    fn eval_factor(
+        &self,
+        factor: CodedFactor,
+        encode: &mut impl FnMut(&str, bool) -> RoResult<Columns<DefaultOrder>>,
+    ) -> RoResult<Columns<DefaultOrder>> {
+        match self.factor_expr(factor.factor) {
+            Some(expr) => {
+                let vars = expr
+                    .var_names()
+                    .iter()
+                    .map(|vn| Ok(WilkinsonValue::Columns(encode(vn, false)?)))
+                    .collect::<RoResult<Vec<_>>>()?;
+                result_columns(expr.eval_vec(vars).map_err(to_ro)?)
+            }
+            None => encode(factor_var_name(factor.factor), factor.full_rank),
+        }
+    }
+
+    fn eval(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+        unseen: UnseenLevels,
+        tolerance: Tolerance,
+        na_level: &str,
+    ) -> RoResult<ModelMatrix> {
+        let factors = self.load_factors(data, states, unseen, tolerance, na_level)?;
+        let find_factor = |vn: &str| {
+            factors
+                .iter()
+                .position(|(name, _)| *name == vn)
+                .ok_or_else(|| roerr!("did not find Variable {}", vn))
+        };
+        let coded_terms = code_categoricals(&self.terms, |vn| {
+            find_factor(vn).is_ok_and(|idx| factors[idx].1.is_categorical())
+        });
+
+        // the shape is cheap to evaluate and lets us allocate the result only once
+        let mut shape = |vn: &str, full_rank: bool| {
+            Ok(Columns::shape(
+                factors[find_factor(vn)?].1.n_cols(full_rank)?,
+            ))
+        };
+        let mut n_cols = 0;
+        for term in &coded_terms {
+            let mut term_n_cols = 1;
+            for factor in term {
+                term_n_cols *= self.eval_factor(*factor, &mut shape)?.n_cols;
+            }
+            n_cols += term_n_cols;
+        }
+
+        // the data of a factor is moved into its last use instead of being copied
+        let mut uses = vec![0; factors.len()];
+        for factor in coded_terms.iter().flatten() {
+            for vn in self.factor_vars(*factor) {
+                uses[find_factor(vn)?] += 1;
+            }
+        }
+        let mut factors = factors
+            .iter()
+            .map(|(_, f)| Some(f.clone()))
+            .collect::<Vec<_>>();
+        let mut encode = |vn: &str, full_rank: bool| {
+            let idx = find_factor(vn)?;
+            uses[idx] -= 1;
+            let factor = if uses[idx] == 0 {
+                factors[idx].take()
+            } else {
+                factors[idx].clone()
+            };
+            factor
+                .ok_or_else(|| roerr!("variable {} has been used up", vn))?
+                .encode(full_rank, skip_names)
+        };
+        let mut result = None;
+        for term in &coded_terms {
+            let mut term_value = None;
+            for factor in term {
+                let value = WilkinsonValue::Columns(self.eval_factor(*factor, &mut encode)?);
+                term_value = Some(match term_value {
+                    Some(term_value) => op_multiply(term_value, value),
+                    None => value,
+                });
+            }
+            result = match (result, term_value) {
+                (Some(result), Some(term_value)) => Some(op_concat(result, term_value)),
+                (None, Some(mut term_value)) => {
+                    if let WilkinsonValue::Columns(Columns {
+                        n_cols: term_n_cols,
+                        data: Some(arr),
+                        ..
+                    }) = &mut term_value
+                    {
+                        arr.reserve((n_cols - *term_n_cols) * arr.n_rows());
+                    }
+                    Some(term_value)
+                }
+                (result, None) => result,
+            };
+        }
+        let result =
+            result_columns(result.ok_or_else(|| roerr!("computation did not result in data"))?)?;
+        let data = result
+            .data
+            .ok_or_else(|| roerr!("computation did not result in data"))?;
+        Ok(ModelMatrix {
+            names: result.names,
+            data,
+            rows: None,
+        })
+    }
+
+    /// Column names and descriptions of the terms whose columns start at `offset`
+    fn term_infos(
+        &self,
+        data: &impl DataSource,
+        states: Option<&HashMap<String, FactorState>>,
+        unseen: UnseenLevels,
+        tolerance: Tolerance,
+        na_level: &str,
+        offset: usize,
+    ) -> RoResult<(Vec<String>, Vec<TermInfo>)> {
+        let factors = self.load_factors(data, states, unseen, tolerance, na_level)?;
+        let find_factor = |vn: &str| {
+            factors
+                .iter()
+                .find(|(name, _)| *name == vn)
+                .map(|(_, f)| f)
+                .ok_or_else(|| roerr!("did not find Variable {}", vn))
+        };
+        let coded = code_categoricals_by_term(&self.terms, |vn| {
+            find_factor(vn).is_ok_and(|f| f.is_categorical())
+        });
+        // names suffice, no need to compute any data
+        let mut names_only = |vn: &str, full_rank: bool| {
+            let factor = find_factor(vn)?;
+            Ok(Columns {
+                n_cols: factor.n_cols(full_rank)?,
+                names: Some(factor.col_names(full_rank)?),
+                data: None,
+            })
+        };
+        let mut names = vec![];
+        let mut term_infos = vec![];
+        for (term, subterms) in self.terms.terms().iter().zip(coded) {
+            let term_start = offset + names.len();
+            let mut subterm_infos = vec![];
+            for subterm in subterms {
+                let subterm_start = offset + names.len();
+                let mut subterm_names: Option<Vec<String>> = None;
+                let mut factor_infos = vec![];
+                for factor in subterm {
+                    let vn = factor_var_name(factor.factor);
+                    let (factor_names, encoding) = match self.factor_expr(factor.factor) {
+                        Some(_) => {
+                            let columns = self.eval_factor(factor, &mut names_only)?;
+                            (
+                                columns.names.unwrap_or_default(),
+                                FactorEncoding::Numerical {
+                                    n_cols: columns.n_cols,
+                                },
+                            )
+                        }
+                        None => {
+                            let f = find_factor(vn)?;
+                            (
+                                f.col_names(factor.full_rank)?,
+                                f.encoding(factor.full_rank)?,
+                            )
+                        }
+                    };
+                    subterm_names = Some(match subterm_names {
+                        Some(names) => interaction_names(&names, &factor_names),
+                        None => factor_names,
+                    });
+                    factor_infos.push(FactorInfo {
+                        name: vn.to_string(),
+                        encoding,
+                    });
+                }
+                names.extend(subterm_names.unwrap_or_default());
+                subterm_infos.push(SubtermInfo {
+                    factors: factor_infos,
+                    columns: subterm_start..offset + names.len(),
+                });
+            }
+            term_infos.push(TermInfo {
+                name: term
+                    .factors()
+                    .iter()
+                    .map(|f| factor_var_name(f))
+                    .collect::<Vec<_>>()
+                    .join(":"),
+                factors: term
+                    .factors()
+                    .iter()
+                    .map(|f| factor_var_name(f).to_string())
+                    .collect(),
+                columns: term_start..offset + names.len(),
+                subterms: subterm_infos,
+            });
+        }
+        Ok((names, term_infos))
+    }
+
+    /// Evaluates the terms column by column such that the dense model matrix is never
+    /// materialized. Factors that are not plain variables like `a^^2` are evaluated densely.
+    fn eval_sparse(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+        unseen: UnseenLevels,
+        tolerance: Tolerance,
+        na_level: &str,
+    ) -> RoResult<(Vec<String>, Vec<SparseCol>)> {
+        let factors = self.load_factors(data, states, unseen, tolerance, na_level)?;
+        let find_factor = |vn: &str| {
+            factors
+                .iter()
+                .find(|(name, _)| *name == vn)
+                .map(|(_, f)| f)
+                .ok_or_else(|| roerr!("did not find Variable {}", vn))
+        };
+        let coded_terms = code_categoricals(&self.terms, |vn| {
+            find_factor(vn).is_ok_and(|f| f.is_categorical())
+        });
+        let factor_cols = |factor: CodedFactor| -> RoResult<(Vec<String>, Vec<SparseCol>)> {
+            if self.factor_expr(factor.factor).is_some() {
+                let mut encode = |vn: &str, full_rank: bool| {
+                    find_factor(vn)?.clone().encode(full_rank, skip_names)
+                };
+                let columns = self.eval_factor(factor, &mut encode)?;
+                let data = columns
+                    .data
+                    .ok_or_else(|| roerr!("computation did not result in data"))?;
+                let cols = (0..data.n_cols())
+                    .map(|col| {
+                        SparseCol::from_dense((0..data.n_rows()).map(|row| data.get(row, col)))
+                    })
+                    .collect();
+                Ok((columns.names.unwrap_or_default(), cols))
+            } else {
+                let full_rank = factor.full_rank;
+                let factor = find_factor(factor_var_name(factor.factor))?;
+                let names = if skip_names {
+                    vec![]
+                } else {
+                    factor.col_names(full_rank)?
+                };
+                Ok((names, factor.encode_sparse(full_rank)?))
+            }
+        };
+        let mut names = vec![];
+        let mut cols = vec![];
+        for term in &coded_terms {
+            let mut factors = term.iter();
+            let Some(first) = factors.next() else {
+                continue;
+            };
+            let (mut term_names, mut term_cols) = factor_cols(*first)?;
+            for factor in factors {
+                let (factor_names, factor_cols) = factor_cols(*factor)?;
+                // the first factor varies fastest like in the dense evaluation
+                term_cols = factor_cols
+                    .iter()
+                    .flat_map(|b| term_cols.iter().map(move |a| a.multiply(b)))
+                    .collect();
+                term_names = interaction_names(&term_names, &factor_names);
+            }
+            names.extend(term_names);
+            cols.extend(term_cols);
+        }
+        Ok((names, cols))
+    }
+}
+
+/// Wilkinson formula with optional response, e.g., `y ~ a*b - 1` or `y1 + y2 ~ a + b`.
+/// ```rust
+/// # use std::error::Error;
+/// use rormula_rs::{data::SeparatedData, expression::Wilkinson};
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let data = SeparatedData::new(
+///     vec!["y".to_string(), "a".to_string(), "b".to_string()],
+///     vec![vec![1.0, 2.0], vec![0.5, 1.5], vec![2.0, 3.0]],
+///     vec![],
+///     vec![],
+/// )?;
+/// let formula = Wilkinson::parse("y ~ a*b")?;
+/// let (response, model_matrix) = formula.eval_with_response(&data, false)?;
+/// assert_eq!(response.names, Some(vec!["y".to_string()]));
+/// assert_eq!(response.data.n_cols(), 1);
+/// let names = ["Intercept", "a", "b", "a:b"].map(|n| n.to_string()).to_vec();
+/// assert_eq!(model_matrix.names, Some(names));
+/// assert_eq!(model_matrix.data.get(1, 3), 4.5);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Wilkinson {
+    /// `None` if the formula has no left-hand side
+    response: Option<Box<WilkinsonExprs>>,
+    /// `None` if the formula does not contain any terms besides the intercept
+    predictors: Option<Box<WilkinsonExprs>>,
+    has_intercept: bool,
+    unseen_levels: UnseenLevels,
+    na_action: NaAction,
+    tolerance: Tolerance,
+    na_level: String,
+    formula: Formula,
+}
+impl Wilkinson {
+    /// Parses the formula. Repeated terms like in `a + b + a` are only used once.
+    pub fn parse(formula: &str) -> RoResult<Self> {
+        Self::from_formula(Formula::parse(formula)?)
+    }
+
+    /// Parses the formula and keeps repeated terms, e.g., `a + b + a` results in three columns.
+    pub fn parse_literal(formula: &str) -> RoResult<Self> {
+        Self::from_formula(Formula::parse_literal(formula)?)
+    }
+
+    /// Prepares the evaluation of a parsed and possibly edited formula.
+    pub fn from_formula(formula: Formula) -> RoResult<Self> {
+        let response = match formula.response() {
+            Some(terms) => Some(
+                WilkinsonExprs::parse(terms.clone())?
+                    .ok_or_else(|| roerr!("response does not contain variables"))?,
+            ),
+            None => None,
+        };
+        Ok(Wilkinson {
+            response,
+            predictors: WilkinsonExprs::parse(formula.predictors().clone())?,
+            has_intercept: formula.has_intercept(),
+            unseen_levels: UnseenLevels::default(),
+            na_action: NaAction::default(),
+            tolerance: Tolerance::default(),
+            na_level: NA_LEVEL.to_string(),
+            formula,
+        })
+    }
+
+    /// The parsed formula with its terms
+    pub fn formula(&self) -> &Formula {
+        &self.formula
+    }
+
+    /// Sets the policy for values of categoricals that are not among the declared or fitted
+    /// levels. By default, such values are an error.
+    pub fn with_unseen_levels(mut self, unseen_levels: UnseenLevels) -> Self {
+        self.unseen_levels = unseen_levels;
+        self
+    }
+
+    /// Sets the handling of missing values. By default, NaN is propagated and missing
+    /// categoricals form a level of their own, see [`Wilkinson::with_na_level`]. If rows are
+    /// dropped, the indices of the remaining rows are reported in [`ModelMatrix::rows`].
+    pub fn with_na_action(mut self, na_action: NaAction) -> Self {
+        self.na_action = na_action;
+        self
+    }
+
+    /// Sets the level that kept missing values of categoricals form. The level must not be
+    /// present in the data as well. By default, the level is [`NA_LEVEL`].
+    pub fn with_na_level(mut self, na_level: &str) -> Self {
+        self.na_level = na_level.to_string();
+        self
+    }
+
+    /// Sets the tolerance of comparisons within `I(...)` like `I(temp == 100)`. By default, values
+    /// are equal if their relative difference is below `1e-8`.
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Names of the data columns the formula refers to, optionally including the response
+    fn columns(&self, with_response: bool) -> RoResult<Vec<String>> {
+        let response = self.response.iter().filter(|_| with_response);
+        let mut columns = vec![];
+        for exprs in response.chain(self.predictors.iter()) {
+            for col in exprs.columns()? {
+                if !columns.contains(&col) {
+                    columns.push(col);
+                }
+            }
+        }
+        Ok(columns)
+    }
+
+    pub fn has_response(&self) -> bool {
+        self.response.is_some()
+    }
+
+    /// Indices of the rows of the data that are used depending on the [`NaAction`], optionally
+    /// including the columns of the response. `None` if all rows are used.
+    pub fn complete_rows(
+        &self,
+        data: &impl DataSource,
+        with_response: bool,
+    ) -> RoResult<Option<Vec<usize>>> {
+        self.na_action
+            .complete_rows(data, &self.columns(with_response)?)
+    }
+
+    /// Evaluates the right-hand side of the formula, i.e., the model matrix.
+    pub fn eval(&self, data: &impl DataSource, skip_names: bool) -> RoResult<ModelMatrix> {
+        self.eval_impl(data, skip_names, None)
+    }
+
+    /// Evaluates the right-hand side of the formula into a sparse matrix in compressed sparse
+    /// column format. This pays off for high-cardinality categoricals and their interactions.
+    pub fn eval_sparse(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+    ) -> RoResult<SparseModelMatrix> {
+        self.eval_sparse_impl(data, skip_names, None)
+    }
+
+    /// Describes the columns of the model matrix of the data, i.e., the terms with their
+    /// columns and the encodings of their factors.
+    pub fn design_info(&self, data: &impl DataSource) -> RoResult<DesignInfo> {
+        self.design_info_impl(data, None)
+    }
+
+    /// Evaluates the left-hand side and the right-hand side of the formula and returns the
+    /// response and the model matrix.
+    pub fn eval_with_response(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+    ) -> RoResult<(ModelMatrix, ModelMatrix)> {
+        self.eval_with_response_impl(data, skip_names, None)
+    }
+
+    /// Records the levels of categoricals and other data-dependent parameters such that new data
+    /// can be transformed into model matrices with the same columns, see
+    /// [`ModelSpec`](ModelSpec).
+    pub fn fit(&self, data: &impl DataSource) -> RoResult<ModelSpec> {
+        match self.complete_rows(data, true)? {
+            Some(rows) => self.fit_impl(&RowSubset::new(data, rows)),
+            None => self.fit_impl(data),
+        }
+    }
+
+    fn fit_impl(&self, data: &impl DataSource) -> RoResult<ModelSpec> {
+        let mut states = HashMap::new();
+        for exprs in self.response.iter().chain(self.predictors.iter()) {
+            for (var_name, factor) in exprs.load_factors(
+                data,
+                None,
+                self.unseen_levels,
+                self.tolerance,
+                &self.na_level,
+            )? {
+                if let Some(state) = factor.state() {
+                    states.insert(var_name.to_string(), state);
+                }
+            }
+        }
+        Ok(ModelSpec {
+            formula: self.clone(),
+            states,
+        })
+    }
+
+    fn eval_impl(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<ModelMatrix> {
+        match self.complete_rows(data, false)? {
+            Some(rows) => {
+                let data = RowSubset::new(data, rows);
+                let model_matrix = self.eval_predictors(&data, skip_names, states)?;
+                Ok(ModelMatrix {
+                    rows: Some(data.into_rows()),
+                    ..model_matrix
+                })
+            }
+            None => self.eval_predictors(data, skip_names, states),
+        }
+    }
+
+    fn eval_predictors(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<ModelMatrix> {
+        let ModelMatrix {
+            names, data: mm, ..
+        } = if let Some(predictors) = &self.predictors {
+            predictors.eval(
+                data,
+                skip_names,
+                states,
+                self.unseen_levels,
+                self.tolerance,
+                &self.na_level,
+            )?
+        } else {
+            ModelMatrix {
+                names: (!skip_names).then(Vec::new),
+                data: Array2d::zeros(data.n_rows(), 0),
+                rows: None,
+            }
+        };
+        if self.has_intercept {
+            let names = names.map(|mut names| {
+                names.insert(0, "Intercept".to_string());
+                names
+            });
+            let intercept = Array2d::ones(mm.n_rows(), 1);
+            let mm = timing!(intercept.concatenate_cols(mm)?, "intercept");
+            Ok(ModelMatrix {
+                names,
+                data: mm,
+                rows: None,
+            })
+        } else {
+            Ok(ModelMatrix {
+                names,
+                data: mm,
+                rows: None,
+            })
+        }
+    }
+
+    fn design_info_impl(
+        &self,
+        data: &impl DataSource,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<DesignInfo> {
+        match self.complete_rows(data, false)? {
+            Some(rows) => self.design_info_predictors(&RowSubset::new(data, rows), states),
+            None => self.design_info_predictors(data, states),
+        }
+    }
+
+    fn design_info_predictors(
+        &self,
+        data: &impl DataSource,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<DesignInfo> {
+        let mut column_names = vec![];
+        let mut terms = vec![];
+        if self.has_intercept {
+            column_names.push("Intercept".to_string());
+            terms.push(TermInfo {
+                name: "Intercept".to_string(),
+                factors: vec![],
+                columns: 0..1,
+                subterms: vec![SubtermInfo {
+                    factors: vec![],
+                    columns: 0..1,
+                }],
+            });
+        }
+        if let Some(predictors) = &self.predictors {
+            let (names, term_infos) = predictors.term_infos(
+                data,
+                states,
+                self.unseen_levels,
+                self.tolerance,
+                &self.na_level,
+                column_names.len(),
+            )?;
+            column_names.extend(names);
+            terms.extend(term_infos);
+        }
+        Ok(DesignInfo {
+            column_names,
+            terms,
+        })
+    }
+
+    fn eval_sparse_impl(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<SparseModelMatrix> {
+        match self.complete_rows(data, false)? {
+            Some(rows) => {
+                let data = RowSubset::new(data, rows);
+                let model_matrix = self.eval_predictors_sparse(&data, skip_names, states)?;
+                Ok(SparseModelMatrix {
+                    rows: Some(data.into_rows()),
+                    ..model_matrix
+                })
+            }
+            None => self.eval_predictors_sparse(data, skip_names, states),
+        }
+    }
+
+    fn eval_predictors_sparse(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<SparseModelMatrix> {
+        let (mut names, mut cols) = match &self.predictors {
+            Some(predictors) => predictors.eval_sparse(
+                data,
+                skip_names,
+                states,
+                self.unseen_levels,
+                self.tolerance,
+                &self.na_level,
+            )?,
+            None => (vec![], vec![]),
+        };
+        if self.has_intercept {
+            names.insert(0, "Intercept".to_string());
+            cols.insert(0, SparseCol::ones(data.n_rows()));
+        }
+        Ok(SparseModelMatrix {
+            names: (!skip_names).then_some(names),
+            data: CscMatrix::from_cols(data.n_rows(), cols),
+            rows: None,
+        })
+    }
+
+    fn eval_with_response_impl(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+        states: Option<&HashMap<String, FactorState>>,
+    ) -> RoResult<(ModelMatrix, ModelMatrix)> {
+        let response = self
+            .response
+            .as_ref()
+            .ok_or_else(|| roerr!("formula does not have a response, i.e., a left-hand side"))?;
+        // rows are dropped consistently from response and model matrix
+        match self.complete_rows(data, true)? {
+            Some(rows) => {
+                let data = RowSubset::new(data, rows);
+                let response = response.eval(
+                    &data,
+                    skip_names,
+                    states,
+                    self.unseen_levels,
+                    self.tolerance,
+                    &self.na_level,
+                )?;
+                let model_matrix = self.eval_predictors(&data, skip_names, states)?;
+                let rows = data.into_rows();
+                Ok((
+                    ModelMatrix {
+                        rows: Some(rows.clone()),
+                        ..response
+                    },
+                    ModelMatrix {
+                        rows: Some(rows),
+                        ..model_matrix
+                    },
+                ))
+            }
+            None => Ok((
+                response.eval(
+                    data,
+                    skip_names,
+                    states,
+                    self.unseen_levels,
+                    self.tolerance,
+                    &self.na_level,
+                )?,
+                self.eval_predictors(data, skip_names, states)?,
+            )),
+        }
+    }
+}
+
+/// A formula that has been fitted to training data. The levels of categoricals and other
+/// data-dependent parameters are stored such that new data results in the same columns.
+/// ```rust
+/// # use std::error::Error;
+/// use rormula_rs::{data::SeparatedData, expression::Wilkinson};
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let cats = |c: &[&str]| c.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+/// let training = SeparatedData::new(
+///     vec![],
+///     vec![],
+///     vec!["x".to_string()],
+///     vec![cats(&["a", "b", "c"])],
+/// )?;
+/// let spec = Wilkinson::parse("x")?.fit(&training)?;
+/// let batch = SeparatedData::new(vec![], vec![], vec!["x".to_string()], vec![cats(&["b"])])?;
+/// let model_matrix = spec.transform(&batch, false)?;
+/// let names = ["Intercept", "x_a", "x_b"].map(|n| n.to_string()).to_vec();
+/// assert_eq!(model_matrix.names, Some(names));
+/// assert_eq!(model_matrix.data.get(0, 2), 1.0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ModelSpec {
+    formula: Wilkinson,
+    /// Fitted parameters by variable name
+    states: HashMap<String, FactorState>,
+}
+impl ModelSpec {
+    pub fn formula(&self) -> &Wilkinson {
+        &self.formula
+    }
+
+    pub fn states(&self) -> &HashMap<String, FactorState> {
+        &self.states
+    }
+
+    /// Evaluates the right-hand side of the formula with the fitted parameters.
+    pub fn transform(&self, data: &impl DataSource, skip_names: bool) -> RoResult<ModelMatrix> {
+        self.formula.eval_impl(data, skip_names, Some(&self.states))
+    }
+
+    /// Evaluates the right-hand side of the formula with the fitted parameters into a sparse
+    /// matrix, see [`Wilkinson::eval_sparse`].
+    pub fn transform_sparse(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+    ) -> RoResult<SparseModelMatrix> {
+        self.formula
+            .eval_sparse_impl(data, skip_names, Some(&self.states))
+    }
+
+    /// Describes the columns of the model matrix with the fitted parameters, see
+    /// [`Wilkinson::design_info`].
+    pub fn design_info(&self, data: &impl DataSource) -> RoResult<DesignInfo> {
+        self.formula.design_info_impl(data, Some(&self.states))
+    }
+
+    /// Evaluates both sides of the formula with the fitted parameters.
+    pub fn transform_with_response(
+        &self,
+        data: &impl DataSource,
+        skip_names: bool,
+    ) -> RoResult<(ModelMatrix, ModelMatrix)> {
+        self.formula
+            .eval_with_response_impl(data, skip_names, Some(&self.states))
+    }
+}
+
+#[test]
+fn test_response() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[
+            ("y1", &[1.0, 2.0, 3.0]),
+            ("y2", &[4.0, 5.0, 6.0]),
+            ("a", &[0.5, 1.5, 2.5]),
+        ],
+        &[("c", &["x", "y", "x"])],
+    );
+    let formula = Wilkinson::parse("y1 + y2 ~ a + c - 1").unwrap();
+    assert!(formula.has_response());
+    let (response, model_matrix) = formula.eval_with_response(&data, false).unwrap();
+    assert_eq!(response.names.unwrap(), vec!["y1", "y2"]);
+    assert_eq!(
+        response.data,
+        Array2d::from_iter([1.0, 4.0, 2.0, 5.0, 3.0, 6.0].iter(), 3, 2).unwrap()
+    );
+    assert_eq!(model_matrix.names.unwrap(), vec!["a", "c_x", "c_y"]);
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([0.5, 1.0, 0.0, 1.5, 0.0, 1.0, 2.5, 1.0, 0.0].iter(), 3, 3).unwrap()
+    );
+
+    let formula = Wilkinson::parse("~ a").unwrap();
+    assert!(!formula.has_response());
+    assert!(formula.eval_with_response(&data, false).is_err());
+    let model_matrix = formula.eval(&data, true).unwrap();
+    assert!(model_matrix.names.is_none());
+    assert_eq!(model_matrix.data.n_cols(), 2);
+
+    let formula = Wilkinson::parse("y1 ~ 1").unwrap();
+    let (response, model_matrix) = formula.eval_with_response(&data, false).unwrap();
+    assert_eq!(response.data.n_cols(), 1);
+    assert_eq!(model_matrix.names.unwrap(), vec!["Intercept"]);
+    assert_eq!(model_matrix.data, Array2d::ones(3, 1));
+
+    assert!(Wilkinson::parse("y ~ a ~ b").is_err());
+    let model_matrix = Wilkinson::parse("y1 ~ a + a")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["Intercept", "a"]);
+    let model_matrix = Wilkinson::parse_literal("y1 ~ a + a")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["Intercept", "a", "a"]);
+    let formula = Wilkinson::parse("y ~ z").unwrap();
+    assert!(formula.eval(&data, false).is_err());
+}
+
+#[test]
+fn test_identity() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[("temp", &[1.0, 2.0, 3.0]), ("pressure", &[1.0, 4.0, 9.0])],
+        &[],
+    );
+    let formula = Wilkinson::parse("temp + I(temp^2) + I(sqrt(pressure)/2) - 1").unwrap();
+    let model_matrix = formula.eval(&data, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["temp", "temp^2", "sqrt(pressure)/2"]
+    );
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([1.0, 1.0, 0.5, 2.0, 4.0, 1.0, 3.0, 9.0, 1.5].iter(), 3, 3).unwrap()
+    );
+    let model_matrix = Wilkinson::parse("I(temp*pressure):temp")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "temp*pressure:temp"]
+    );
+    assert_eq!(model_matrix.data.get(2, 1), 81.0);
+    let model_matrix = Wilkinson::parse("I(where(temp > 1, temp - 1, 0)) - 1")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["where(temp>1,temp-1,0)"]);
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([0.0, 1.0, 2.0].iter(), 3, 1).unwrap()
+    );
+    // comparisons use the tolerance of the formula, also when transforming new data
+    let formula = Wilkinson::parse("I(where(temp >= 2.1, 1, 0)) - 1").unwrap();
+    assert_eq!(
+        formula.eval(&data, true).unwrap().data,
+        Array2d::from_iter([0.0, 0.0, 1.0].iter(), 3, 1).unwrap()
+    );
+    let spec = formula
+        .with_tolerance(Tolerance::Absolute(0.2))
+        .fit(&data)
+        .unwrap();
+    assert_eq!(
+        spec.transform(&data, true).unwrap().data,
+        Array2d::from_iter([0.0, 1.0, 1.0].iter(), 3, 1).unwrap()
+    );
+    // names do not depend on whitespace
+    let model_matrix = Wilkinson::parse("I(temp ^ 2) + I( sqrt(pressure) / 2 ) - 1")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["temp^2", "sqrt(pressure)/2"]
+    );
+    assert!(Wilkinson::parse("I(temp").is_err());
+    assert!(
+        Wilkinson::parse("I(temp|temp>1)")
+            .unwrap()
+            .eval(&data, false)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_contrast() {
+    use crate::data::separated_data;
+    let data = separated_data(
+        &[("a", &[1.0, 2.0, 3.0, 4.0])],
+        &[("x", &["ctrl", "t1", "t2", "t1"])],
+    );
+    let eval = |formula| {
+        Wilkinson::parse(formula)
+            .unwrap()
+            .eval(&data, false)
+            .unwrap()
+    };
+    let model_matrix = eval("C(x, Treatment(reference=\"ctrl\"))");
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "x_t1", "x_t2"]
+    );
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter(
+            [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0].iter(),
+            4,
+            3
+        )
+        .unwrap()
+    );
+    let model_matrix = eval("a:C(x, Sum) - 1");
+    // without intercept, the categorical needs full rank
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["a:x_mean", "a:x_S.ctrl", "a:x_S.t1"]
+    );
+    assert_eq!(model_matrix.data.get(2, 0), 3.0);
+    assert_eq!(model_matrix.data.get(2, 1), -3.0);
+    assert_eq!(model_matrix.data.get(3, 2), 4.0);
+    assert_eq!(eval("C(x)").names, eval("x").names);
+    assert!(
+        Wilkinson::parse("C(a, Sum)")
+            .unwrap()
+            .eval(&data, false)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_fit_transform() {
+    use crate::data::{separated_data, to_strings};
+    let training = separated_data(
+        &[("y", &[1.0, 2.0, 3.0, 4.0]), ("t", &[0.1, 0.2, 0.3, 0.4])],
+        &[("x", &["a", "b", "c", "a"]), ("z", &["u", "v", "u", "v"])],
+    );
+    let formula = Wilkinson::parse("y ~ t + C(x, Sum) + x:z").unwrap();
+    let spec = formula.fit(&training).unwrap();
+    assert_eq!(
+        spec.states().get("x"),
+        Some(&FactorState::Levels(to_strings(&["a", "b", "c"])))
+    );
+    assert_eq!(
+        spec.states().get("C(x, Sum)"),
+        Some(&FactorState::Levels(to_strings(&["a", "b", "c"])))
+    );
+    let (response, model_matrix) = spec.transform_with_response(&training, false).unwrap();
+    assert_eq!(
+        (response.clone(), model_matrix.clone()),
+        formula.eval_with_response(&training, false).unwrap()
+    );
+
+    // a batch that contains only a subset of the levels results in the same columns
+    let batch = separated_data(
+        &[("t", &[0.2, 0.2])],
+        &[("x", &["b", "b"]), ("z", &["v", "v"])],
+    );
+    assert!(formula.eval(&batch, false).unwrap().names != model_matrix.names);
+    let batch_matrix = spec.transform(&batch, false).unwrap();
+    assert_eq!(batch_matrix.names, model_matrix.names);
+    assert_eq!(batch_matrix.data.n_cols(), model_matrix.data.n_cols());
+    for col in 0..model_matrix.data.n_cols() {
+        assert_eq!(batch_matrix.data.get(0, col), model_matrix.data.get(1, col));
+    }
+
+    let unknown = separated_data(&[("t", &[0.5])], &[("x", &["d"]), ("z", &["v"])]);
+    assert!(spec.transform(&unknown, false).is_err());
+}
+
+#[test]
+fn test_declared_levels() {
+    use crate::data::{separated_data, to_strings};
+    let single_run = |x: &str| {
+        separated_data(&[("t", &[1.0])], &[("x", &[x])])
+            .with_levels("x", to_strings(&["ctrl", "b", "a"]))
+            .unwrap()
+    };
+    let formula = Wilkinson::parse("t + x").unwrap();
+    // the order of the declared levels is kept and the last level is the reference
+    let model_matrix = formula.eval(&single_run("b"), false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "t", "x_ctrl", "x_b"]
+    );
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([1.0, 1.0, 0.0, 1.0].iter(), 1, 4).unwrap()
+    );
+    let model_matrix = formula.eval(&single_run("a"), false).unwrap();
+    assert_eq!(model_matrix.data.n_cols(), 4);
+    let model_matrix = Wilkinson::parse("C(x, Treatment(reference=\"ctrl\")) - 1")
+        .unwrap()
+        .eval(&single_run("a"), false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["x_ctrl", "x_b", "x_a"]);
+    let err = formula.eval(&single_run("d"), false).unwrap_err();
+    assert!(err.msg().contains('x') && err.msg().contains('d'));
+    let data = single_run("a");
+    assert!(data.clone().with_levels("t", to_strings(&["a"])).is_err());
+    assert!(data.with_levels("x", to_strings(&["a", "a"])).is_err());
+}
+
+#[test]
+fn test_unseen_levels() {
+    use crate::data::{separated_data, to_strings};
+    let training = separated_data(&[], &[("x", &["a", "b", "c"])]);
+    let batch = separated_data(&[], &[("x", &["b", "d"])]);
+    let formula = Wilkinson::parse("x").unwrap();
+    let err = formula
+        .fit(&training)
+        .unwrap()
+        .transform(&batch, false)
+        .unwrap_err();
+    assert!(err.msg().contains("column x") && err.msg().contains("level d"));
+
+    // zeros are the reference level of the reduced-rank treatment coding
+    let err = formula
+        .clone()
+        .with_unseen_levels(UnseenLevels::Zeros)
+        .fit(&training)
+        .unwrap()
+        .transform(&batch, false)
+        .unwrap_err();
+    assert!(err.msg().contains("level d") && err.msg().contains("reference level"));
+    let spec = Wilkinson::parse("x - 1")
+        .unwrap()
+        .with_unseen_levels(UnseenLevels::Zeros)
+        .fit(&training)
+        .unwrap();
+    let model_matrix = spec.transform(&batch, false).unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["x_a", "x_b", "x_c"]);
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([0.0, 1.0, 0.0, 0.0, 0.0, 0.0].iter(), 2, 3).unwrap()
+    );
+    let spec = Wilkinson::parse("C(x, Sum)")
+        .unwrap()
+        .with_unseen_levels(UnseenLevels::Zeros)
+        .fit(&training)
+        .unwrap();
+    let model_matrix = spec.transform(&batch, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "x_S.a", "x_S.b"]
+    );
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([1.0, 0.0, 1.0, 1.0, 0.0, 0.0].iter(), 2, 3).unwrap()
+    );
+
+    let spec = formula
+        .with_unseen_levels(UnseenLevels::Other)
+        .fit(&training)
+        .unwrap();
+    assert_eq!(
+        spec.states().get("x"),
+        Some(&FactorState::Levels(to_strings(&["a", "b", "c"])))
+    );
+    let model_matrix = spec.transform(&batch, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "x_a", "x_b", "x_other"]
+    );
+    assert_eq!(
+        model_matrix.data,
+        Array2d::from_iter([1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0].iter(), 2, 4).unwrap()
+    );
+    // without levels known in advance, there is no bucket
+    let model_matrix = spec.formula().eval(&batch, false).unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["Intercept", "x_b"]);
+    // a real level other would silently collect the unseen values
+    let training = separated_data(&[], &[("x", &["a", "other", "b"])]);
+    let err = spec.formula().fit(&training).unwrap_err();
+    assert_eq!(
+        err.msg(),
+        "column x, level other collides with the level for unseen values"
+    );
+}
+
+#[test]
+fn test_na_action() {
+    use crate::data::{separated_data, to_strings};
+    let data = separated_data(
+        &[
+            ("y", &[1.0, 2.0, f64::NAN, 4.0]),
+            ("t", &[0.1, f64::NAN, 0.3, 0.4]),
+        ],
+        &[("x", &["a", "b", "a", "c"])],
+    )
+    .with_missing("x", &[false, false, false, true])
+    .unwrap();
+    let formula = Wilkinson::parse("y ~ t + x").unwrap();
+    let model_matrix = formula.eval(&data, false).unwrap();
+    assert!(model_matrix.rows.is_none());
+    assert!(model_matrix.data.get(1, 1).is_nan());
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["Intercept", "t", "x_NA", "x_a"]
+    );
+
+    let formula = formula.with_na_action(NaAction::Drop);
+    let model_matrix = formula.eval(&data, false).unwrap();
+    assert_eq!(model_matrix.rows, Some(vec![0, 2]));
+    assert_eq!(model_matrix.names.unwrap(), vec!["Intercept", "t"]);
+    // rows with a missing response are dropped as well
+    let (response, model_matrix) = formula.eval_with_response(&data, false).unwrap();
+    assert_eq!(response.rows, Some(vec![0]));
+    assert_eq!(model_matrix.rows, Some(vec![0]));
+    assert_eq!(response.data.n_rows(), 1);
+    assert_eq!(model_matrix.data.n_rows(), 1);
+    let spec = formula.fit(&data).unwrap();
+    assert_eq!(
+        spec.states().get("x"),
+        Some(&FactorState::Levels(to_strings(&["a"])))
+    );
+
+    let formula = Wilkinson::parse("I(2*t) + x")
+        .unwrap()
+        .with_na_action(NaAction::Raise);
+    let err = formula.eval(&data, false).unwrap_err();
+    assert_eq!(err.msg(), "missing value in column t in row 1");
+    let err = Wilkinson::parse("y ~ 1")
+        .unwrap()
+        .with_na_action(NaAction::Raise)
+        .eval_with_response(&data, false)
+        .unwrap_err();
+    assert_eq!(err.msg(), "missing value in column y in row 2");
+
+    // the string NA is a level like any other
+    let data = separated_data(&[], &[("region", &["EU", "NA", "APAC"])]);
+    for na_action in [NaAction::Raise, NaAction::Drop] {
+        let formula = Wilkinson::parse("region")
+            .unwrap()
+            .with_na_action(na_action);
+        let model_matrix = formula.eval(&data, false).unwrap();
+        assert_eq!(model_matrix.data.n_rows(), 3);
+        assert_eq!(
+            model_matrix.names.unwrap(),
+            vec!["Intercept", "region_APAC", "region_EU"]
+        );
+    }
+    let data = data.with_missing("region", &[false, false, true]).unwrap();
+    let err = Wilkinson::parse("region")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap_err();
+    assert_eq!(
+        err.msg(),
+        "column region, missing values collide with the level NA, choose another level for \
+         missing values"
+    );
+    // the level of missing values is kept when transforming new data
+    let spec = Wilkinson::parse("region - 1")
+        .unwrap()
+        .with_na_level("missing")
+        .fit(&data)
+        .unwrap();
+    let model_matrix = spec.transform(&data, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec!["region_EU", "region_NA", "region_missing"]
+    );
+    assert_eq!(model_matrix.data.get(1, 1), 1.0);
+    assert_eq!(model_matrix.data.get(2, 2), 1.0);
+    let formula = Wilkinson::parse("region")
+        .unwrap()
+        .with_na_action(NaAction::Drop);
+    assert_eq!(formula.eval(&data, false).unwrap().rows, Some(vec![0, 1]));
+}
+
+#[test]
+fn test_poly() {
+    use crate::data::separated_data;
+    let data = |temp: Vec<f64>| separated_data(&[("temp", &temp)], &[]);
+    let training = data(vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+    let formula = Wilkinson::parse("poly(temp, 3)").unwrap();
+    let model_matrix = formula.eval(&training, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec![
+            "Intercept",
+            "poly(temp,3)[1]",
+            "poly(temp,3)[2]",
+            "poly(temp,3)[3]"
+        ]
+    );
+    // the columns are orthonormal and orthogonal to the intercept
+    for i in 0..4 {
+        for j in 0..4 {
+            let dot = (0..5)
+                .map(|row| model_matrix.data.get(row, i) * model_matrix.data.get(row, j))
+                .sum::<f64>();
+            let expected = match (i, j) {
+                (0, 0) => 5.0,
+                _ if i == j => 1.0,
+                _ => 0.0,
+            };
+            assert!((dot - expected).abs() < 1e-12);
+        }
+    }
+    // new data is transformed with the coefficients of the training data
+    let spec = formula.fit(&training).unwrap();
+    assert!(matches!(
+        spec.states().get("poly(temp, 3)"),
+        Some(FactorState::Poly { .. })
+    ));
+    let batch = spec.transform(&data(vec![2.0]), false).unwrap();
+    for col in 0..4 {
+        assert!((batch.data.get(0, col) - model_matrix.data.get(1, col)).abs() < 1e-12);
+    }
+    assert!(formula.eval(&data(vec![2.0]), false).is_err());
+    assert!(
+        Wilkinson::parse("poly(temp)")
+            .unwrap()
+            .eval(&training, false)
+            .is_err()
+    );
+    assert!(
+        Wilkinson::parse("poly(temp, -1)")
+            .unwrap()
+            .eval(&training, false)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_splines() {
+    use crate::data::separated_data;
+    let data = |temp: Vec<f64>| separated_data(&[("temp", &temp)], &[]);
+    let training = data((0..=20).map(|i| i as f64).collect());
+    let formula = Wilkinson::parse("bs(temp, df=5) + ns(temp, 3) + cr(temp, df=4)").unwrap();
+    let model_matrix = formula.eval(&training, false).unwrap();
+    let names = model_matrix.names.unwrap();
+    assert_eq!(names.len(), 13);
+    assert_eq!(names[1], "bs(temp,df=5)[1]");
+    assert_eq!(names[6], "ns(temp,3)[1]");
+    assert_eq!(names[12], "cr(temp,df=4)[4]");
+
+    // new data is transformed with the knots of the training data
+    let spec = formula.fit(&training).unwrap();
+    assert!(matches!(
+        spec.states().get("bs(temp, df=5)"),
+        Some(FactorState::Spline(_))
+    ));
+    let batch = spec.transform(&data(vec![7.0, 13.0]), false).unwrap();
+    for col in 0..13 {
+        assert!((batch.data.get(0, col) - model_matrix.data.get(7, col)).abs() < 1e-12);
+        assert!((batch.data.get(1, col) - model_matrix.data.get(13, col)).abs() < 1e-12);
+    }
+    for invalid in [
+        "bs(temp)",
+        "bs(temp, df=1)",
+        "bs(temp, df=5, knots=3)",
+        "cr(temp, df=4, constraints=\"none\")",
+    ] {
+        assert!(
+            Wilkinson::parse(invalid)
+                .unwrap()
+                .eval(&training, false)
+                .is_err()
+        );
+    }
+}
+
+#[test]
+fn test_affine() {
+    use crate::data::separated_data;
+    let data = |temp: Vec<f64>| separated_data(&[("temp", &temp)], &[]);
+    let training = data(vec![100.0, 150.0, 200.0, f64::NAN]);
+    let formula =
+        Wilkinson::parse("center(temp) + scale(temp) + standardize(temp) + coded(temp) - 1")
+            .unwrap();
+    let model_matrix = formula.eval(&training, false).unwrap();
+    assert_eq!(
+        model_matrix.names.unwrap(),
+        vec![
+            "center(temp)",
+            "scale(temp)",
+            "standardize(temp)",
+            "coded(temp)"
+        ]
+    );
+    let expected = [
+        [-50.0, -1.0, -1.0, -1.0],
+        [0.0, 0.0, 0.0, 0.0],
+        [50.0, 1.0, 1.0, 1.0],
+    ];
+    for (row, expected_row) in expected.iter().enumerate() {
+        for (col, e) in expected_row.iter().enumerate() {
+            assert!((model_matrix.data.get(row, col) - e).abs() < 1e-12);
+        }
+    }
+    assert!(model_matrix.data.get(3, 0).is_nan());
+
+    // new data is transformed with the parameters of the training data
+    let spec = formula.fit(&training).unwrap();
+    assert_eq!(
+        spec.states().get("coded(temp)"),
+        Some(&FactorState::Affine {
+            shift: 150.0,
+            scale: 50.0
+        })
+    );
+    let batch = spec.transform(&data(vec![175.0]), false).unwrap();
+    assert!((batch.data.get(0, 0) - 25.0).abs() < 1e-12);
+    assert!((batch.data.get(0, 3) - 0.5).abs() < 1e-12);
+
+    let model_matrix = Wilkinson::parse("coded(temp, 0, 400) - 1")
+        .unwrap()
+        .eval(&training, false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["coded(temp,0,400)"]);
+    assert!((model_matrix.data.get(0, 0) + 0.5).abs() < 1e-12);
+    for invalid in ["coded(temp, 1, 1)", "coded(temp, low)", "center(temp, 1)"] {
+        assert!(
+            Wilkinson::parse(invalid)
+                .unwrap()
+                .eval(&training, false)
+                .is_err()
+        );
+    }
+    assert!(
+        Wilkinson::parse("standardize(temp)")
+            .unwrap()
+            .eval(&data(vec![1.0, 1.0]), false)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_sparse() {
+    use crate::{array::ColMajor, data::separated_data};
+    let data = separated_data(
+        &[
+            ("t", &[0.5, 0.0, 2.0, 3.0, 1.0]),
+            ("u", &[1.0, 2.0, 0.0, 1.0, 5.0]),
+        ],
+        &[
+            ("x", &["a", "b", "c", "a", "c"]),
+            ("z", &["p", "p", "q", "q", "p"]),
+        ],
+    );
+    for formula in [
+        "x*z",
+        "0 + x:z",
+        "t*x + u",
+        "C(x, Sum):t - 1",
+        "(t + u)^^2 + I(t * u) + x:z:t",
+        "z + poly(t, 2):x",
+    ] {
+        let formula = Wilkinson::parse(formula).unwrap();
+        let dense = formula.eval(&data, false).unwrap();
+        let sparse = formula.eval_sparse(&data, false).unwrap();
+        assert_eq!(sparse.names, dense.names);
+        assert_eq!(sparse.data.to_dense::<ColMajor>(), dense.data.clone());
+        // only non-zeros are stored
+        assert!(sparse.data.data().iter().all(|x| *x != 0.0));
+    }
+    let formula = Wilkinson::parse("x:z - 1").unwrap();
+    let sparse = formula.eval_sparse(&data, true).unwrap();
+    assert_eq!(sparse.names, None);
+    assert_eq!(sparse.data.n_cols(), 6);
+    assert_eq!(sparse.data.nnz(), 5);
+
+    // NaN is propagated into interactions like in the dense evaluation
+    let data = separated_data(
+        &[
+            ("t", &[f64::NAN, 0.0, 2.0, 3.0, 1.0]),
+            ("u", &[1.0, 2.0, 0.0, f64::INFINITY, 5.0]),
+        ],
+        &[
+            ("x", &["a", "b", "c", "a", "c"]),
+            ("z", &["p", "p", "q", "q", "p"]),
+        ],
+    );
+    let same = |a: f64, b: f64| a == b || (a.is_nan() && b.is_nan());
+    for formula in ["t:x", "x*z:t + u:z", "(t + u)^^2:x + I(t * u)"] {
+        let formula = Wilkinson::parse(formula).unwrap();
+        let dense = formula.eval(&data, false).unwrap();
+        let sparse = formula.eval_sparse(&data, false).unwrap();
+        assert_eq!(sparse.names, dense.names);
+        let sparse = sparse.data.to_dense::<ColMajor>();
+        assert!(
+            sparse
+                .iter()
+                .zip(dense.data.iter())
+                .all(|(a, b)| same(a, b))
+        );
+    }
+
+    // sparse transform of new data with dropped rows
+    let spec = Wilkinson::parse("x + t")
+        .unwrap()
+        .with_na_action(NaAction::Drop)
+        .fit(&data)
+        .unwrap();
+    let batch = separated_data(&[("t", &[f64::NAN, 4.0])], &[("x", &["a", "b"])]);
+    let sparse = spec.transform_sparse(&batch, false).unwrap();
+    let dense = spec.transform(&batch, false).unwrap();
+    assert_eq!(sparse.rows, Some(vec![1]));
+    assert_eq!(sparse.names, dense.names);
+    assert_eq!(sparse.data.to_dense::<ColMajor>(), dense.data);
+}
+
+#[test]
+fn test_design_info() {
+    use super::{Contrast, FactorEncoding};
+    use crate::data::{separated_data, to_strings};
+    let data = separated_data(
+        &[("t", &[0.5, 1.0, 2.0, 3.0])],
+        &[("x", &["a", "b", "c", "a"]), ("z", &["p", "p", "q", "q"])],
+    );
+    let formula = Wilkinson::parse("poly(t, 2) + C(x, Sum):t + x:z").unwrap();
+    let info = formula.design_info(&data).unwrap();
+    let model_matrix = formula.eval(&data, false).unwrap();
+    assert_eq!(Some(info.column_names.clone()), model_matrix.names);
+    assert_eq!(
+        info.term_names(),
+        vec!["Intercept", "poly(t, 2)", "C(x, Sum):t", "x:z"]
+    );
+    assert_eq!(info.term_columns("Intercept"), Some(0..1));
+    assert_eq!(info.term_columns("poly(t, 2)"), Some(1..3));
+    let term = info.term("C(x, Sum):t").unwrap();
+    assert_eq!(term.factors, to_strings(&["C(x, Sum)", "t"]));
+    assert_eq!(term.columns, 3..6);
+    assert_eq!(
+        term.subterms[0].factors[0].encoding,
+        FactorEncoding::Categorical {
+            contrast: Contrast::Sum,
+            levels: to_strings(&["a", "b", "c"]),
+            full_rank: true
+        }
+    );
+    assert_eq!(
+        term.subterms[0].factors[1].encoding,
+        FactorEncoding::Numerical { n_cols: 1 }
+    );
+    // x:z without main effects is split into subterms
+    let term = info.term("x:z").unwrap();
+    assert_eq!(term.columns, 6..model_matrix.data.n_cols());
+    assert_eq!(term.subterms.len(), 2);
+    assert_eq!(term.subterms.last().unwrap().columns.end, term.columns.end);
+    assert_eq!(Contrast::Sum.to_string(), "Sum");
+    assert_eq!(
+        Contrast::Treatment {
+            reference: Some("a".to_string())
+        }
+        .to_string(),
+        "Treatment(reference=\"a\")"
+    );
+
+    let spec = Wilkinson::parse("x - 1").unwrap().fit(&data).unwrap();
+    let batch = separated_data(&[], &[("x", &["b"])]);
+    let info = spec.design_info(&batch).unwrap();
+    assert_eq!(info.column_names, to_strings(&["x_a", "x_b", "x_c"]));
+    assert_eq!(info.terms[0].subterms[0].factors[0].encoding.n_cols(), 3);
+}
+
+#[test]
+fn test_from_formula() {
+    use crate::data::separated_data;
+    let data = separated_data(&[("a", &[1.0, 2.0]), ("b", &[3.0, 4.0])], &[]);
+    let formula = Wilkinson::parse("a*b").unwrap();
+    assert_eq!(formula.formula().to_string(), "a + b + a:b");
+    let edited = formula
+        .formula()
+        .clone()
+        .without_terms("b")
+        .unwrap()
+        .with_intercept(false);
+    let model_matrix = Wilkinson::from_formula(edited)
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(model_matrix.names.unwrap(), vec!["a", "a:b"]);
+    assert_eq!(model_matrix.data.get(1, 1), 8.0);
+}
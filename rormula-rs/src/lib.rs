@@ -1,6 +1,8 @@
 pub mod array;
+pub mod data;
 pub mod expression;
 pub mod result;
+pub mod sparse;
 pub use exmex;
 
 #[macro_export]
@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+
+use crate::{
+    array::{Array2d, MemOrder},
+    result::RoResult,
+    roerr,
+};
+
+/// Sparse column with row indices in ascending order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct SparseCol {
+    indices: Vec<usize>,
+    data: Vec<f64>,
+}
+impl SparseCol {
+    pub(crate) fn ones(n_rows: usize) -> Self {
+        SparseCol {
+            indices: (0..n_rows).collect(),
+            data: vec![1.0; n_rows],
+        }
+    }
+
+    /// Keeps only the non-zero values of a dense column. NaN is kept.
+    pub(crate) fn from_dense(col: impl Iterator<Item = f64>) -> Self {
+        let (indices, data) = col.enumerate().filter(|(_, x)| *x != 0.0).unzip();
+        SparseCol { indices, data }
+    }
+
+    /// Keeps only the non-zero values of row indices and values in arbitrary order
+    pub(crate) fn from_entries(mut entries: Vec<(usize, f64)>) -> Self {
+        entries.sort_unstable_by_key(|(row, _)| *row);
+        let (indices, data) = entries.into_iter().filter(|(_, x)| *x != 0.0).unzip();
+        SparseCol { indices, data }
+    }
+
+    /// Componentwise product. Like in the dense evaluation, an implicit zero times NaN or infinity
+    /// is NaN.
+    pub(crate) fn multiply(&self, other: &Self) -> Self {
+        let mut product = SparseCol::default();
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let order = match (self.indices.get(i), other.indices.get(j)) {
+                (Some(row_a), Some(row_b)) => row_a.cmp(row_b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            let (row, x) = match order {
+                Ordering::Less => (self.indices[i], self.data[i] * 0.0),
+                Ordering::Greater => (other.indices[j], 0.0 * other.data[j]),
+                Ordering::Equal => (self.indices[i], self.data[i] * other.data[j]),
+            };
+            if order != Ordering::Greater {
+                i += 1;
+            }
+            if order != Ordering::Less {
+                j += 1;
+            }
+            if x != 0.0 {
+                product.indices.push(row);
+                product.data.push(x);
+            }
+        }
+        product
+    }
+}
+
+/// Sparse matrix in compressed sparse column format like `scipy.sparse.csc_matrix`. The row
+/// indices and values of column `j` are stored at the positions `indptr[j]..indptr[j + 1]` of
+/// `indices` and `data`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CscMatrix {
+    n_rows: usize,
+    indptr: Vec<usize>,
+    indices: Vec<usize>,
+    data: Vec<f64>,
+}
+impl CscMatrix {
+    /// Checks that the pointers and row indices are consistent.
+    pub fn new(
+        n_rows: usize,
+        indptr: Vec<usize>,
+        indices: Vec<usize>,
+        data: Vec<f64>,
+    ) -> RoResult<Self> {
+        if indptr.first() != Some(&0)
+            || indptr.last() != Some(&indices.len())
+            || indices.len() != data.len()
+            || indptr.windows(2).any(|w| w[0] > w[1])
+        {
+            return Err(roerr!(
+                "invalid column pointers {:?} for {} values",
+                indptr,
+                data.len()
+            ));
+        }
+        for w in indptr.windows(2) {
+            let col = &indices[w[0]..w[1]];
+            if col.iter().any(|i| *i >= n_rows) || col.windows(2).any(|i| i[0] >= i[1]) {
+                return Err(roerr!(
+                    "row indices {:?} are not ascending or out of bounds",
+                    col
+                ));
+            }
+        }
+        Ok(CscMatrix {
+            n_rows,
+            indptr,
+            indices,
+            data,
+        })
+    }
+
+    pub(crate) fn from_cols(n_rows: usize, cols: impl IntoIterator<Item = SparseCol>) -> Self {
+        let mut matrix = CscMatrix {
+            n_rows,
+            indptr: vec![0],
+            indices: vec![],
+            data: vec![],
+        };
+        for col in cols {
+            matrix.indices.extend(col.indices);
+            matrix.data.extend(col.data);
+            matrix.indptr.push(matrix.indices.len());
+        }
+        matrix
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.n_rows
+    }
+    pub fn n_cols(&self) -> usize {
+        self.indptr.len() - 1
+    }
+    /// Number of explicitly stored values
+    pub fn nnz(&self) -> usize {
+        self.data.len()
+    }
+    pub fn indptr(&self) -> &[usize] {
+        &self.indptr
+    }
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn get(&self, row_idx: usize, col_idx: usize) -> f64 {
+        let (start, end) = (self.indptr[col_idx], self.indptr[col_idx + 1]);
+        match self.indices[start..end].binary_search(&row_idx) {
+            Ok(pos) => self.data[start + pos],
+            Err(_) => 0.0,
+        }
+    }
+
+    /// Returns `indptr`, `indices`, and `data`.
+    pub fn into_parts(self) -> (Vec<usize>, Vec<usize>, Vec<f64>) {
+        (self.indptr, self.indices, self.data)
+    }
+
+    pub fn to_dense<M: MemOrder>(&self) -> Array2d<M> {
+        let mut dense = Array2d::zeros(self.n_rows, self.n_cols());
+        for col in 0..self.n_cols() {
+            for pos in self.indptr[col]..self.indptr[col + 1] {
+                dense.set(self.indices[pos], col, self.data[pos]);
+            }
+        }
+        dense
+    }
+}
+
+#[test]
+fn test_csc() {
+    use crate::array::ColMajor;
+    let a = SparseCol::from_dense([0.0, 2.0, f64::NAN, 1.0].into_iter());
+    let b = SparseCol::from_dense([1.0, 0.0, 0.0, 3.0].into_iter());
+    let matrix = CscMatrix::from_cols(
+        4,
+        [a.clone(), b.clone(), a.multiply(&b), SparseCol::ones(4)],
+    );
+    assert_eq!(matrix.n_cols(), 4);
+    assert_eq!(matrix.nnz(), 11);
+    assert_eq!(matrix.indptr(), &[0, 3, 5, 7, 11]);
+    assert_eq!(matrix.get(3, 2), 3.0);
+    assert_eq!(matrix.get(1, 2), 0.0);
+    // NaN times an implicit zero is NaN like in the dense product
+    assert!(matrix.get(2, 2).is_nan());
+    let dense = matrix.to_dense::<ColMajor>();
+    assert_eq!(dense.get(1, 0), 2.0);
+    assert_eq!(dense.get(0, 3), 1.0);
+    let (indptr, indices, data) = matrix.clone().into_parts();
+    let reconstructed = CscMatrix::new(4, indptr, indices, data).unwrap();
+    assert_eq!(reconstructed.indices(), matrix.indices());
+    assert_eq!(reconstructed.n_cols(), 4);
+    assert!(CscMatrix::new(2, vec![0, 2], vec![1, 0], vec![1.0, 1.0]).is_err());
+    assert!(CscMatrix::new(2, vec![0, 1], vec![2], vec![1.0]).is_err());
+    assert!(CscMatrix::new(2, vec![0, 2], vec![0], vec![1.0]).is_err());
+}
@@ -1,41 +1,62 @@
 use exmex::Express;
 use rormula_rs::{
     array::{Array2d, ColMajor, MemOrder, RowMajor},
-    expression::{ExprArithmetic, ExprNames, ExprWilkinson, NameValue, Value},
+    data::SeparatedData,
+    expression::{Columns, ExprArithmetic, ExprWilkinson, Value, Wilkinson, WilkinsonValue},
 };
 
 #[test]
 fn test_wilkinson() {
     let v1 = Array2d::from_iter([0.1, 0.2, 0.3].iter(), 3, 1).unwrap();
     let v2 = Array2d::from_iter([0.4, 0.5, 0.6].iter(), 3, 1).unwrap();
-    let n1 = NameValue::Array(vec!["n".to_string()]);
-    let v1 = Value::Array(v1);
-    let n2 = NameValue::Array(vec!["o".to_string()]);
-    let v2 = Value::Array(v2);
+    let v1 = WilkinsonValue::Columns(Columns::new(Some(vec!["n".to_string()]), v1));
+    let v2 = WilkinsonValue::Columns(Columns::new(Some(vec!["o".to_string()]), v2));
     let s = "n+o+n";
     let expr = ExprWilkinson::parse(s).unwrap();
     let ref_arr: Array2d<ColMajor> =
         Array2d::from_iter([0.1, 0.4, 0.1, 0.2, 0.5, 0.2, 0.3, 0.6, 0.3].iter(), 3, 3).unwrap();
     let res = expr.eval_vec(vec![v1, v2]).unwrap();
     match res {
-        Value::Array(a) => {
-            a.iter()
+        WilkinsonValue::Columns(columns) => {
+            columns
+                .data
+                .unwrap()
+                .iter()
                 .zip(ref_arr.iter())
                 .for_each(|(x, y)| assert!((x - y).abs() < 1e-12));
+            // the flat expression evaluates the formula literally
+            assert_eq!(columns.names.unwrap(), vec!["n", "o", "n"]);
+            assert_eq!(columns.n_cols, 3);
         }
-        Value::Error(e) => {
-            println!("{e}");
-            assert!(false)
-        }
-        _ => assert!(false),
-    }
-    let name_expr = ExprNames::parse(s).unwrap();
-    match name_expr.eval(&[n1, n2]).unwrap() {
-        NameValue::Array(names) => {
-            assert_eq!(names, vec!["n", "o", "n"]);
-        }
-        _ => panic!("need names as result"),
+        WilkinsonValue::Error(e) => panic!("{e}"),
+        _ => panic!("need columns as result"),
     }
+
+    // terms of a formula are a set unless we ask for literal evaluation
+    let data = SeparatedData::new(
+        vec!["n".to_string(), "o".to_string()],
+        vec![vec![0.1, 0.2, 0.3], vec![0.4, 0.5, 0.6]],
+        vec![],
+        vec![],
+    )
+    .unwrap();
+    let mm = Wilkinson::parse(&format!("{s} - 1"))
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(mm.names.unwrap(), vec!["n", "o"]);
+    assert_eq!(mm.data.n_cols(), 2);
+    let mm = Wilkinson::parse("n:o + o:n - 1")
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(mm.names.unwrap(), vec!["n:o"]);
+    let mm = Wilkinson::parse_literal(&format!("{s} - 1"))
+        .unwrap()
+        .eval(&data, false)
+        .unwrap();
+    assert_eq!(mm.names.unwrap(), vec!["n", "o", "n"]);
+    assert_eq!(mm.data, ref_arr);
 }
 
 #[test]
@@ -44,11 +65,86 @@ fn test_more_formulas() {
     let formula_str = "(alpha + beta):(gamma + eta)";
     let expr = ExprWilkinson::parse(formula_str).unwrap();
     let vars = (0..cols.len())
-        .map(|_| Value::Array(Array2d::zeros(5, 1)))
+        .map(|_| WilkinsonValue::Columns(Columns::new(None, Array2d::zeros(5, 1))))
         .collect::<Vec<_>>();
     expr.eval_vec(vars).unwrap();
 }
 
+#[test]
+fn test_cross() {
+    let s = "(a+b+c)*d";
+    let vars = [0.1, 0.2, 0.3, 0.4]
+        .iter()
+        .zip(["a", "b", "c", "d"])
+        .map(|(x, n)| {
+            let arr = Array2d::<ColMajor>::from_iter([*x, 2.0 * x].iter(), 2, 1).unwrap();
+            WilkinsonValue::Columns(Columns::new(Some(vec![n.to_string()]), arr))
+        })
+        .collect::<Vec<_>>();
+    let expr = ExprWilkinson::parse(s).unwrap();
+    let columns = match expr.eval_vec(vars).unwrap() {
+        WilkinsonValue::Columns(columns) => columns,
+        _ => panic!("need columns as result"),
+    };
+    #[rustfmt::skip]
+    let ref_arr: Array2d<ColMajor> = Array2d::from_iter([
+        0.1, 0.2, 0.3, 0.4, 0.04, 0.08, 0.12,
+        0.2, 0.4, 0.6, 0.8, 0.16, 0.32, 0.48,
+    ].iter(), 2, 7).unwrap();
+    assert_eq!(columns.data.unwrap(), ref_arr);
+    assert_eq!(
+        columns.names.unwrap(),
+        vec!["a", "b", "c", "d", "a:d", "b:d", "c:d"]
+    );
+    assert_eq!(columns.n_cols, 7);
+    let shapes = (0..4)
+        .map(|_| WilkinsonValue::Columns(Columns::shape(1)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        expr.eval_vec(shapes).unwrap(),
+        WilkinsonValue::Columns(Columns::shape(7))
+    );
+}
+
+#[test]
+fn test_nesting() {
+    let to_strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let data = SeparatedData::new(
+        vec![],
+        vec![],
+        to_strings(&["supplier", "batch"]),
+        vec![
+            to_strings(&["s1", "s1", "s2", "s2"]),
+            to_strings(&["b1", "b2", "b1", "b2"]),
+        ],
+    )
+    .unwrap();
+    // batches are encoded within each supplier
+    let ref_names = vec![
+        "Intercept",
+        "supplier_s1",
+        "supplier_s1:batch_b1",
+        "supplier_s2:batch_b1",
+    ];
+    for formula in ["supplier/batch", "supplier + batch %in% supplier"] {
+        let mm = Wilkinson::parse(formula)
+            .unwrap()
+            .eval(&data, false)
+            .unwrap();
+        assert_eq!(mm.names.unwrap(), ref_names);
+        let ref_arr: Array2d<ColMajor> = Array2d::from_iter(
+            [
+                1.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0,
+            ]
+            .iter(),
+            4,
+            4,
+        )
+        .unwrap();
+        assert_eq!(mm.data, ref_arr);
+    }
+}
+
 #[test]
 fn test_arithmetic() {
     fn test<O: MemOrder>() {
@@ -59,7 +155,7 @@ fn test_arithmetic() {
             .map(|_| Value::Array(Array2d::<O>::ones(5, 1)))
             .collect::<Vec<_>>();
         let res = expr.eval_vec(vars).unwrap();
-        let s_ref = "x * -2.0";
+        let s_ref = "-x * -2.0";
         let expr_ref = ExprArithmetic::parse(s_ref).unwrap();
         let rev_val = expr_ref
             .eval(&[Value::Array(Array2d::<O>::ones(5, 1))])
@@ -94,7 +190,7 @@ fn test_restrict() {
     if let Value::Array(a) = res {
         assert_eq!(a.iter().collect::<Vec<_>>(), vec![0.0; 5]);
     } else {
-        assert!(false);
+        panic!("need array as result");
     }
     let s = "first_var|{second.var}==1.0 - first_var|{second.var}==1.0";
 
@@ -103,11 +199,7 @@ fn test_restrict() {
         .collect::<Vec<_>>();
     let exp = ExprArithmetic::parse(s).unwrap();
     let res = exp.eval_vec(vars).unwrap();
-    if let Value::Error(_) = res {
-        assert!(true);
-    } else {
-        assert!(false);
-    }
+    assert!(matches!(res, Value::Error(_)));
 }
 
 #[test]
@@ -1,10 +1,26 @@
 from collections.abc import Sequence
-from typing import NamedTuple
+from dataclasses import dataclass
+from typing import Literal, NamedTuple
 
 import numpy as np
 import pandas as pd
 
-from .rormula import eval_arithmetic, eval_wilkinson, parse_arithmetic, parse_wilkinson
+from .rormula import (
+    complete_rows_arithmetic,
+    complete_rows_wilkinson,
+    design_info_spec,
+    design_info_wilkinson,
+    eval_arithmetic,
+    eval_wilkinson,
+    eval_wilkinson_sparse,
+    eval_wilkinson_with_response,
+    fit_wilkinson,
+    parse_arithmetic,
+    parse_wilkinson,
+    transform_wilkinson,
+    transform_wilkinson_sparse,
+    transform_wilkinson_with_response,
+)
 
 
 class SeparatedData(NamedTuple):
@@ -14,6 +30,84 @@ class SeparatedData(NamedTuple):
     categorical_data: np.ndarray
 
 
+@dataclass
+class FactorInfo:
+    # variable name of the factor, e.g., `x` or `C(x, Sum)`
+    name: str
+    n_cols: int
+    # contrast, levels, and full rank are None for numerical factors
+    contrast: str | None = None
+    levels: list[str] | None = None
+    full_rank: bool | None = None
+
+    @property
+    def is_categorical(self) -> bool:
+        return self.levels is not None
+
+
+@dataclass
+class SubtermInfo:
+    """Part of a term with its own encoding. Categorical factors that are spanned by
+    lower-order terms are missing."""
+
+    factors: list[FactorInfo]
+    columns: slice
+
+
+@dataclass
+class TermInfo:
+    name: str
+    factors: list[str]
+    columns: slice
+    subterms: list[SubtermInfo]
+
+
+@dataclass
+class DesignInfo:
+    """Describes which term and which encoding of its factors leads to which columns of
+    the model matrix."""
+
+    column_names: list[str]
+    terms: list[TermInfo]
+
+    @classmethod
+    def _from_rust(cls, info) -> "DesignInfo":
+        column_names, terms = info
+        return cls(
+            column_names=column_names,
+            terms=[
+                TermInfo(
+                    name=name,
+                    factors=factors,
+                    columns=slice(*columns),
+                    subterms=[
+                        SubtermInfo(
+                            factors=[FactorInfo(*f) for f in sub_factors],
+                            columns=slice(*sub_columns),
+                        )
+                        for sub_factors, sub_columns in subterms
+                    ],
+                )
+                for name, factors, columns, subterms in terms
+            ],
+        )
+
+    def term(self, name: str) -> TermInfo:
+        for term in self.terms:
+            if term.name == name:
+                return term
+        raise KeyError(f"term {name} not found")
+
+    def term_slices(self) -> dict[str, slice]:
+        return {term.name: term.columns for term in self.terms}
+
+
+def _to_csc_matrix(data, shape):
+    from scipy.sparse import csc_matrix
+
+    return csc_matrix(data, shape=shape)
+
+
 def separate_num_cat(
     data: pd.DataFrame,
 ) -> SeparatedData:
@@ -33,61 +127,297 @@ def separate_num_cat(
     )
 
 
+def _separate(
+    data: pd.DataFrame | SeparatedData, levels: dict[str, list[str]] | None
+) -> tuple[SeparatedData, dict[str, list[str]] | None]:
+    """Separates a data frame and declares the categories of its columns with
+    categorical dtype as levels. Explicitly passed levels take precedence."""
+    if isinstance(data, SeparatedData):
+        return data, levels
+    declared = {
+        col: [str(c) for c in data[col].cat.categories]
+        for col in data.columns
+        if isinstance(data[col].dtype, pd.CategoricalDtype)
+    }
+    declared.update(levels or {})
+    return separate_num_cat(data), declared if len(declared) > 0 else None
+
+
 class Wilkinson:
-    def __init__(self, formula: str):
-        self.ror = parse_wilkinson(formula)
+    def __init__(
+        self,
+        formula: str,
+        literal: bool = False,
+        unseen_levels: Literal["error", "zeros", "other"] = "error",
+        na_action: Literal["raise", "drop", "keep"] = "keep",
+        tolerance: float = 1e-8,
+        tolerance_mode: Literal["relative", "absolute", "exact"] = "relative",
+        na_level: str = "NA",
+    ):
+        """Parses a Wilkinson formula. Repeated terms like in `a + b + a` are used once
+        unless `literal` is set. Values of categoricals that are not among the declared
+        or fitted levels raise an error, are encoded as zeros, or are mapped to the
+        level `other` depending on `unseen_levels`. Zeros are not possible with the
+        reduced-rank treatment coding, where zeros encode the reference level, but with
+        other contrasts or full-rank terms. Missing values raise an error, lead
+        to dropped rows, or are kept depending on `na_action`. Kept missing values of
+        categoricals form the level `na_level`. If the data contains this level as
+        well, another `na_level` needs to be chosen. Comparisons within `I(...)` use
+        `tolerance` like in `Arithmetic`.
+
+        Methods that take data accept `levels`, a dictionary from column names to
+        declared levels whose order is the order of the encoding. The categories of
+        columns with categorical dtype are declared levels as well."""
+        self.ror = parse_wilkinson(
+            formula,
+            literal,
+            unseen_levels,
+            na_action,
+            tolerance,
+            tolerance_mode,
+            na_level,
+        )
+        self.spec = None
+
+    def has_response(self) -> bool:
+        return self.ror.has_response()
+
+    def complete_rows(
+        self, data: pd.DataFrame | SeparatedData, with_response: bool = False
+    ) -> list[int] | None:
+        """Returns the indices of the rows of the data that end up in the model matrix
+        depending on `na_action`, or None if all rows do. With `with_response`, also
+        missing values of the response are considered like in `eval_with_response`."""
+        if not isinstance(data, SeparatedData):
+            data = separate_num_cat(data)
+        return complete_rows_wilkinson(
+            self.ror,
+            data,
+            with_response=with_response,
+        )
 
     def eval(
-        self, data: pd.DataFrame | SeparatedData, skip_names: bool = False
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
     ) -> tuple[list[str], np.ndarray]:
-        if isinstance(data, SeparatedData):
-            numerical_cols, numerical_data, categorical_cols, categorical_data = data
-        else:
-            (
-                numerical_cols,
-                numerical_data,
-                categorical_cols,
-                categorical_data,
-            ) = separate_num_cat(data)
-
+        """Returns the names and the model matrix of the right-hand side of the formula."""
+        data, levels = _separate(data, levels)
         names, resulting_data = eval_wilkinson(
             self.ror,
-            numerical_data,
-            numerical_cols,
-            categorical_data,
-            categorical_cols,
+            data,
+            skip_names=skip_names,
+            levels=levels,
+        )
+        if names is None:
+            names = []
+        return names, resulting_data
+
+    def eval_sparse(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ):
+        """Returns the names and the model matrix as `scipy.sparse.csc_matrix` without
+        materializing the dense matrix. Requires Scipy."""
+        data, levels = _separate(data, levels)
+        names, csc_data, shape = eval_wilkinson_sparse(
+            self.ror,
+            data,
+            skip_names=skip_names,
+            levels=levels,
+        )
+        if names is None:
+            names = []
+        return names, _to_csc_matrix(csc_data, shape)
+
+    def eval_with_response(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ) -> tuple[tuple[list[str], np.ndarray], tuple[list[str], np.ndarray]]:
+        """Returns names and values of the response, i.e., the left-hand side of a formula
+        such as `y ~ a + b`, and names and values of the model matrix."""
+        data, levels = _separate(data, levels)
+        (response_names, response), (names, resulting_data) = (
+            eval_wilkinson_with_response(
+                self.ror,
+                data,
+                skip_names=skip_names,
+                levels=levels,
+            )
+        )
+        if response_names is None:
+            response_names = []
+        if names is None:
+            names = []
+        return (response_names, response), (names, resulting_data)
+
+    def eval_asdf(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ):
+        names, resulting_data = self.eval(data, skip_names=skip_names, levels=levels)
+        index = None
+        if isinstance(data, pd.DataFrame):
+            rows = self.complete_rows(data)
+            if rows is not None:
+                index = data.index[rows]
+        return pd.DataFrame(data=resulting_data, columns=names, index=index)
+
+    def fit(
+        self, data: pd.DataFrame | SeparatedData, levels: dict[str, list[str]] | None = None
+    ) -> "Wilkinson":
+        """Records the levels of categoricals and other data-dependent parameters such that
+        `transform` results in the same columns for new data."""
+        data, levels = _separate(data, levels)
+        self.spec = fit_wilkinson(
+            self.ror,
+            data,
+            levels=levels,
+        )
+        return self
+
+    def design_info(
+        self, data: pd.DataFrame | SeparatedData, levels: dict[str, list[str]] | None = None
+    ) -> DesignInfo:
+        """Describes the columns of the model matrix of the data. The parameters that
+        have been recorded by `fit` are used if there are any."""
+        data, levels = _separate(data, levels)
+        if self.spec is None:
+            design_info, formula = design_info_wilkinson, self.ror
+        else:
+            design_info, formula = design_info_spec, self.spec
+        info = design_info(
+            formula,
+            data,
+            levels=levels,
+        )
+        return DesignInfo._from_rust(info)
+
+    def transform(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ) -> tuple[list[str], np.ndarray]:
+        """Returns the names and the model matrix like `eval` but with the parameters that
+        have been recorded by `fit`."""
+        if self.spec is None:
+            raise ValueError("call fit before transform")
+        data, levels = _separate(data, levels)
+        names, resulting_data = transform_wilkinson(
+            self.spec,
+            data,
             skip_names=skip_names,
+            levels=levels,
         )
         if names is None:
             names = []
         return names, resulting_data
 
-    def eval_asdf(self, data: pd.DataFrame | SeparatedData, skip_names: bool = False):
-        names, resulting_data = self.eval(data, skip_names=skip_names)
-        return pd.DataFrame(data=resulting_data, columns=names)
+    def transform_sparse(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ):
+        """Returns the names and the sparse model matrix like `eval_sparse` but with the
+        parameters that have been recorded by `fit`."""
+        if self.spec is None:
+            raise ValueError("call fit before transform")
+        data, levels = _separate(data, levels)
+        names, csc_data, shape = transform_wilkinson_sparse(
+            self.spec,
+            data,
+            skip_names=skip_names,
+            levels=levels,
+        )
+        if names is None:
+            names = []
+        return names, _to_csc_matrix(csc_data, shape)
+
+    def transform_with_response(
+        self,
+        data: pd.DataFrame | SeparatedData,
+        skip_names: bool = False,
+        levels: dict[str, list[str]] | None = None,
+    ) -> tuple[tuple[list[str], np.ndarray], tuple[list[str], np.ndarray]]:
+        """Returns response and model matrix like `eval_with_response` but with the
+        parameters that have been recorded by `fit`."""
+        if self.spec is None:
+            raise ValueError("call fit before transform")
+        data, levels = _separate(data, levels)
+        (response_names, response), (names, resulting_data) = (
+            transform_wilkinson_with_response(
+                self.spec,
+                data,
+                skip_names=skip_names,
+                levels=levels,
+            )
+        )
+        if response_names is None:
+            response_names = []
+        if names is None:
+            names = []
+        return (response_names, response), (names, resulting_data)
 
 
 class Arithmetic:
-    def __init__(self, formula: str, name: str):
-        self.ror = parse_arithmetic(formula)
+    def __init__(
+        self,
+        formula: str,
+        name: str,
+        na_action: Literal["raise", "drop", "keep"] = "keep",
+        tolerance: float = 1e-8,
+        tolerance_mode: Literal["relative", "absolute", "exact"] = "relative",
+    ):
+        """Parses an arithmetic expression. Rows with NaN in the columns of the
+        expression raise an error, are dropped, or are kept depending on `na_action`.
+        Comparisons like `==` or `!=` consider values equal if their relative or absolute
+        difference is within `tolerance`, or only if they are exactly equal."""
+        self.ror = parse_arithmetic(formula, tolerance, tolerance_mode)
         self.name = name
+        self.na_action = na_action
 
-    def eval(self, data: pd.DataFrame) -> np.ndarray:
-        numerical_cols = data.columns.to_list()
-        numerical_data = data.to_numpy()
-
+    def eval(self, data: pd.DataFrame | SeparatedData) -> np.ndarray:
+        """Evaluates the expression. Columns with object dtype can be compared with
+        string literals, e.g., `catalyst == "Pt"`."""
+        if not isinstance(data, SeparatedData):
+            data = separate_num_cat(data)
         resulting_data = eval_arithmetic(
             self.ror,
-            numerical_data,
-            numerical_cols,
+            data,
+            na_action=self.na_action,
         )
 
         return resulting_data
 
+    def complete_rows(self, data: pd.DataFrame | SeparatedData) -> list[int] | None:
+        """Returns the indices of the rows of the data that are used depending on
+        `na_action`, or None if all rows are used."""
+        if not isinstance(data, SeparatedData):
+            data = separate_num_cat(data)
+        return complete_rows_arithmetic(
+            self.ror,
+            data,
+            na_action=self.na_action,
+        )
+
     def eval_asdf(self, data: pd.DataFrame):
         resulting_data = self.eval(data)
+        rows = self.complete_rows(data)
         if resulting_data.shape[0] == data.shape[0]:
             data[self.name] = resulting_data
+        elif rows is not None and resulting_data.shape[0] == len(rows):
+            data = pd.DataFrame(
+                data=resulting_data, columns=[self.name], index=data.index[rows]
+            )
         else:
             data = pd.DataFrame(data=resulting_data, columns=[self.name])
         return data
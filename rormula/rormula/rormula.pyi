@@ -3,7 +3,10 @@ from typing import NamedTuple
 
 import numpy as np
 
-class Wilkinson: ...
+class Wilkinson:
+    def has_response(self) -> bool: ...
+
+class ModelSpec: ...
 
 class SeparatedData(NamedTuple):
     numerical_cols: list[str]
@@ -11,15 +14,119 @@ class SeparatedData(NamedTuple):
     categorical_cols: list[str]
     categorical_data: np.ndarray
 
-def parse_wilkinson(s: str) -> Wilkinson: ...
+def parse_wilkinson(
+    s: str,
+    literal: bool = False,
+    unseen_levels: str = "error",
+    na_action: str = "keep",
+    tolerance: float = 1e-8,
+    tolerance_mode: str = "relative",
+    na_level: str = "NA",
+) -> Wilkinson: ...
 def eval_wilkinson(
     ror: Wilkinson,
-    numerical_data: np.ndarray,
-    numerical_cols: Sequence[str],
-    cat_data: np.ndarray,
-    cat_cols: Sequence[str],
+    data: SeparatedData,
     skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
 ) -> tuple[list[str] | None, np.ndarray]: ...
+def complete_rows_wilkinson(
+    ror: Wilkinson,
+    data: SeparatedData,
+    with_response: bool = False,
+) -> list[int] | None: ...
+def eval_wilkinson_sparse(
+    ror: Wilkinson,
+    data: SeparatedData,
+    skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    list[str] | None,
+    tuple[np.ndarray, np.ndarray, np.ndarray],
+    tuple[int, int],
+]: ...
+def eval_wilkinson_with_response(
+    ror: Wilkinson,
+    data: SeparatedData,
+    skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    tuple[list[str] | None, np.ndarray],
+    tuple[list[str] | None, np.ndarray],
+]: ...
+def fit_wilkinson(
+    ror: Wilkinson,
+    data: SeparatedData,
+    levels: dict[str, list[str]] | None = None,
+) -> ModelSpec: ...
+def design_info_wilkinson(
+    ror: Wilkinson,
+    data: SeparatedData,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    list[str],
+    list[
+        tuple[
+            str,
+            list[str],
+            tuple[int, int],
+            list[
+                tuple[
+                    list[
+                        tuple[str, int, str | None, list[str] | None, bool | None]
+                    ],
+                    tuple[int, int],
+                ]
+            ],
+        ]
+    ],
+]: ...
+def design_info_spec(
+    spec: ModelSpec,
+    data: SeparatedData,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    list[str],
+    list[
+        tuple[
+            str,
+            list[str],
+            tuple[int, int],
+            list[
+                tuple[
+                    list[
+                        tuple[str, int, str | None, list[str] | None, bool | None]
+                    ],
+                    tuple[int, int],
+                ]
+            ],
+        ]
+    ],
+]: ...
+def transform_wilkinson(
+    spec: ModelSpec,
+    data: SeparatedData,
+    skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[list[str] | None, np.ndarray]: ...
+def transform_wilkinson_sparse(
+    spec: ModelSpec,
+    data: SeparatedData,
+    skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    list[str] | None,
+    tuple[np.ndarray, np.ndarray, np.ndarray],
+    tuple[int, int],
+]: ...
+def transform_wilkinson_with_response(
+    spec: ModelSpec,
+    data: SeparatedData,
+    skip_names: bool = False,
+    levels: dict[str, list[str]] | None = None,
+) -> tuple[
+    tuple[list[str] | None, np.ndarray],
+    tuple[list[str] | None, np.ndarray],
+]: ...
 
 class Arithmetic:
     def has_row_change_op(self) -> bool: ...
@@ -28,9 +135,16 @@ class Arithmetic:
     def binary_reprs(self) -> Sequence[str]: ...
     def unary_reprs(self) -> Sequence[str]: ...
 
-def parse_arithmetic(s: str) -> Arithmetic: ...
+def parse_arithmetic(
+    s: str, tolerance: float = 1e-8, tolerance_mode: str = "relative"
+) -> Arithmetic: ...
 def eval_arithmetic(
     ror: Arithmetic,
-    numerical_data: np.ndarray,
-    numerical_cols: Sequence[str],
+    data: SeparatedData,
+    na_action: str = "keep",
 ) -> np.ndarray: ...
+def complete_rows_arithmetic(
+    ror: Arithmetic,
+    data: SeparatedData,
+    na_action: str = "keep",
+) -> list[int] | None: ...
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
+
 use numpy::{
-    IntoPyArray, PyArray2, PyReadonlyArray2,
-    ndarray::{Array2, ArrayView1, Axis, Dim, concatenate, s},
+    IntoPyArray, PyArray1, PyArray2, PyReadonlyArray2,
+    ndarray::{Array2, ArrayView1, ArrayView2, s},
 };
 use pyo3::{
     exceptions::{PyTypeError, PyValueError},
@@ -9,17 +11,18 @@ use pyo3::{
 };
 pub use rormula_rs::exmex::ExError;
 pub use rormula_rs::exmex::prelude::*;
+use rormula_rs::result::RoErr;
 use rormula_rs::{
-    array::Array2d,
-    expression::{ExprArithmetic, has_row_change_op},
-};
-use rormula_rs::{array::DefaultOrder, result::RoErr};
-use rormula_rs::{
-    expression::{ExprColCount, ExprNames, ExprWilkinson, NameValue, Value},
-    timing,
+    data::{DataSource, NaAction},
+    expression::{
+        DesignInfo, FactorEncoding, FactorInfo, ModelMatrix, ModelSpec, SparseModelMatrix,
+        Tolerance, UnseenLevels,
+    },
 };
+use rormula_rs::{expression::Value, result::RoResult, roerr, timing};
 
-fn ex_to_pyerr(e: ExError) -> PyErr {
+/// Parse errors are type errors like the errors of exmex
+fn parse_err_to_pyerr(e: RoErr) -> PyErr {
     PyTypeError::new_err(e.msg().to_string())
 }
 fn ro_to_pyerr(e: RoErr) -> PyErr {
@@ -38,238 +41,461 @@ fn find_col(cols: &Bound<'_, PyList>, needle: &str) -> Option<usize> {
 }
 
 #[pyfunction]
+#[pyo3(signature = (ror, data, na_action="keep"))]
 fn eval_arithmetic<'py>(
     py: Python<'py>,
     ror: &Arithmetic,
-    numerical_data: PyReadonlyArray2<f64>,
-    numerical_cols: &Bound<'py, PyList>,
+    data: PySeparatedData<'py>,
+    na_action: &str,
 ) -> PyResult<Bound<'py, PyArray2<f64>>> {
-    let numerical_data = numerical_data.as_array();
-    let vars = ror
-        .expr
-        .var_names()
-        .iter()
-        .map(|vn: &String| {
-            if let Some(num_idx) = find_col(numerical_cols, vn) {
-                let s: ArrayView1<'_, f64> = numerical_data.slice(s![.., num_idx]);
-                let n_rows = s.dim();
-                Ok(Value::Array(
-                    Array2d::from_iter(s.into_iter(), n_rows, 1).map_err(ro_to_pyerr)?,
-                ))
-            } else {
-                Err(PyValueError::new_err(format!(
-                    "did not find Variable {vn} in the data"
-                )))
-            }
-        })
-        .collect::<PyResult<Vec<_>>>()?;
-    let vars: Vec<Value<DefaultOrder>> = vars.into_iter().collect();
-
-    if vars.len() != ror.expr.var_names().len() {
-        Err(PyValueError::new_err(
-            "there is a column missing for a variable in the formula",
-        ))
-    } else {
-        let result_data = ror.expr.eval_vec(vars).map_err(ex_to_pyerr)?;
+    let data = data.with_levels(None);
+    let na_action = na_action.parse::<NaAction>().map_err(ro_to_pyerr)?;
+    let (result_data, _) = rormula_rs::expression::eval_arithmetic(&ror.expr, &data, na_action)
+        .map_err(ro_to_pyerr)?;
 
-        match result_data {
-            Value::Array(a) => {
-                let mut pya = Array2::<f64>::ones([a.n_rows(), a.n_cols()]);
-                for col in 0..a.n_cols() {
-                    for row in 0..a.n_rows() {
-                        pya[(row, col)] = a.get(row, col);
-                    }
+    let res = match result_data {
+        Value::Array(a) => {
+            let mut pya = Array2::<f64>::ones([a.n_rows(), a.n_cols()]);
+            for col in 0..a.n_cols() {
+                for row in 0..a.n_rows() {
+                    pya[(row, col)] = a.get(row, col);
                 }
-                let res = pya.into_pyarray(py);
-
-                Ok(res)
             }
-            Value::RowInds(row_inds) => {
-                let mut pya = Array2::<f64>::ones([row_inds.len(), 1]);
-                for row in 0..row_inds.len() {
-                    pya[(row, 0)] = row_inds[row] as f64;
-                }
-                let res = pya.into_pyarray(py);
-                Ok(res)
+            pya.into_pyarray(py)
+        }
+        Value::RowInds { inds: row_inds, .. } => {
+            let mut pya = Array2::<f64>::ones([row_inds.len(), 1]);
+            for row in 0..row_inds.len() {
+                pya[(row, 0)] = row_inds[row] as f64;
             }
-            Value::Scalar(s) => Ok(Array2::<f64>::from_elem((1, 1), s).into_pyarray(py)),
-            Value::Cats(_) => Err(PyValueError::new_err("result cannot be cat".to_string())),
-            Value::Error(e) => Err(PyValueError::new_err(format!("computation failed, {e:?}"))),
+            pya.into_pyarray(py)
+        }
+        Value::Scalar(s) => Array2::<f64>::from_elem((1, 1), s).into_pyarray(py),
+        Value::Cats(_) => return Err(PyValueError::new_err("result cannot be cat".to_string())),
+        Value::Str(s) => {
+            return Err(PyValueError::new_err(format!(
+                "result cannot be string literal {s}"
+            )));
+        }
+        Value::Set(_) => return Err(PyValueError::new_err("result cannot be list".to_string())),
+        Value::Error(e) => {
+            return Err(PyValueError::new_err(format!("computation failed, {e:?}")));
+        }
+    };
+    Ok(res)
+}
+
+/// Indices of the rows that are used by `eval_arithmetic` depending on `na_action`, `None` if
+/// all rows are used
+#[pyfunction]
+#[pyo3(signature = (ror, data, na_action="keep"))]
+fn complete_rows_arithmetic<'py>(
+    ror: &Arithmetic,
+    data: PySeparatedData<'py>,
+    na_action: &str,
+) -> PyResult<Option<Vec<usize>>> {
+    let data = data.with_levels(None);
+    let na_action = na_action.parse::<NaAction>().map_err(ro_to_pyerr)?;
+    na_action
+        .complete_rows(&data, ror.expr.var_names())
+        .map_err(ro_to_pyerr)
+}
+
+/// Columns as separated by `separate_num_cat` in Python, i.e., numerical names, numerical data,
+/// categorical names, and categorical data
+#[derive(FromPyObject)]
+struct PySeparatedData<'py>(
+    Bound<'py, PyList>,
+    PyReadonlyArray2<'py, f64>,
+    Bound<'py, PyList>,
+    PyReadonlyArray2<'py, Py<PyAny>>,
+);
+impl<'py> PySeparatedData<'py> {
+    fn with_levels(&self, levels: Option<HashMap<String, Vec<String>>>) -> PyData<'_, 'py> {
+        PyData {
+            py: self.0.py(),
+            numerical_data: self.1.as_array(),
+            numerical_cols: &self.0,
+            cat_data: self.3.as_array(),
+            cat_cols: &self.2,
+            levels,
         }
     }
 }
 
+/// Numerical and categorical data as passed from Python
+struct PyData<'a, 'py> {
+    py: Python<'py>,
+    numerical_data: ArrayView2<'a, f64>,
+    numerical_cols: &'a Bound<'py, PyList>,
+    cat_data: ArrayView2<'a, Py<PyAny>>,
+    cat_cols: &'a Bound<'py, PyList>,
+    levels: Option<HashMap<String, Vec<String>>>,
+}
+impl DataSource for PyData<'_, '_> {
+    fn n_rows(&self) -> usize {
+        self.numerical_data.nrows()
+    }
+    fn numerical(&self, name: &str) -> RoResult<Option<Vec<f64>>> {
+        Ok(find_col(self.numerical_cols, name).map(|num_idx| {
+            let s: ArrayView1<'_, f64> = self.numerical_data.slice(s![.., num_idx]);
+            timing!(s.to_vec(), "arr from pyarray")
+        }))
+    }
+    fn categorical(&self, name: &str) -> RoResult<Option<Vec<Option<String>>>> {
+        find_col(self.cat_cols, name)
+            .map(|cat_idx| {
+                let col: ArrayView1<'_, Py<PyAny>> = self.cat_data.slice(s![.., cat_idx]);
+                timing!(
+                    col.iter()
+                        .map(|s: &Py<PyAny>| match s.extract::<&str>(self.py) {
+                            Ok(s) => Ok(Some(s.to_string())),
+                            // pandas represents missing values by None or NaN
+                            Err(_)
+                                if s.is_none(self.py)
+                                    || s.extract::<f64>(self.py).is_ok_and(f64::is_nan) =>
+                            {
+                                Ok(None)
+                            }
+                            Err(e) => Err(roerr!("column {}, {}", name, e)),
+                        })
+                        .collect::<RoResult<Vec<_>>>(),
+                    "categorical conversion"
+                )
+            })
+            .transpose()
+    }
+    fn levels(&self, name: &str) -> RoResult<Option<Vec<String>>> {
+        Ok(self
+            .levels
+            .as_ref()
+            .and_then(|levels| levels.get(name).cloned()))
+    }
+}
+
 type WilkonsonReturnType<'py> = (Option<Vec<String>>, Bound<'py, PyArray2<f64>>);
 
 #[pyfunction]
+#[pyo3(signature = (ror, data, skip_names, levels=None))]
 fn eval_wilkinson<'py>(
     py: Python<'py>,
     ror: &Wilkinson,
-    numerical_data: PyReadonlyArray2<f64>,
-    numerical_cols: &Bound<'py, PyList>,
-    cat_data: PyReadonlyArray2<Py<PyAny>>,
-    cat_cols: &Bound<'py, PyList>,
+    data: PySeparatedData<'py>,
     skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
 ) -> PyResult<WilkonsonReturnType<'py>> {
-    let numerical_data = numerical_data.as_array();
-    let cat_data = cat_data.as_array();
+    let data = data.with_levels(levels);
+    let ModelMatrix { names, data, .. } =
+        ror.formula.eval(&data, skip_names).map_err(ro_to_pyerr)?;
+    let pya = timing!(data.to_ndarray().map_err(ro_to_pyerr)?, "to ndarray");
+    let res = timing!(pya.into_pyarray(py), "into bound");
+    Ok((names, res))
+}
 
-    let vars = timing!(
-        ror.expr
-            .var_names()
-            .iter()
-            .map(|vn| {
-                if let Some(num_idx) = find_col(numerical_cols, vn) {
-                    let s: ArrayView1<'_, f64> = numerical_data.slice(s![.., num_idx]);
-                    let n_rows = s.dim();
-                    let names = if skip_names {
-                        None
-                    } else {
-                        Some(NameValue::Array(vec![
-                            numerical_cols.get_item(num_idx)?.extract::<String>()?,
-                        ]))
-                    };
-                    timing!(
-                        Ok((
-                            names,
-                            Value::Array(
-                                Array2d::from_vec(s.to_vec(), n_rows, 1).map_err(ro_to_pyerr)?,
-                            ),
-                        )),
-                        "arr from pyarray"
-                    )
-                } else if let Some(cat_idx) = find_col(cat_cols, vn) {
-                    let col: ArrayView1<'_, Py<PyAny>> = cat_data.slice(s![.., cat_idx]);
-                    let col = timing!(
-                        col.iter()
-                            .map(|s: &pyo3::Py<pyo3::PyAny>| Ok(s.extract::<&str>(py)?.to_string()))
-                            .collect::<PyResult<Vec<_>>>()?,
-                        "categorical conversion"
-                    );
-                    let x = Value::Cats(col);
-                    let feature_name = if skip_names {
-                        None
-                    } else {
-                        Some(
-                            NameValue::cats_from_value(
-                                cat_cols.get_item(cat_idx)?.extract::<String>()?,
-                                x.clone(),
-                            )
-                            .unwrap(),
-                        )
-                    };
-                    Ok((feature_name, x))
-                } else {
-                    Err(PyValueError::new_err(format!(
-                        "did not find Variable {vn} in the data"
-                    )))
-                }
-            })
-            .collect::<PyResult<Vec<_>>>()?,
-        "vars"
-    );
-    let (vars_name, mut vars): (Vec<Option<NameValue>>, Vec<Value<DefaultOrder>>) =
-        vars.into_iter().unzip();
-    let vars_name: Vec<NameValue> = vars_name.into_iter().flatten().collect();
+/// Indices of the rows that are used by the evaluation depending on the `na_action` of the
+/// formula, optionally including the columns of the response. `None` if all rows are used.
+#[pyfunction]
+#[pyo3(signature = (ror, data, with_response=false))]
+fn complete_rows_wilkinson<'py>(
+    ror: &Wilkinson,
+    data: PySeparatedData<'py>,
+    with_response: bool,
+) -> PyResult<Option<Vec<usize>>> {
+    let data = data.with_levels(None);
+    ror.formula
+        .complete_rows(&data, with_response)
+        .map_err(ro_to_pyerr)
+}
 
-    if vars.len() != ror.expr.var_names().len() {
-        Err(PyValueError::new_err(
-            "there is a column missing for a variable in the formula",
-        ))
-    } else {
-        let count_vars = vec![1; vars.len()];
-        let n_cols = ror.expr_count.eval(&count_vars).map_err(ex_to_pyerr)?;
+/// Names, `(data, indices, indptr)` of a `scipy.sparse.csc_matrix`, and its shape
+type WilkinsonSparseReturnType<'py> = (
+    Option<Vec<String>>,
+    (
+        Bound<'py, PyArray1<f64>>,
+        Bound<'py, PyArray1<usize>>,
+        Bound<'py, PyArray1<usize>>,
+    ),
+    (usize, usize),
+);
 
-        let var_indices_ordered = ror.expr.var_indices_ordered();
-        // increase capacity of first array
-        for var_idx in var_indices_ordered {
-            if let Value::Array(arr) = &mut vars[var_idx] {
-                arr.set_capacity(n_cols * arr.n_rows() - arr.len());
-                break;
-            }
+fn sparse_to_py<'py>(py: Python<'py>, mm: SparseModelMatrix) -> WilkinsonSparseReturnType<'py> {
+    let shape = (mm.data.n_rows(), mm.data.n_cols());
+    let (indptr, indices, data) = mm.data.into_parts();
+    (
+        mm.names,
+        (
+            data.into_pyarray(py),
+            indices.into_pyarray(py),
+            indptr.into_pyarray(py),
+        ),
+        shape,
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (ror, data, skip_names, levels=None))]
+fn eval_wilkinson_sparse<'py>(
+    py: Python<'py>,
+    ror: &Wilkinson,
+    data: PySeparatedData<'py>,
+    skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<WilkinsonSparseReturnType<'py>> {
+    let data = data.with_levels(levels);
+    let mm = ror
+        .formula
+        .eval_sparse(&data, skip_names)
+        .map_err(ro_to_pyerr)?;
+    Ok(sparse_to_py(py, mm))
+}
+
+#[pyfunction]
+#[pyo3(signature = (ror, data, skip_names, levels=None))]
+fn eval_wilkinson_with_response<'py>(
+    py: Python<'py>,
+    ror: &Wilkinson,
+    data: PySeparatedData<'py>,
+    skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<(WilkonsonReturnType<'py>, WilkonsonReturnType<'py>)> {
+    let data = data.with_levels(levels);
+    let (response, model_matrix) = ror
+        .formula
+        .eval_with_response(&data, skip_names)
+        .map_err(ro_to_pyerr)?;
+    let to_py = |mm: ModelMatrix| -> PyResult<WilkonsonReturnType<'py>> {
+        let pya = mm.data.to_ndarray().map_err(ro_to_pyerr)?;
+        Ok((mm.names, pya.into_pyarray(py)))
+    };
+    Ok((to_py(response)?, to_py(model_matrix)?))
+}
+
+#[pyfunction]
+#[pyo3(signature = (ror, data, levels=None))]
+fn fit_wilkinson<'py>(
+    ror: &Wilkinson,
+    data: PySeparatedData<'py>,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<Spec> {
+    let data = data.with_levels(levels);
+    Ok(Spec {
+        spec: ror.formula.fit(&data).map_err(ro_to_pyerr)?,
+    })
+}
+
+/// Name, number of columns, and for categoricals contrast, levels, and full rank of a factor
+type PyFactorInfo = (
+    String,
+    usize,
+    Option<String>,
+    Option<Vec<String>>,
+    Option<bool>,
+);
+/// Factors and column range of a subterm
+type PySubtermInfo = (Vec<PyFactorInfo>, (usize, usize));
+/// Name, factors, column range, and subterms of a term
+type PyTermInfo = (String, Vec<String>, (usize, usize), Vec<PySubtermInfo>);
+
+fn design_info_to_py(info: DesignInfo) -> (Vec<String>, Vec<PyTermInfo>) {
+    let factor_to_py = |factor: FactorInfo| -> PyFactorInfo {
+        let n_cols = factor.encoding.n_cols();
+        match factor.encoding {
+            FactorEncoding::Numerical { .. } => (factor.name, n_cols, None, None, None),
+            FactorEncoding::Categorical {
+                contrast,
+                levels,
+                full_rank,
+            } => (
+                factor.name,
+                n_cols,
+                Some(contrast.to_string()),
+                Some(levels),
+                Some(full_rank),
+            ),
         }
-        let result_data = ror.expr.eval_vec(vars).map_err(ex_to_pyerr)?;
-        let result_names = if !vars_name.is_empty() {
-            Some(ror.expr_names.eval_vec(vars_name).map_err(ex_to_pyerr)?)
-        } else {
-            None
-        };
+    };
+    let terms = info
+        .terms
+        .into_iter()
+        .map(|term| {
+            let subterms = term
+                .subterms
+                .into_iter()
+                .map(|subterm| {
+                    (
+                        subterm.factors.into_iter().map(factor_to_py).collect(),
+                        (subterm.columns.start, subterm.columns.end),
+                    )
+                })
+                .collect();
+            (
+                term.name,
+                term.factors,
+                (term.columns.start, term.columns.end),
+                subterms,
+            )
+        })
+        .collect();
+    (info.column_names, terms)
+}
 
-        timing!(
-            match result_data {
-                Value::Array(a) => {
-                    let names = if let Some(NameValue::Array(mut names)) = result_names {
-                        names.insert(0, "Intercept".to_string());
-                        Some(names)
-                    } else {
-                        None
-                    };
-                    let intercept = timing!(Array2::ones(Dim([a.n_rows(), 1])), "intercept alloc");
+#[pyfunction]
+#[pyo3(signature = (ror, data, levels=None))]
+fn design_info_wilkinson<'py>(
+    ror: &Wilkinson,
+    data: PySeparatedData<'py>,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<(Vec<String>, Vec<PyTermInfo>)> {
+    let data = data.with_levels(levels);
+    let info = ror.formula.design_info(&data).map_err(ro_to_pyerr)?;
+    Ok(design_info_to_py(info))
+}
 
-                    let pya = timing!(a.to_ndarray().map_err(ro_to_pyerr)?, "to ndarray");
-                    let pya = timing!(concatenate![Axis(1), intercept, pya], "intercept");
-                    let res = timing!(pya.into_pyarray(py), "into bound");
+#[pyfunction]
+#[pyo3(signature = (spec, data, levels=None))]
+fn design_info_spec<'py>(
+    spec: &Spec,
+    data: PySeparatedData<'py>,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<(Vec<String>, Vec<PyTermInfo>)> {
+    let data = data.with_levels(levels);
+    let info = spec.spec.design_info(&data).map_err(ro_to_pyerr)?;
+    Ok(design_info_to_py(info))
+}
 
-                    Ok((names, res))
-                }
-                Value::Cats(_) => Err(PyValueError::new_err("result cannot be cat".to_string())),
-                Value::RowInds(_) => Err(PyValueError::new_err(
-                    "result cannot be row indices".to_string(),
-                )),
-                Value::Scalar(s) => Err(PyValueError::new_err(format!(
-                    "result cannot be skalar but got {s}"
-                ))),
-                Value::Error(e) => Err(PyValueError::new_err(format!("computation failed, {e:?}"))),
-            },
-            "convert res"
-        )
-    }
+#[pyfunction]
+#[pyo3(signature = (spec, data, skip_names, levels=None))]
+fn transform_wilkinson<'py>(
+    py: Python<'py>,
+    spec: &Spec,
+    data: PySeparatedData<'py>,
+    skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<WilkonsonReturnType<'py>> {
+    let data = data.with_levels(levels);
+    let ModelMatrix { names, data, .. } = spec
+        .spec
+        .transform(&data, skip_names)
+        .map_err(ro_to_pyerr)?;
+    let pya = data.to_ndarray().map_err(ro_to_pyerr)?;
+    Ok((names, pya.into_pyarray(py)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (spec, data, skip_names, levels=None))]
+fn transform_wilkinson_sparse<'py>(
+    py: Python<'py>,
+    spec: &Spec,
+    data: PySeparatedData<'py>,
+    skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<WilkinsonSparseReturnType<'py>> {
+    let data = data.with_levels(levels);
+    let mm = spec
+        .spec
+        .transform_sparse(&data, skip_names)
+        .map_err(ro_to_pyerr)?;
+    Ok(sparse_to_py(py, mm))
+}
+
+#[pyfunction]
+#[pyo3(signature = (spec, data, skip_names, levels=None))]
+fn transform_wilkinson_with_response<'py>(
+    py: Python<'py>,
+    spec: &Spec,
+    data: PySeparatedData<'py>,
+    skip_names: bool,
+    levels: Option<HashMap<String, Vec<String>>>,
+) -> PyResult<(WilkonsonReturnType<'py>, WilkonsonReturnType<'py>)> {
+    let data = data.with_levels(levels);
+    let (response, model_matrix) = spec
+        .spec
+        .transform_with_response(&data, skip_names)
+        .map_err(ro_to_pyerr)?;
+    let to_py = |mm: ModelMatrix| -> PyResult<WilkonsonReturnType<'py>> {
+        let pya = mm.data.to_ndarray().map_err(ro_to_pyerr)?;
+        Ok((mm.names, pya.into_pyarray(py)))
+    };
+    Ok((to_py(response)?, to_py(model_matrix)?))
 }
 
 #[pyfunction]
-fn parse_arithmetic(s: &str) -> PyResult<Arithmetic> {
+#[pyo3(signature = (s, tolerance=1e-8, tolerance_mode="relative"))]
+fn parse_arithmetic(s: &str, tolerance: f64, tolerance_mode: &str) -> PyResult<Arithmetic> {
+    let tolerance = Tolerance::from_mode(tolerance_mode, tolerance).map_err(ro_to_pyerr)?;
     Ok(Arithmetic {
-        expr: ExprArithmetic::parse(s).map_err(ex_to_pyerr)?,
+        expr: rormula_rs::expression::parse_arithmetic(s, tolerance).map_err(parse_err_to_pyerr)?,
     })
 }
 #[derive(Debug)]
 #[pyclass]
 struct Arithmetic {
-    expr: ExprArithmetic,
+    expr: rormula_rs::expression::Arithmetic,
 }
 #[pymethods]
 impl Arithmetic {
     pub fn has_row_change_op(&self) -> PyResult<bool> {
-        Ok(has_row_change_op(&self.expr))
+        Ok(self.expr.has_row_change_op())
     }
     pub fn unparse(&self) -> PyResult<String> {
         Ok(self.expr.unparse().to_string())
     }
     pub fn operator_reprs(&self) -> PyResult<Vec<String>> {
-        Ok(self.expr.operator_reprs().to_vec())
+        Ok(self.expr.operator_reprs())
     }
     pub fn unary_reprs(&self) -> PyResult<Vec<String>> {
-        Ok(self.expr.unary_reprs().to_vec())
+        Ok(self.expr.unary_reprs())
     }
     pub fn binary_reprs(&self) -> PyResult<Vec<String>> {
-        Ok(self.expr.binary_reprs().to_vec())
+        Ok(self.expr.binary_reprs())
     }
 }
 
 #[derive(Debug)]
 #[pyclass]
 struct Wilkinson {
-    expr: ExprWilkinson,
-    expr_names: ExprNames,
-    expr_count: ExprColCount,
+    formula: rormula_rs::expression::Wilkinson,
 }
+#[pymethods]
+impl Wilkinson {
+    pub fn has_response(&self) -> PyResult<bool> {
+        Ok(self.formula.has_response())
+    }
+}
+/// Wilkinson formula fitted to training data
+#[derive(Debug)]
+#[pyclass(name = "ModelSpec")]
+struct Spec {
+    spec: ModelSpec,
+}
+
 #[pyfunction]
-fn parse_wilkinson(s: &str) -> PyResult<Wilkinson> {
+#[pyo3(signature = (s, literal=false, unseen_levels="error", na_action="keep", tolerance=1e-8, tolerance_mode="relative", na_level="NA"))]
+fn parse_wilkinson(
+    s: &str,
+    literal: bool,
+    unseen_levels: &str,
+    na_action: &str,
+    tolerance: f64,
+    tolerance_mode: &str,
+    na_level: &str,
+) -> PyResult<Wilkinson> {
+    let unseen_levels = unseen_levels.parse::<UnseenLevels>().map_err(ro_to_pyerr)?;
+    let na_action = na_action.parse::<NaAction>().map_err(ro_to_pyerr)?;
+    let tolerance = Tolerance::from_mode(tolerance_mode, tolerance).map_err(ro_to_pyerr)?;
+    let formula = if literal {
+        rormula_rs::expression::Wilkinson::parse_literal(s)
+    } else {
+        rormula_rs::expression::Wilkinson::parse(s)
+    };
     Ok(timing!(
         Wilkinson {
-            expr: ExprWilkinson::parse(s).map_err(ex_to_pyerr)?,
-            expr_names: ExprNames::parse(s).map_err(ex_to_pyerr)?,
-            expr_count: ExprColCount::parse(s).map_err(ex_to_pyerr)?,
+            formula: formula
+                .map_err(ro_to_pyerr)?
+                .with_unseen_levels(unseen_levels)
+                .with_na_action(na_action)
+                .with_tolerance(tolerance)
+                .with_na_level(na_level),
         },
         "parse"
     ))
@@ -279,9 +505,20 @@ fn parse_wilkinson(s: &str) -> PyResult<Wilkinson> {
 fn rormula(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(parse_wilkinson, m)?)?;
     m.add_function(wrap_pyfunction!(eval_wilkinson, m)?)?;
+    m.add_function(wrap_pyfunction!(complete_rows_wilkinson, m)?)?;
+    m.add_function(wrap_pyfunction!(eval_wilkinson_sparse, m)?)?;
+    m.add_function(wrap_pyfunction!(eval_wilkinson_with_response, m)?)?;
+    m.add_function(wrap_pyfunction!(fit_wilkinson, m)?)?;
+    m.add_function(wrap_pyfunction!(design_info_wilkinson, m)?)?;
+    m.add_function(wrap_pyfunction!(design_info_spec, m)?)?;
+    m.add_function(wrap_pyfunction!(transform_wilkinson, m)?)?;
+    m.add_function(wrap_pyfunction!(transform_wilkinson_sparse, m)?)?;
+    m.add_function(wrap_pyfunction!(transform_wilkinson_with_response, m)?)?;
     m.add_function(wrap_pyfunction!(parse_arithmetic, m)?)?;
     m.add_function(wrap_pyfunction!(eval_arithmetic, m)?)?;
+    m.add_function(wrap_pyfunction!(complete_rows_arithmetic, m)?)?;
     m.add_class::<Wilkinson>()?;
+    m.add_class::<Spec>()?;
     m.add_class::<Arithmetic>()?;
     Ok(())
 }
@@ -2,6 +2,7 @@ from time import perf_counter
 
 import numpy as np
 import pandas as pd
+import pytest
 
 from rormula import Arithmetic
 
@@ -130,6 +131,115 @@ def test_scalar_scalar():
     assert not rormula.has_row_change_op()
 
 
+def test_na_action():
+    df = pd.DataFrame({"alpha": [1.0, np.nan, 3.0], "beta": [1.0, 2.0, 3.0]})
+    res = Arithmetic("alpha + beta", "s").eval(df)
+    assert np.isnan(res[1, 0])
+    rormula = Arithmetic("alpha + beta", "s", na_action="drop")
+    res = rormula.eval_asdf(df)
+    assert rormula.complete_rows(df) == [0, 2]
+    assert Arithmetic("beta", "s", na_action="drop").complete_rows(df) is None
+    assert res.index.to_list() == [0, 2]
+    assert np.allclose(res["s"], [2.0, 6.0])
+    with pytest.raises(ValueError, match="column alpha in row 1"):
+        Arithmetic("alpha + beta", "s", na_action="raise").eval(df)
+
+
+def test_logical():
+    df = pd.DataFrame(
+        {
+            "a": [0.0, 2.0, 3.0, 4.0],
+            "b": [1.0, 1.0, 3.0, 1.0],
+            "c": [3.0, 3.0, 3.0, 1.0],
+            "x": [10.0, 11.0, 12.0, 13.0],
+        }
+    )
+    res = Arithmetic("x|(a>1 && b<2)", "s").eval(df)
+    assert np.allclose(res[:, 0], [11.0, 13.0])
+    res = Arithmetic("x|a<1 || b>2 && c==3", "s").eval(df)
+    assert np.allclose(res[:, 0], [10.0, 12.0])
+    res = Arithmetic("x|!(c==3)", "s").eval(df)
+    assert np.allclose(res[:, 0], [13.0])
+
+
+def test_tolerance():
+    df = pd.DataFrame({"x": [1.0, 1.0 + 1e-10, 1.05, 2.0], "y": [1.0, 2.0, 3.0, 4.0]})
+    res = Arithmetic("y|x!=1", "s").eval(df)
+    assert np.allclose(res[:, 0], [3.0, 4.0])
+    res = Arithmetic("y|x==1", "s", tolerance_mode="exact").eval(df)
+    assert np.allclose(res[:, 0], [1.0])
+    res = Arithmetic("y|x==1", "s", tolerance=0.1, tolerance_mode="absolute").eval(df)
+    assert np.allclose(res[:, 0], [1.0, 2.0, 3.0])
+    with pytest.raises(ValueError, match="tolerance mode"):
+        Arithmetic("y|x==1", "s", tolerance_mode="approximate")
+    with pytest.raises(ValueError, match="non-negative"):
+        Arithmetic("y|x==1", "s", tolerance=-1.0)
+
+
+def test_categorical():
+    df = pd.DataFrame(
+        {
+            "yield": [0.5, 0.7, 0.9, 0.1],
+            "catalyst": ["Pt", "Pd", "Pt", "none"],
+            "batch": ["b 1", "b 2", "b 1", "b 1"],
+        }
+    )
+    rormula = Arithmetic('yield | catalyst == "Pt"', "reduced")
+    res = rormula.eval_asdf(df)
+    assert np.allclose(res["reduced"], [0.5, 0.9])
+    res = Arithmetic("yield|catalyst != 'none' && batch == 'b 1'", "s").eval(df)
+    assert np.allclose(res[:, 0], [0.5, 0.9])
+    res = Arithmetic("yield * 2", "s").eval(df)
+    assert res.shape == (4, 1)
+    with pytest.raises(ValueError, match="did not find Variable"):
+        Arithmetic('yield|solvent == "water"', "s").eval(df)
+
+
+def test_membership():
+    df = pd.DataFrame(
+        {
+            "x": [1.0, 2.0, 3.0, 4.0],
+            "batch": [3.0, 4.0, 5.0, 7.0],
+            "site": ["Ludwigshafen", "Antwerp", "Geismar", "Antwerp"],
+        }
+    )
+    res = Arithmetic('x | site in ("Ludwigshafen", "Antwerp")', "s").eval(df)
+    assert np.allclose(res[:, 0], [1.0, 2.0, 4.0])
+    rormula = Arithmetic("x | batch in (3, 5, 7)", "s")
+    assert np.allclose(rormula.eval(df)[:, 0], [1.0, 3.0, 4.0])
+    assert rormula.unparse() == "x | batch %in% [3, 5, 7]"
+    res = Arithmetic("x|!(site in ('Antwerp')) && batch in (3, 4, 5)", "s").eval(df)
+    assert np.allclose(res[:, 0], [1.0, 3.0])
+    with pytest.raises(TypeError):
+        Arithmetic("x|site in 'Antwerp'", "s")
+
+
+def test_where():
+    df = pd.DataFrame(
+        {
+            "temp": [90.0, 110.0, 100.0, 130.0],
+            "site": ["a", "b", "a", "c"],
+        }
+    )
+    res = Arithmetic("where(temp > 100, temp - 100, 0)", "excess").eval(df)
+    assert res.shape == (4, 1)
+    assert np.allclose(res[:, 0], [0.0, 10.0, 0.0, 30.0])
+    res = Arithmetic("where(site == 'a', temp, where(temp > 120, 1, -1))", "t").eval(df)
+    assert np.allclose(res[:, 0], [90.0, -1.0, 100.0, 1.0])
+    with pytest.raises(ValueError):
+        Arithmetic("where(temp, 1, 0)", "t").eval(df)
+    with pytest.raises(TypeError):
+        Arithmetic("where(temp > 100, 1)", "t")
+    with pytest.raises(TypeError):
+        Arithmetic("temp > 100 ? 1 : 0", "t")
+
+
 if __name__ == "__main__":
     test_arithmetic()
     test_scalar_scalar()
+    test_logical()
+    test_tolerance()
+    test_categorical()
+    test_membership()
+    test_where()
+
@@ -5,6 +5,7 @@ from typing import cast
 import formulaic
 import numpy as np
 import pandas as pd
+import pytest
 
 import rormula as ror
 from rormula import SeparatedData, Wilkinson
@@ -61,13 +62,13 @@ def test_small_numerical():
         axis=1,
     )
     np.allclose(res, ref_)
-    rormula = Wilkinson("a+b+c+a:b+c^2")
+    rormula = Wilkinson("a+b+c+a:b+c^^2")
     res = rormula.eval_asdf(data)
     ref = pd.concat(
         [
             ref,
             pd.Series(data=[0, 12], name="a:b"),
-            pd.Series(data=[9, 36], name="c^2"),
+            pd.Series(data=[9, 36], name="c^^2"),
         ],
         axis=1,
     )
@@ -177,6 +178,365 @@ def test_more_formulas():
     test(formula_str, extract_reference, extract_result)
 
 
+def test_cross():
+    cols = ["a", "b", "c", "d"]
+    data = pd.DataFrame(data=np.random.random((10, len(cols))), columns=cols)
+    names, mm = Wilkinson("(a+b+c)*d").eval(data)
+    assert names == ["Intercept", "a", "b", "c", "d", "a:d", "b:d", "c:d"]
+    assert mm.shape == (10, 8)
+    assert np.allclose(mm[:, 5], data["a"] * data["d"])
+    names_expanded, mm_expanded = Wilkinson("a+b+c+d+a:d+b:d+c:d").eval(data)
+    assert names == names_expanded
+    assert np.allclose(mm, mm_expanded)
+
+
+def test_term_removal_and_intercept():
+    cols = ["a", "b"]
+    data = pd.DataFrame(data=np.random.random((10, len(cols))), columns=cols)
+    names, mm = Wilkinson("a + b - 1").eval(data)
+    assert names == ["a", "b"]
+    assert np.allclose(mm, data.to_numpy())
+    names, _ = Wilkinson("0 + a + b").eval(data)
+    assert names == ["a", "b"]
+    names, mm = Wilkinson("a*b - a:b").eval(data)
+    assert names == ["Intercept", "a", "b"]
+    assert mm.shape == (10, 3)
+    names, mm = Wilkinson("a*b - a:b - 1 + 1").eval(data)
+    assert names == ["Intercept", "a", "b"]
+    names, mm = Wilkinson("1").eval(data)
+    assert names == ["Intercept"]
+    assert np.allclose(mm, 1.0)
+
+
+def test_interaction_power():
+    cols = ["a", "b", "c"]
+    data = pd.DataFrame(data=np.random.random((10, len(cols))), columns=cols)
+    names, mm = Wilkinson("(a+b+c)^2").eval(data)
+    assert names == ["Intercept", "a", "b", "c", "a:b", "a:c", "b:c"]
+    assert np.allclose(mm[:, 6], data["b"] * data["c"])
+    names, mm = Wilkinson("a + a^^2").eval(data)
+    assert names == ["Intercept", "a", "a^^2"]
+    assert np.allclose(mm[:, 2], data["a"] ** 2)
+
+
+def test_identity():
+    data = pd.DataFrame(
+        {"temp": np.random.random(10), "pressure": np.random.random(10) + 1}
+    )
+    names, mm = Wilkinson("temp + I(temp^2) + I(log(pressure)/2)").eval(data)
+    assert names == ["Intercept", "temp", "temp^2", "log(pressure)/2"]
+    assert np.allclose(mm[:, 2], data.temp**2)
+    assert np.allclose(mm[:, 3], np.log(data.pressure) / 2)
+    data = pd.DataFrame({"temp": [1.0, 2.0, 3.0]})
+    formula = "I(where(temp >= 2.1, 1, 0)) - 1"
+    _, mm = Wilkinson(formula).eval(data)
+    assert np.allclose(mm[:, 0], [0.0, 0.0, 1.0])
+    _, mm = Wilkinson(formula, tolerance=0.2, tolerance_mode="absolute").eval(data)
+    assert np.allclose(mm[:, 0], [0.0, 1.0, 1.0])
+
+
+def test_poly():
+    training = pd.DataFrame({"temp": np.arange(1.0, 6.0)})
+    rormula = Wilkinson("poly(temp, 2)")
+    names, mm = rormula.eval(training)
+    assert names == ["Intercept", "poly(temp,2)[1]", "poly(temp,2)[2]"]
+    assert np.allclose(mm.T @ mm, np.diag([5, 1, 1]))
+    assert np.allclose(mm[:, 2], np.array([2, -1, -2, -1, 2]) / np.sqrt(14))
+    _, mm_batch = rormula.fit(training).transform(pd.DataFrame({"temp": [2.0]}))
+    assert np.allclose(mm_batch, mm[1:2])
+
+
+def test_splines():
+    training = pd.DataFrame({"temp": np.linspace(0.0, 1.0, 11)})
+    rormula = Wilkinson("bs(temp, df=3) + cr(temp, df=4) - 1")
+    names, mm = rormula.eval(training)
+    assert names == [
+        "bs(temp,df=3)[1]",
+        "bs(temp,df=3)[2]",
+        "bs(temp,df=3)[3]",
+        "cr(temp,df=4)[1]",
+        "cr(temp,df=4)[2]",
+        "cr(temp,df=4)[3]",
+        "cr(temp,df=4)[4]",
+    ]
+    t = training.temp.to_numpy()
+    assert np.allclose(mm[:, 2], t**3)
+    assert np.allclose(mm[:, 3:].sum(axis=1), 1)
+    _, mm_batch = rormula.fit(training).transform(pd.DataFrame({"temp": [0.3]}))
+    assert np.allclose(mm_batch, mm[3:4])
+
+
+def test_coded_units():
+    training = pd.DataFrame({"temp": [100.0, 150.0, 200.0]})
+    rormula = Wilkinson("coded(temp) + standardize(temp) + center(temp)")
+    names, mm = rormula.eval(training)
+    assert names == ["Intercept", "coded(temp)", "standardize(temp)", "center(temp)"]
+    assert np.allclose(mm[:, 1], [-1, 0, 1])
+    assert np.allclose(mm[:, 2], [-1, 0, 1])
+    assert np.allclose(mm[:, 3], [-50, 0, 50])
+    _, mm_batch = rormula.fit(training).transform(pd.DataFrame({"temp": [175.0]}))
+    assert np.allclose(mm_batch, [[1, 0.5, 0.5, 25]])
+    names, mm = Wilkinson("coded(temp, 0, 400) - 1").eval(training)
+    assert names == ["coded(temp,0,400)"]
+    assert np.allclose(mm[:, 0], [-0.5, -0.25, 0])
+    # like in R, scale centers as well
+    _, mm = Wilkinson("scale(temp) - 1").eval(training)
+    assert np.allclose(mm[:, 0], [-1, 0, 1])
+
+
+def test_sparse():
+    data = pd.DataFrame(
+        {
+            "x": ["a", "b", "c", "a"],
+            "z": ["p", "q", "q", "p"],
+            "t": [0.5, 0.0, 2.0, 3.0],
+        }
+    )
+    rormula = Wilkinson("x*z + t")
+    names, mm = rormula.eval(data)
+    names_sparse, mm_sparse = rormula.eval_sparse(data)
+    assert names_sparse == names
+    assert mm_sparse.format == "csc"
+    assert np.allclose(mm_sparse.toarray(), mm)
+    rormula.fit(data)
+    _, mm_sparse = rormula.transform_sparse(data.iloc[1:3])
+    assert np.allclose(mm_sparse.toarray(), mm[1:3])
+
+
+def test_design_info():
+    data = pd.DataFrame(
+        {"x": ["a", "b", "c", "a"], "t": [0.5, 1.0, 2.0, 3.0]},
+    )
+    rormula = Wilkinson("poly(t, 2) + C(x, Sum):t")
+    names, mm = rormula.eval(data)
+    info = rormula.design_info(data)
+    assert info.column_names == names
+    assert [t.name for t in info.terms] == ["Intercept", "poly(t, 2)", "C(x, Sum):t"]
+    assert info.term_slices()["poly(t, 2)"] == slice(1, 3)
+    term = info.term("C(x, Sum):t")
+    assert mm[:, term.columns].shape == (4, 3)
+    x_info, t_info = term.subterms[0].factors
+    assert x_info.contrast == "Sum"
+    assert x_info.levels == ["a", "b", "c"]
+    assert x_info.full_rank
+    assert not t_info.is_categorical
+    rormula.fit(data)
+    info = rormula.design_info(data.iloc[:1])
+    assert info.column_names == names
+
+
+def test_contrast():
+    data = pd.DataFrame(
+        {"x": ["ctrl", "t1", "t2", "t1"], "a": np.arange(4, dtype=np.float64)}
+    )
+    names, mm = Wilkinson('C(x, Treatment(reference="ctrl"))').eval(data)
+    assert names == ["Intercept", "x_t1", "x_t2"]
+    assert np.allclose(mm[:, 1:], [[0, 0], [1, 0], [0, 1], [1, 0]])
+    names, mm = Wilkinson("C(x, Sum) - 1").eval(data)
+    assert names == ["x_S.ctrl", "x_S.t1"]
+    assert np.allclose(mm, [[1, 0], [0, 1], [-1, -1], [0, 1]])
+    names, _ = Wilkinson("C(x, Helmert) + C(x, Diff) + C(x, Poly)").eval(data)
+    assert names == [
+        "Intercept",
+        "x_H.t1",
+        "x_H.t2",
+        "x_D.t1",
+        "x_D.t2",
+        "x_.L",
+        "x_.Q",
+    ]
+
+
+def test_full_rank():
+    data = pd.DataFrame(
+        {"x": ["a", "b", "c", "a"], "z": ["u", "v", "u", "v"], "t": np.ones(4)}
+    )
+    names, mm = Wilkinson("0 + x").eval(data)
+    assert names == ["x_a", "x_b", "x_c"]
+    assert np.allclose(mm.sum(axis=1), 1)
+    names, _ = Wilkinson("x").eval(data)
+    assert names == ["Intercept", "x_a", "x_b"]
+    names, mm = Wilkinson("t:x").eval(data)
+    assert names == ["Intercept", "t:x_a", "t:x_b", "t:x_c"]
+    names, mm = Wilkinson("x:z").eval(data)
+    assert names == [
+        "Intercept",
+        "z_u",
+        "x_a:z_u",
+        "x_b:z_u",
+        "x_a:z_v",
+        "x_b:z_v",
+    ]
+    assert np.linalg.matrix_rank(mm) == 4
+
+
+def test_deduplication():
+    data = pd.DataFrame(data=np.random.random((5, 2)), columns=["n", "o"])
+    names, mm = Wilkinson("n + o + n + o:n + n:o").eval(data)
+    assert names == ["Intercept", "n", "o", "o:n"]
+    assert mm.shape == (5, 4)
+    names, mm = Wilkinson("n + o + n", literal=True).eval(data)
+    assert names == ["Intercept", "n", "o", "n"]
+    assert np.allclose(mm[:, 1], mm[:, 3])
+
+
+def test_fit_transform():
+    training = pd.DataFrame(
+        {"y": np.arange(4.0), "t": np.arange(4.0), "x": ["a", "b", "c", "a"]}
+    )
+    rormula = Wilkinson("y ~ t + x").fit(training)
+    names, mm = rormula.transform(training)
+    ref_names, ref_mm = rormula.eval(training)
+    assert names == ref_names
+    assert np.allclose(mm, ref_mm)
+    batch = pd.DataFrame({"t": [1.0], "x": ["b"]})
+    names, mm = rormula.transform(batch)
+    assert names == ["Intercept", "t", "x_a", "x_b"]
+    assert np.allclose(mm, [[1, 1, 0, 1]])
+    (response_names, _), _ = rormula.transform_with_response(training)
+    assert response_names == ["y"]
+    with pytest.raises(ValueError):
+        rormula.transform(pd.DataFrame({"t": [1.0], "x": ["d"]}))
+
+
+def test_separate_num_cat():
+    data = pd.DataFrame({"t": [1.0, 2.0], "x": ["a", "b"], "u": [3.0, 4.0]})
+    separated = ror.separate_num_cat(data)
+    assert separated.numerical_cols == ["t", "u"]
+    assert separated.categorical_cols == ["x"]
+    assert separated.categorical_data.tolist() == [["a"], ["b"]]
+    names, mm = Wilkinson("t + x").eval(data)
+    assert names == ["Intercept", "t", "x_a"]
+    assert np.allclose(mm, [[1, 1, 1], [1, 2, 0]])
+
+
+def test_declared_levels():
+    levels = ["ctrl", "b", "a"]
+    single_run = pd.DataFrame(
+        {"t": [1.0], "x": pd.Categorical(["b"], categories=levels)}
+    )
+    names, mm = Wilkinson("t + x").eval(single_run)
+    assert names == ["Intercept", "t", "x_ctrl", "x_b"]
+    assert np.allclose(mm, [[1, 1, 0, 1]])
+    separated = ror.separate_num_cat(pd.DataFrame({"t": [1.0], "x": ["a"]}))
+    numerical_cols, numerical_data, categorical_cols, categorical_data = separated
+    assert categorical_cols == ["x"]
+    names, mm = Wilkinson("t + x").eval(separated, levels={"x": levels})
+    assert names == ["Intercept", "t", "x_ctrl", "x_b"]
+    assert np.allclose(mm, [[1, 1, 0, 0]])
+    # explicit levels take precedence over categories
+    names, _ = Wilkinson("t + x").eval(single_run, levels={"x": ["b", "ctrl", "a"]})
+    assert names == ["Intercept", "t", "x_b", "x_ctrl"]
+
+
+def test_unseen_levels():
+    training = pd.DataFrame({"x": ["a", "b", "c"]})
+    batch = pd.DataFrame({"x": ["b", "d"]})
+    with pytest.raises(ValueError):
+        Wilkinson("x").fit(training).transform(batch)
+    # zeros are the reference level of the reduced-rank treatment coding
+    with pytest.raises(ValueError):
+        Wilkinson("x", unseen_levels="zeros").fit(training).transform(batch)
+    rormula = Wilkinson("x - 1", unseen_levels="zeros")
+    names, mm = rormula.fit(training).transform(batch)
+    assert names == ["x_a", "x_b", "x_c"]
+    assert np.allclose(mm, [[0, 1, 0], [0, 0, 0]])
+    rormula = Wilkinson("C(x, Sum)", unseen_levels="zeros")
+    names, mm = rormula.fit(training).transform(batch)
+    assert names == ["Intercept", "x_S.a", "x_S.b"]
+    assert np.allclose(mm, [[1, 0, 1], [1, 0, 0]])
+    names, mm = Wilkinson("x", unseen_levels="other").fit(training).transform(batch)
+    assert names == ["Intercept", "x_a", "x_b", "x_other"]
+    assert np.allclose(mm, [[1, 0, 1, 0], [1, 0, 0, 1]])
+    with pytest.raises(ValueError):
+        Wilkinson("x", unseen_levels="ignore")
+
+
+def test_na_action():
+    data = pd.DataFrame(
+        {
+            "y": [1.0, 2.0, np.nan, 4.0],
+            "t": [0.1, np.nan, 0.3, 0.4],
+            "x": ["a", "b", "a", None],
+        }
+    )
+    names, mm = Wilkinson("t + x").eval(data)
+    assert names == ["Intercept", "t", "x_NA", "x_a"]
+    assert np.isnan(mm[1, 1])
+    rormula = Wilkinson("y ~ t + x", na_action="drop")
+    mm = rormula.eval_asdf(data)
+    assert rormula.complete_rows(data) == [0, 2]
+    assert mm.index.to_list() == [0, 2]
+    (_, response), (_, mm) = rormula.eval_with_response(data)
+    assert rormula.complete_rows(data, with_response=True) == [0]
+    assert response.shape == (1, 1)
+    assert mm.shape[0] == 1
+    with pytest.raises(ValueError, match="column t in row 1"):
+        Wilkinson("t + x", na_action="raise").eval(data)
+    # the string NA is a level like any other
+    regions = pd.DataFrame({"region": ["EU", "NA", "APAC"]})
+    names, mm = Wilkinson("region", na_action="drop").eval(regions)
+    assert names == ["Intercept", "region_APAC", "region_EU"]
+    assert mm.shape[0] == 3
+    regions.loc[3, "region"] = None
+    with pytest.raises(ValueError, match="collide with the level NA"):
+        Wilkinson("region").eval(regions)
+    names, mm = Wilkinson("region - 1", na_level="missing").eval(regions)
+    assert names == ["region_APAC", "region_EU", "region_NA", "region_missing"]
+    assert np.allclose(mm[:, 3], [0, 0, 0, 1])
+
+
+def test_nesting():
+    data = pd.DataFrame(
+        {
+            "supplier": ["s1", "s1", "s2", "s2"] * 2,
+            "batch": ["b1", "b2", "b1", "b2"] * 2,
+            "x": np.random.random(8),
+        }
+    )
+    ref_names = [
+        "Intercept",
+        "x",
+        "supplier_s1",
+        "supplier_s1:batch_b1",
+        "supplier_s2:batch_b1",
+    ]
+    names, mm = Wilkinson("x + supplier/batch").eval(data)
+    assert names == ref_names
+    names_in, mm_in = Wilkinson("x + supplier + batch %in% supplier").eval(data)
+    assert names_in == ref_names
+    assert np.allclose(mm, mm_in)
+
+
+def test_response():
+    cols = ["y1", "y2", "temp", "pressure"]
+    data = pd.DataFrame(data=np.random.random((10, len(cols))), columns=cols)
+    rormula = Wilkinson("y1 ~ temp + pressure + temp:pressure")
+    assert rormula.has_response()
+    (response_names, response), (names, mm) = rormula.eval_with_response(data)
+    assert response_names == ["y1"]
+    assert np.allclose(response[:, 0], data["y1"])
+    assert names == ["Intercept", "temp", "pressure", "temp:pressure"]
+    assert mm.shape == (10, 4)
+    names_rhs, mm_rhs = rormula.eval(data.drop(columns=["y1"]))
+    assert names == names_rhs
+    assert np.allclose(mm, mm_rhs)
+
+    rormula = Wilkinson("y1 + y2 ~ temp - 1")
+    (response_names, response), (names, mm) = rormula.eval_with_response(data)
+    assert response_names == ["y1", "y2"]
+    assert np.allclose(response, data[["y1", "y2"]])
+    assert names == ["temp"]
+
+    rormula = Wilkinson("temp + pressure")
+    assert not rormula.has_response()
+    try:
+        rormula.eval_with_response(data)
+        assert False
+    except ValueError:
+        pass
+
+
 def test_separated():
     data = pd.DataFrame(
         data=np.random.random((100, 3)),